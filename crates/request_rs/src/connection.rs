@@ -1,54 +1,484 @@
 use std::{
-    io::{BufReader, Write},
+    io::{self, BufRead, BufReader, Read, Write},
     net::TcpStream,
+    slice,
     time::Duration,
 };
 
 use crate::{
-    error::WgetResult,
-    http::{HttpMethod, HttpRequest, HttpResponse, HttpVersion},
+    error::{WgetError, WgetResult},
+    http::{
+        Conditions, HttpMethod, HttpRequest, HttpResponse, HttpStatusCode, HttpStatusFamily,
+        HttpVersion,
+    },
+    http2::Session,
+    protocol::Protocol,
+    rate_limit::ThrottledReader,
     Configuration,
 };
 
+/// How long to wait for the server to send anything before giving up
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Debug)]
 pub struct Connection {
     domain: String,
     port: u16,
     socket: TcpStream,
+    /// Reads from `socket`, keeping anything that arrived after one response
+    /// for whatever comes next
+    reader: BufReader<ThrottledReader<TcpStream>>,
+    requests_sent: usize,
+    /// The HTTP/2 state, if the connection speaks it rather than HTTP/1.x
+    http2: Option<Session>,
+    /// The version of HTTP/1 requests
+    version: HttpVersion,
+    /// Whether requests ask the server to keep the connection open
+    keep_alive: bool,
+    /// Headers to add to or remove from the defaults of every request
+    headers: Vec<(String, String)>,
+    /// Set when the server won't take another request on this connection:
+    /// it said it is closing it, or answered a request before its body was
+    /// sent and may take the next request for the body
+    broken: bool,
+    /// Whether `send_all` writes HTTP/1.1 requests without waiting for each
+    /// response. Turned off if the server closes the connection in the
+    /// middle of a pipeline.
+    pipelining: bool,
+    /// Whether the server has started a response on this socket, after
+    /// which it can't be an HTTP/0.9 server
+    responded: bool,
+    /// The scheme of the URLs requested on this connection
+    scheme: Protocol,
 }
 
 impl Connection {
     pub fn new(domain: String, port: u16, config: &Configuration) -> WgetResult<Self> {
-        if config.debug > 1 {
-            println!("Connecting to {domain} port {port}");
-        }
+        crate::trace!(config, "Connecting to {domain} port {port}");
         let socket = TcpStream::connect((&domain[..], port))?;
-        socket.set_read_timeout(Some(Duration::from_secs(30)))?;
+        socket.set_read_timeout(Some(READ_TIMEOUT))?;
+        let mut reader = BufReader::new(ThrottledReader::new(
+            socket.try_clone()?,
+            config.rate_limiter.clone(),
+        ));
+        let http2 = if config.http2_prior_knowledge {
+            crate::debug!(config, "Starting HTTP/2 with prior knowledge");
+            Some(Session::handshake(&mut reader, &mut &socket, config)?)
+        } else {
+            None
+        };
         Ok(Self {
             domain,
             port,
             socket,
+            reader,
+            requests_sent: 0,
+            http2,
+            version: match config.http_version {
+                HttpVersion::Version1_0 => HttpVersion::Version1_0,
+                _ => HttpVersion::Version1_1,
+            },
+            keep_alive: !config.no_keep_alive,
+            headers: config.headers.clone(),
+            broken: false,
+            pipelining: false,
+            responded: false,
+            scheme: Protocol::Http,
         })
     }
 
-    pub fn send_request(&mut self, path: &str, config: &Configuration) -> WgetResult<HttpResponse> {
-        let mut request = HttpRequest::new(HttpMethod::Get, path, HttpVersion::Version1_1);
+    /// Makes `send_all` pipeline HTTP/1.1 requests, sending them one after the
+    /// other without waiting for the responses in between. This saves a round
+    /// trip per request, but not every server handles it, so it is opt-in.
+    pub fn set_pipelining(&mut self, pipelining: bool) {
+        self.pipelining = pipelining;
+    }
+
+    /// Sets the scheme of the URLs requested on this connection, which
+    /// HTTP/2 sends with every request
+    pub fn set_scheme(&mut self, scheme: Protocol) {
+        self.scheme = scheme;
+    }
+
+    /// Creates a request for `path` on this connection's host, with the default
+    /// headers set as changed by `Configuration::headers`
+    pub fn new_request(&self, method: HttpMethod, path: &str) -> HttpRequest {
+        let version = if self.http2.is_some() {
+            HttpVersion::Version2_0
+        } else {
+            self.version
+        };
+        let mut request = HttpRequest::new(method, path, version);
+        request.set_scheme(self.scheme);
         request.add_header("Host", &self.domain);
         request.add_header("User-Agent", "Wget/1.21.3");
         request.add_header("Accept", "*/*");
         request.add_header("Accept-Encoding", "identity");
-        request.add_header("Connection", "Keep-Alive");
+        if self.http2.is_none() {
+            let connection = if self.keep_alive {
+                "Keep-Alive"
+            } else {
+                "close"
+            };
+            request.add_header("Connection", connection);
+        }
+        for (name, value) in &self.headers {
+            if value.is_empty() {
+                request.delete_header(name);
+            } else {
+                request.add_header(name, value);
+            }
+        }
+        request
+    }
+
+    pub fn send_request(&mut self, path: &str, config: &Configuration) -> WgetResult<HttpResponse> {
+        self.send_conditional_request(path, &Conditions::default(), config)
+    }
+
+    /// Sends a `GET` for `path` that only fetches the body if `conditions`
+    /// no longer hold, otherwise the response is a `304 Not Modified`
+    pub fn send_conditional_request(
+        &mut self,
+        path: &str,
+        conditions: &Conditions,
+        config: &Configuration,
+    ) -> WgetResult<HttpResponse> {
+        let mut request = self.new_request(HttpMethod::Get, path);
+        request.set_conditions(conditions);
+        self.send(&request, config)
+    }
+
+    /// Sends `request` and waits for the response
+    ///
+    /// The body is not read for `HEAD` requests or `304 Not Modified` responses,
+    /// since the server never sends one.
+    pub fn send(
+        &mut self,
+        request: &HttpRequest,
+        config: &Configuration,
+    ) -> WgetResult<HttpResponse> {
+        self.send_with_progress(request, config, &mut |_, _| {})
+    }
+
+    /// Sends `request` like `send`, calling `progress` as the response body is
+    /// received. See `HttpResponse::receive_body_with_progress`.
+    pub fn send_with_progress(
+        &mut self,
+        request: &HttpRequest,
+        config: &Configuration,
+        progress: &mut dyn FnMut(u64, Option<u64>),
+    ) -> WgetResult<HttpResponse> {
+        self.send_with_interim(request, config, progress, &mut |_| {})
+    }
+
+    /// Sends `request` like `send_with_progress`, also passing any interim
+    /// `1xx` responses to `interim`. See `HttpResponse::receive_final_head`.
+    ///
+    /// A `101 Switching Protocols` response is returned without reading a
+    /// body, after which `into_upgraded` gives the connection to talk the new
+    /// protocol on.
+    pub fn send_with_interim(
+        &mut self,
+        request: &HttpRequest,
+        config: &Configuration,
+        progress: &mut dyn FnMut(u64, Option<u64>),
+        interim: &mut dyn FnMut(&HttpResponse),
+    ) -> WgetResult<HttpResponse> {
+        crate::debug!(
+            config,
+            "------ request start ------\n{request}\n------ request end -----"
+        );
+        self.requests_sent += 1;
+        if let Some(session) = &mut self.http2 {
+            let mut responses = session.exchange(
+                &mut self.reader,
+                &mut &self.socket,
+                slice::from_ref(request),
+                config,
+                &mut |_, received, total| progress(received, total),
+            )?;
+            let response = responses.remove(0);
+            if let Ok(response) = &response {
+                log_head(response, config);
+            }
+            return response;
+        }
+        let interim = &mut |response: &HttpResponse| {
+            log_head(response, config);
+            interim(response);
+        };
+        let response = match request.continue_wait() {
+            Some(wait) => self.send_expecting_continue(request, wait, config, interim)?,
+            None => {
+                self.socket.write_all(&request.serialize())?;
+                self.receive_head(config, interim)?
+            }
+        };
+        self.receive_body(request, response, config, progress)
+    }
+
+    /// Reads the body of `response` to `request`, unless it has none
+    fn receive_body(
+        &mut self,
+        request: &HttpRequest,
+        mut response: HttpResponse,
+        config: &Configuration,
+        progress: &mut dyn FnMut(u64, Option<u64>),
+    ) -> WgetResult<HttpResponse> {
+        log_head(&response, config);
+        if request.method() != HttpMethod::Head
+            && response.status_code != HttpStatusCode::NotModified
+            && response.status_code != HttpStatusCode::SwitchingProtocols
+        {
+            response.receive_body_with_progress(&mut self.reader, config, progress)?;
+            if response.is_close_delimited() {
+                self.broken = true;
+            }
+        }
+        // After a switch the server no longer talks HTTP on this connection,
+        // so it can only be taken over with `into_upgraded`
+        if response.closes_connection()
+            || !self.keep_alive
+            || response.status_code == HttpStatusCode::SwitchingProtocols
+        {
+            self.broken = true;
+        }
+        Ok(response)
+    }
+
+    /// Sends the head of `request`, then its body once the server answers
+    /// `100 Continue` or hasn't answered within `wait`, and reads the head of
+    /// the final response. The body is never sent if the server answers with
+    /// a final response straight away, like `401 Unauthorized`.
+    fn send_expecting_continue(
+        &mut self,
+        request: &HttpRequest,
+        wait: Duration,
+        config: &Configuration,
+        interim: &mut dyn FnMut(&HttpResponse),
+    ) -> WgetResult<HttpResponse> {
+        self.socket.write_all(request.to_string().as_bytes())?;
+        loop {
+            // Only the start of an answer is waited for briefly. Once the
+            // server is answering, a slow head mustn't be cut off halfway.
+            self.socket.set_read_timeout(Some(wait))?;
+            let answered = self.reader.fill_buf().map(|_| ());
+            self.socket.set_read_timeout(Some(READ_TIMEOUT))?;
+            match answered {
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    crate::debug!(
+                        config,
+                        "No 100 Continue within {wait:?}, sending the body anyway"
+                    );
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+                Ok(()) => {}
+            }
+            // A server that answers `Expect` speaks HTTP/1.1
+            self.responded = true;
+            match HttpResponse::receive_response_head(&mut self.reader, config)? {
+                response if response.status_code == HttpStatusCode::Continue => {
+                    interim(&response);
+                    break;
+                }
+                response
+                    if response.status_family() != HttpStatusFamily::Informational
+                        || response.status_code == HttpStatusCode::SwitchingProtocols =>
+                {
+                    self.broken = true;
+                    return Ok(response);
+                }
+                response => interim(&response),
+            }
+        }
+        self.socket.write_all(request.body())?;
+        self.receive_head(config, interim)
+    }
 
-        if config.debug > 0 {
-            println!(
+    /// Reads the head of the next final response, which may only be HTTP/0.9
+    /// if it is the first on this socket
+    fn receive_head(
+        &mut self,
+        config: &Configuration,
+        interim: &mut dyn FnMut(&HttpResponse),
+    ) -> WgetResult<HttpResponse> {
+        if std::mem::replace(&mut self.responded, true) {
+            HttpResponse::receive_final_head(&mut self.reader, config, interim)
+        } else {
+            HttpResponse::receive_first_head(&mut self.reader, config, interim)
+        }
+    }
+
+    /// Sends all of `requests` and waits for their responses, which are in
+    /// the same order. Over HTTP/2 they are all sent at once on separate
+    /// streams, and an error for one request doesn't stop the others. Over
+    /// HTTP/1.1 they are sent one at a time, unless pipelining is on.
+    pub fn send_all(
+        &mut self,
+        requests: &[HttpRequest],
+        config: &Configuration,
+    ) -> WgetResult<Vec<WgetResult<HttpResponse>>> {
+        let Some(session) = &mut self.http2 else {
+            let mut responses = Vec::with_capacity(requests.len());
+            while responses.len() < requests.len() {
+                let rest = &requests[responses.len()..];
+                if self.broken {
+                    if let Err(e) = self.reconnect(config) {
+                        responses.push(Err(e));
+                        continue;
+                    }
+                }
+                // Only requests that can safely be sent again are pipelined
+                let batch = rest
+                    .iter()
+                    .take_while(|request| {
+                        request.method().is_idempotent() && request.continue_wait().is_none()
+                    })
+                    .count();
+                if self.pipelining && self.keep_alive && batch > 1 {
+                    self.send_pipelined(&rest[..batch], &mut responses, config);
+                } else {
+                    responses.push(self.send(&rest[0], config));
+                }
+            }
+            return Ok(responses);
+        };
+        for request in requests {
+            crate::debug!(
+                config,
+                "------ request start ------\n{request}\n------ request end -----"
+            );
+        }
+        self.requests_sent += requests.len();
+        let responses = session.exchange(
+            &mut self.reader,
+            &mut &self.socket,
+            requests,
+            config,
+            &mut |_, _, _| {},
+        )?;
+        for response in responses.iter().flatten() {
+            log_head(response, config);
+        }
+        Ok(responses)
+    }
+
+    /// Writes all of `requests` at once, then reads their responses in order
+    /// into `responses`. If the server closes the connection before
+    /// answering them all, the rest are sent again one at a time on a new
+    /// connection, and pipelining is turned off. Any other error, like the
+    /// server taking too long, is the result for the request it happened on.
+    fn send_pipelined(
+        &mut self,
+        requests: &[HttpRequest],
+        responses: &mut Vec<WgetResult<HttpResponse>>,
+        config: &Configuration,
+    ) {
+        let mut data = Vec::new();
+        for request in requests {
+            crate::debug!(
+                config,
                 "------ request start ------\n{request}\n------ request end -----"
             );
+            data.extend(request.serialize());
         }
-        self.socket.write_all(&request.serialize())?;
+        self.requests_sent += requests.len();
+        let mut answered = 0;
+        if let Err(e) = self.socket.write_all(&data) {
+            crate::debug!(config, "Could not send pipelined requests: {e}");
+            if !is_closed(&e) {
+                responses.push(Err(e.into()));
+                answered += 1;
+            }
+        } else {
+            for request in requests {
+                let interim = &mut |response: &HttpResponse| log_head(response, config);
+                let response = self.receive_head(config, interim).and_then(|response| {
+                    self.receive_body(request, response, config, &mut |_, _| {})
+                });
+                match response {
+                    // The server may have stopped anywhere in the response,
+                    // so it is asked again. A server that is just slow would
+                    // only be slowed down further.
+                    Err(WgetError::Io(e)) if is_closed(&e) => {
+                        crate::debug!(config, "Pipelined response failed: {e}");
+                        break;
+                    }
+                    response => {
+                        // Anything else wrong leaves the rest of the responses
+                        // out of step with the requests
+                        let failed = response.is_err();
+                        responses.push(response);
+                        answered += 1;
+                        if failed {
+                            self.broken = true;
+                        }
+                        if self.broken {
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        if answered < requests.len() {
+            crate::debug!(
+                config,
+                "Server answered {answered} of {} pipelined requests, sending the rest one at a time",
+                requests.len()
+            );
+            self.broken = true;
+            self.pipelining = false;
+        }
+    }
 
-        let mut reader = BufReader::new(&mut self.socket);
+    /// Replaces the connection with a new one to the same server, after the
+    /// server closed it
+    fn reconnect(&mut self, config: &Configuration) -> WgetResult<()> {
+        crate::trace!(
+            config,
+            "Server closed the connection, reconnecting to {} port {}",
+            self.domain,
+            self.port
+        );
+        let connection = Connection::new(self.domain.clone(), self.port, config)?;
+        *self = Connection {
+            requests_sent: self.requests_sent,
+            pipelining: self.pipelining,
+            scheme: self.scheme,
+            ..connection
+        };
+        Ok(())
+    }
 
-        HttpResponse::receive_response(&mut reader, config)
+    /// Whether the connection speaks HTTP/2
+    pub fn is_http2(&self) -> bool {
+        self.http2.is_some()
+    }
+
+    /// Whether more requests can be sent. An HTTP/2 server may say it is
+    /// closing the connection while still answering earlier requests.
+    pub fn is_reusable(&self) -> bool {
+        !self.broken
+            && !self
+                .http2
+                .as_ref()
+                .is_some_and(|session| session.is_closing())
+    }
+
+    /// The connection for talking whatever protocol the server switched to
+    /// with a `101 Switching Protocols` response
+    pub fn into_upgraded(self) -> Upgraded {
+        Upgraded {
+            reader: self.reader,
+            socket: self.socket,
+        }
     }
 
     pub fn get_domain(&self) -> &str {
@@ -59,6 +489,13 @@ impl Connection {
         self.port
     }
 
+    /// How many requests have been sent on this connection. A failure on a
+    /// connection that has already been used usually just means the server
+    /// closed it while it sat idle.
+    pub fn requests_sent(&self) -> usize {
+        self.requests_sent
+    }
+
     pub fn get_socket(&self) -> &TcpStream {
         &self.socket
     }
@@ -68,9 +505,66 @@ impl Connection {
     }
 }
 
+/// Shows the status line and headers of `response`, indented like wget's
+/// `--server-response`, if the configuration asks for them
+pub(crate) fn log_head(response: &HttpResponse, config: &Configuration) {
+    if config.server_response {
+        let head = response.head();
+        let lines: Vec<String> = head
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| format!("  {line}"))
+            .collect();
+        crate::info!(config, "{}", lines.join("\n"));
+    }
+}
+
+/// Whether `error` means the server closed the connection, rather than
+/// just being slow
+fn is_closed(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::UnexpectedEof
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+    )
+}
+
+/// A connection that no longer speaks HTTP, after the server agreed to an
+/// `Upgrade`. Reading starts with anything that arrived right after the
+/// `101 Switching Protocols` response.
+#[derive(Debug)]
+pub struct Upgraded {
+    reader: BufReader<ThrottledReader<TcpStream>>,
+    socket: TcpStream,
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.socket.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.socket.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::http::HttpStatusCode;
+    use crate::http2::tests::Peer;
+    use crate::log::{tests::Captured, Logger, Verbosity};
+    use crate::rate_limit::{
+        tests::{assert_throughput, MockClock},
+        RateLimiter,
+    };
 
     use super::*;
 
@@ -122,7 +616,7 @@ mod tests {
     #[test]
     fn can_create_connection() {
         let (port, _l_thread) = create_listener_thread(|_s| {});
-        let config = Configuration { debug: 0 };
+        let config = Configuration::default();
         let _conn = Connection::new("localhost".to_string(), port, &config)
             .expect("Could not create connection");
     }
@@ -164,7 +658,7 @@ mod tests {
             s.write_all(&response.serialize())
                 .expect("Could not write response");
         });
-        let config = Configuration { debug: 0 };
+        let config = Configuration::default();
         let mut conn = Connection::new("localhost".to_string(), port, &config)
             .expect("Could not create connection");
         let resp = conn
@@ -175,4 +669,646 @@ mod tests {
         assert_eq!(resp.status_message, "Ok");
         assert_eq!(resp.get_header("My Header"), Some("Value"));
     }
+
+    #[test]
+    fn limits_download_rate() {
+        const BODY_LENGTH: usize = 20_000;
+        let response_head = format!("HTTP/1.1 200 Ok\r\nContent-Length: {BODY_LENGTH}\r\n\r\n");
+        let response_length = response_head.len() + BODY_LENGTH;
+        let (port, _l_thread) = create_listener_thread(move |mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            let mut line = String::new();
+            while reader.read_line(&mut line).is_ok() && line != "\r\n" {
+                line.clear();
+            }
+            s.write_all(response_head.as_bytes())
+                .expect("Could not write response");
+            s.write_all(&[b'a'; BODY_LENGTH])
+                .expect("Could not write response");
+        });
+        let clock = Arc::new(MockClock::default());
+        let config = Configuration {
+            rate_limiter: Some(Arc::new(RateLimiter::with_clock(4000, clock.clone()))),
+            ..Default::default()
+        };
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let resp = conn
+            .send_request("/", &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.get_data().len(), BODY_LENGTH);
+        assert_throughput(&clock, response_length, 4000);
+    }
+
+    #[test]
+    fn does_not_read_body_of_head_response() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            for expected in ["HEAD /file HTTP/1.1", "GET /file HTTP/1.1"] {
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("Could not read line");
+                assert_eq!(line.trim(), expected);
+                while line.trim() != "" {
+                    line.clear();
+                    reader.read_line(&mut line).expect("Could not read line");
+                }
+                let response: &[u8] = if expected.starts_with("GET") {
+                    b"HTTP/1.1 200 Ok\r\nContent-Length: 5\r\n\r\nabcde"
+                } else {
+                    b"HTTP/1.1 200 Ok\r\nContent-Length: 5\r\n\r\n"
+                };
+                s.write_all(response).expect("Could not write response");
+            }
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+
+        let request = conn.new_request(HttpMethod::Head, "/file");
+        let resp = conn
+            .send(&request, &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.get_header("Content-Length"), Some("5"));
+        assert!(resp.get_data().is_empty());
+
+        // If the HEAD response's body had been read, this would be out of sync
+        let resp = conn
+            .send_request("/file", &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.get_data(), b"abcde");
+    }
+
+    #[test]
+    fn speaks_http2_with_prior_knowledge() {
+        let (port, _l_thread) = create_listener_thread(|s| {
+            let mut peer = Peer::accept(s, &[]);
+            let (stream_id, _) = peer.read_request();
+            peer.respond(stream_id, "200", b"first");
+            let requests = [peer.read_request(), peer.read_request()];
+            for (stream_id, fields) in requests.iter().rev() {
+                let path = &fields
+                    .iter()
+                    .find(|(name, _)| name == ":path")
+                    .expect("No path")
+                    .1;
+                peer.respond(*stream_id, "200", path.as_bytes());
+            }
+        });
+        let config = Configuration {
+            http2_prior_knowledge: true,
+            ..Default::default()
+        };
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        assert!(conn.is_http2());
+        let resp = conn
+            .send_request("/", &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.version, HttpVersion::Version2_0);
+        assert_eq!(resp.get_data(), b"first");
+
+        let requests = [
+            conn.new_request(HttpMethod::Get, "/a"),
+            conn.new_request(HttpMethod::Get, "/b"),
+        ];
+        let responses = conn
+            .send_all(&requests, &config)
+            .expect("Could not receive responses");
+        let bodies: Vec<&[u8]> = responses
+            .iter()
+            .map(|resp| resp.as_ref().expect("Request failed").get_data())
+            .collect();
+        assert_eq!(bodies, [b"/a", b"/b"]);
+        assert_eq!(conn.requests_sent(), 3);
+        assert!(conn.is_reusable());
+    }
+
+    #[test]
+    fn sends_conditional_requests() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            for _ in 0..2 {
+                let mut etag = None;
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("Could not read line");
+                while line.trim() != "" {
+                    if let Some(value) = line.trim().strip_prefix("If-None-Match: ") {
+                        etag = Some(value.to_string());
+                    }
+                    line.clear();
+                    reader.read_line(&mut line).expect("Could not read line");
+                }
+                // A 304 may give the length the body would have had, without sending it
+                let response: &[u8] = if etag.as_deref() == Some("\"v1\"") {
+                    b"HTTP/1.1 304 Not Modified\r\nContent-Length: 5\r\n\r\n"
+                } else {
+                    b"HTTP/1.1 200 Ok\r\nETag: \"v2\"\r\nContent-Length: 5\r\n\r\nabcde"
+                };
+                s.write_all(response).expect("Could not write response");
+            }
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+
+        let conditions = Conditions {
+            if_modified_since: None,
+            if_none_match: Some("\"v1\"".to_string()),
+        };
+        let resp = conn
+            .send_conditional_request("/file", &conditions, &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.status_code, HttpStatusCode::NotModified);
+        assert!(resp.get_data().is_empty());
+
+        let resp = conn
+            .send_request("/file", &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.get_data(), b"abcde");
+    }
+
+    /// Reads the lines of a request head, up to the empty line after them
+    fn read_head<R: BufRead>(reader: &mut R) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("Could not read line");
+            if line.trim().is_empty() {
+                return lines;
+            }
+            lines.push(line.trim().to_string());
+        }
+    }
+
+    fn post_request(conn: &Connection, wait: Duration) -> HttpRequest {
+        let mut request = conn.new_request(HttpMethod::Post, "/upload");
+        request.set_body("hello");
+        request.expect_continue(wait);
+        request
+    }
+
+    #[test]
+    fn waits_for_continue_before_sending_body() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            let head = read_head(&mut reader);
+            assert!(head.contains(&"Expect: 100-continue".to_string()));
+            // Nothing more may be sent until the server says so
+            s.set_read_timeout(Some(Duration::from_millis(200)))
+                .expect("Could not set timeout");
+            let mut body = [0; 5];
+            assert!(reader.read(&mut body).is_err());
+            s.set_read_timeout(None).expect("Could not set timeout");
+            s.write_all(b"HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\n")
+                .expect("Could not write response");
+            s.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                .expect("Could not write response");
+            reader.read_exact(&mut body).expect("Could not read body");
+            s.write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 5\r\n\r\n")
+                .expect("Could not write response");
+            s.write_all(&body).expect("Could not write response");
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let request = post_request(&conn, Duration::from_secs(30));
+        let mut interim = Vec::new();
+        let resp = conn
+            .send_with_interim(&request, &config, &mut |_, _| {}, &mut |resp| {
+                interim.push(resp.status_code)
+            })
+            .expect("Could not receive response");
+        assert_eq!(
+            interim,
+            [HttpStatusCode::EarlyHints, HttpStatusCode::Continue]
+        );
+        assert_eq!(resp.status_code, HttpStatusCode::Created);
+        assert_eq!(resp.get_data(), b"hello");
+        assert!(conn.is_reusable());
+    }
+
+    #[test]
+    fn sends_body_if_continue_never_comes() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let request = HttpRequest::receive_request(
+                &mut BufReader::new(s.try_clone().expect("Could not clone stream")),
+                &Configuration::default(),
+            )
+            .expect("Could not read request");
+            assert_eq!(request.body(), b"hello");
+            s.write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .expect("Could not write response");
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let request = post_request(&conn, Duration::from_millis(100));
+        let resp = conn
+            .send(&request, &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.status_code, HttpStatusCode::NoContent);
+    }
+
+    #[test]
+    fn reads_slow_answers_to_expect_in_full() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            read_head(&mut reader);
+            // Taking longer over the head than the client waits to start
+            s.write_all(b"HTTP/1.1 100")
+                .expect("Could not write response");
+            thread::sleep(Duration::from_millis(300));
+            s.write_all(b" Continue\r\n\r\n")
+                .expect("Could not write response");
+            let mut body = [0; 5];
+            reader.read_exact(&mut body).expect("Could not read body");
+            s.write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .expect("Could not write response");
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let request = post_request(&conn, Duration::from_millis(100));
+        let mut interim = Vec::new();
+        let resp = conn
+            .send_with_interim(&request, &config, &mut |_, _| {}, &mut |resp| {
+                interim.push(resp.status_code)
+            })
+            .expect("Could not receive response");
+        assert_eq!(interim, [HttpStatusCode::Continue]);
+        assert_eq!(resp.status_code, HttpStatusCode::NoContent);
+    }
+
+    #[test]
+    fn does_not_send_refused_body() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            read_head(&mut BufReader::new(&mut s));
+            s.write_all(b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n")
+                .expect("Could not write response");
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let request = post_request(&conn, Duration::from_secs(30));
+        let resp = conn
+            .send(&request, &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.status_code, HttpStatusCode::Unauthorized);
+        assert!(!conn.is_reusable());
+    }
+
+    #[test]
+    fn switches_protocols() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            assert!(read_head(&mut reader).contains(&"Upgrade: echo".to_string()));
+            s.write_all(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: echo\r\n\r\nhi")
+                .expect("Could not write response");
+            let mut ping = [0; 4];
+            reader.read_exact(&mut ping).expect("Could not read");
+            s.write_all(&ping).expect("Could not write");
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let mut request = conn.new_request(HttpMethod::Get, "/");
+        request.add_header("Connection", "Upgrade");
+        request.add_header("Upgrade", "echo");
+        let resp = conn
+            .send(&request, &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.status_code, HttpStatusCode::SwitchingProtocols);
+        assert!(!conn.is_reusable());
+
+        let mut upgraded = conn.into_upgraded();
+        let mut greeting = [0; 2];
+        upgraded.read_exact(&mut greeting).expect("Could not read");
+        assert_eq!(&greeting, b"hi");
+        upgraded.write_all(b"ping").expect("Could not write");
+        let mut pong = [0; 4];
+        upgraded.read_exact(&mut pong).expect("Could not read");
+        assert_eq!(&pong, b"ping");
+    }
+
+    /// Reads the paths of `count` requests, before anything is answered
+    fn read_paths<R: BufRead>(reader: &mut R, count: usize) -> Vec<String> {
+        (0..count)
+            .map(|_| {
+                let head = read_head(reader);
+                head[0].split(' ').nth(1).expect("No path").to_string()
+            })
+            .collect()
+    }
+
+    fn respond_with_path(s: &mut TcpStream, path: &str) {
+        let response = format!(
+            "HTTP/1.1 200 Ok\r\nContent-Length: {}\r\n\r\n{path}",
+            path.len()
+        );
+        s.write_all(response.as_bytes())
+            .expect("Could not write response");
+    }
+
+    fn pipelined_requests(conn: &mut Connection, paths: &[&str]) -> Vec<String> {
+        let config = Configuration::default();
+        let requests: Vec<HttpRequest> = paths
+            .iter()
+            .map(|path| conn.new_request(HttpMethod::Get, path))
+            .collect();
+        conn.send_all(&requests, &config)
+            .expect("Could not receive responses")
+            .into_iter()
+            .map(|resp| {
+                let resp = resp.expect("Request failed");
+                String::from_utf8_lossy(resp.get_data()).into_owned()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn pipelines_requests() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            // All the requests arrive before the first is answered
+            for path in read_paths(&mut reader, 3) {
+                respond_with_path(&mut s, &path);
+            }
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        conn.set_pipelining(true);
+        assert_eq!(
+            pipelined_requests(&mut conn, &["/a", "/b", "/c"]),
+            ["/a", "/b", "/c"]
+        );
+        assert_eq!(conn.requests_sent(), 3);
+        assert!(conn.is_reusable());
+    }
+
+    #[test]
+    fn sends_unanswered_requests_again_without_pipelining() {
+        let mut connections = 0;
+        let (port, _l_thread) = create_listener_thread(move |mut s| {
+            connections += 1;
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            if connections == 1 {
+                // Answers the first request, then gives up on the rest
+                let paths = read_paths(&mut reader, 3);
+                respond_with_path(&mut s, &paths[0]);
+            } else {
+                for path in ["/b", "/c"] {
+                    assert_eq!(read_paths(&mut reader, 1), [path]);
+                    respond_with_path(&mut s, path);
+                }
+            }
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        conn.set_pipelining(true);
+        assert_eq!(
+            pipelined_requests(&mut conn, &["/a", "/b", "/c"]),
+            ["/a", "/b", "/c"]
+        );
+        assert_eq!(conn.requests_sent(), 5);
+        assert!(!conn.pipelining);
+    }
+
+    #[test]
+    fn does_not_send_timed_out_requests_again() {
+        let mut connections = 0;
+        let (port, _l_thread) = create_listener_thread(move |mut s| {
+            connections += 1;
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            if connections == 1 {
+                // Answers the first request, then takes too long over the rest
+                let paths = read_paths(&mut reader, 3);
+                respond_with_path(&mut s, &paths[0]);
+                thread::sleep(Duration::from_millis(500));
+            } else {
+                assert_eq!(read_paths(&mut reader, 1), ["/c"]);
+                respond_with_path(&mut s, "/c");
+            }
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        conn.socket
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .expect("Could not set timeout");
+        conn.set_pipelining(true);
+        let requests: Vec<HttpRequest> = ["/a", "/b", "/c"]
+            .iter()
+            .map(|path| conn.new_request(HttpMethod::Get, path))
+            .collect();
+        let responses = conn
+            .send_all(&requests, &config)
+            .expect("Could not receive responses");
+        let body = |resp: &WgetResult<HttpResponse>| {
+            resp.as_ref().expect("Request failed").get_data().to_vec()
+        };
+        assert_eq!(body(&responses[0]), b"/a");
+        assert!(matches!(&responses[1], Err(WgetError::Io(e))
+            if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)));
+        assert_eq!(body(&responses[2]), b"/c");
+        assert_eq!(conn.requests_sent(), 4);
+        assert!(!conn.pipelining);
+    }
+
+    #[test]
+    fn does_not_pipeline_past_connection_close() {
+        let mut connections = 0;
+        let (port, _l_thread) = create_listener_thread(move |mut s| {
+            connections += 1;
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            if connections == 1 {
+                read_paths(&mut reader, 2);
+                s.write_all(b"HTTP/1.1 200 Ok\r\nConnection: close\r\nContent-Length: 2\r\n\r\n/a")
+                    .expect("Could not write response");
+            } else {
+                assert_eq!(read_paths(&mut reader, 1), ["/b"]);
+                respond_with_path(&mut s, "/b");
+            }
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        conn.set_pipelining(true);
+        assert_eq!(pipelined_requests(&mut conn, &["/a", "/b"]), ["/a", "/b"]);
+    }
+
+    #[test]
+    fn does_not_pipeline_unsafe_requests() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            assert_eq!(read_paths(&mut reader, 1), ["/form"]);
+            let mut body = [0; 5];
+            reader.read_exact(&mut body).expect("Could not read body");
+            respond_with_path(&mut s, "/form");
+            for path in read_paths(&mut reader, 2) {
+                respond_with_path(&mut s, &path);
+            }
+        });
+        let config = Configuration::default();
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        conn.set_pipelining(true);
+        let mut post = conn.new_request(HttpMethod::Post, "/form");
+        post.set_body("a=1&b");
+        let requests = [
+            post,
+            conn.new_request(HttpMethod::Get, "/a"),
+            conn.new_request(HttpMethod::Get, "/b"),
+        ];
+        let responses = conn
+            .send_all(&requests, &config)
+            .expect("Could not receive responses");
+        let bodies: Vec<&[u8]> = responses
+            .iter()
+            .map(|resp| resp.as_ref().expect("Request failed").get_data())
+            .collect();
+        assert_eq!(bodies, [&b"/form"[..], b"/a", b"/b"]);
+    }
+
+    #[test]
+    fn speaks_http10_without_keep_alive() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            let head = read_head(&mut reader);
+            assert!(head[0].ends_with(" HTTP/1.0"));
+            assert!(head.contains(&"Connection: close".to_string()));
+            // The body ends with the connection
+            let path = head[0].split(' ').nth(1).expect("No path");
+            s.write_all(format!("HTTP/1.0 200 OK\r\n\r\n{path}").as_bytes())
+                .expect("Could not write response");
+        });
+        let config = Configuration {
+            http_version: HttpVersion::Version1_0,
+            no_keep_alive: true,
+            ..Default::default()
+        };
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let requests = [
+            conn.new_request(HttpMethod::Get, "/a"),
+            conn.new_request(HttpMethod::Get, "/b"),
+        ];
+        let responses = conn
+            .send_all(&requests, &config)
+            .expect("Could not receive responses");
+        let bodies: Vec<&[u8]> = responses
+            .iter()
+            .map(|resp| resp.as_ref().expect("Request failed").get_data())
+            .collect();
+        assert_eq!(bodies, [b"/a", b"/b"]);
+        assert!(!conn.is_reusable());
+    }
+
+    #[test]
+    fn takes_only_the_first_response_for_http09() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            let head = read_head(&mut reader);
+            if head[0].starts_with("GET /old ") {
+                s.write_all(b"Just a body").expect("Could not write body");
+                return;
+            }
+            respond_with_path(&mut s, "/a");
+            read_head(&mut reader);
+            s.write_all(b"Not a status line\r\n\r\n")
+                .expect("Could not write response");
+        });
+        let config = Configuration {
+            accept_http09: true,
+            ..Default::default()
+        };
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let resp = conn
+            .send_request("/old", &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.version, HttpVersion::Version0_9);
+        assert_eq!(resp.get_data(), b"Just a body");
+
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let resp = conn
+            .send_request("/a", &config)
+            .expect("Could not receive response");
+        assert_eq!(resp.get_data(), b"/a");
+        assert!(conn.send_request("/b", &config).is_err());
+    }
+
+    #[test]
+    fn changes_default_headers() {
+        let (port, _l_thread) = create_listener_thread(|_s| {});
+        let config = Configuration {
+            headers: vec![
+                ("user-agent".to_string(), "test/1.0".to_string()),
+                ("Accept-Encoding".to_string(), String::new()),
+                ("X-Custom".to_string(), "a: b".to_string()),
+            ],
+            ..Default::default()
+        };
+        let conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        let request = conn.new_request(HttpMethod::Get, "/");
+        assert_eq!(request.get_header("User-Agent"), Some("test/1.0"));
+        assert_eq!(request.get_header("Accept-Encoding"), None);
+        assert_eq!(request.get_header("X-Custom"), Some("a: b"));
+        assert_eq!(request.get_header("Host"), Some("localhost"));
+    }
+
+    #[test]
+    fn logs_response_heads() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            read_head(&mut reader);
+            s.write_all(b"HTTP/1.1 200 Ok\r\nContent-Length: 2\r\nX-Cache: HIT\r\n\r\nhi")
+                .expect("Could not write response");
+        });
+        let captured = Captured::default();
+        let config = Configuration {
+            logger: Arc::new(Logger::new(Verbosity::Verbose, Box::new(captured.clone()))),
+            server_response: true,
+            ..Default::default()
+        };
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        conn.send_request("/", &config)
+            .expect("Could not receive response");
+        assert_eq!(
+            captured.text(),
+            "  HTTP/1.1 200 Ok\n  Content-Length: 2\n  X-Cache: HIT\n"
+        );
+    }
+
+    #[test]
+    fn logs_interim_response_heads() {
+        let (port, _l_thread) = create_listener_thread(|mut s| {
+            let mut reader = BufReader::new(s.try_clone().expect("Could not clone stream"));
+            read_head(&mut reader);
+            s.write_all(
+                b"HTTP/1.1 103 Early Hints\r\nLink: </a.css>\r\n\r\n\
+                  HTTP/1.1 200 Ok\r\nContent-Length: 2\r\n\r\nhi",
+            )
+            .expect("Could not write response");
+        });
+        let captured = Captured::default();
+        let config = Configuration {
+            logger: Arc::new(Logger::new(Verbosity::Verbose, Box::new(captured.clone()))),
+            server_response: true,
+            ..Default::default()
+        };
+        let mut conn = Connection::new("localhost".to_string(), port, &config)
+            .expect("Could not create connection");
+        conn.send_request("/", &config)
+            .expect("Could not receive response");
+        assert_eq!(
+            captured.text(),
+            "  HTTP/1.1 103 Early Hints\n  Link: </a.css>\n  \
+             HTTP/1.1 200 Ok\n  Content-Length: 2\n"
+        );
+    }
 }
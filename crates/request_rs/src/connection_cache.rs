@@ -14,21 +14,44 @@ impl ConnectionCache {
         config: &Configuration,
     ) -> WgetResult<&mut Connection> {
         match self.connections.entry((url.domain_name.clone(), url.port)) {
+            std::collections::hash_map::Entry::Occupied(mut o) if !o.get().is_reusable() => {
+                crate::trace!(
+                    config,
+                    "Old connection for {} port {} is closing, reconnecting",
+                    url.domain_name,
+                    url.port
+                );
+                o.insert(Self::connect(url, config)?);
+                Ok(o.into_mut())
+            }
             std::collections::hash_map::Entry::Occupied(o) => {
-                if config.debug > 1 {
-                    println!(
-                        "Reusing old connection for {} port {}",
-                        url.domain_name, url.port
-                    );
-                }
+                crate::trace!(
+                    config,
+                    "Reusing old connection for {} port {}",
+                    url.domain_name,
+                    url.port
+                );
                 Ok(o.into_mut())
             }
             std::collections::hash_map::Entry::Vacant(v) => {
-                let conn = Connection::new(url.domain_name.to_string(), url.port, config)?;
+                let conn = Self::connect(url, config)?;
                 Ok(v.insert(conn))
             }
         }
     }
+
+    fn connect(url: &ParsedUrl, config: &Configuration) -> WgetResult<Connection> {
+        let mut conn = Connection::new(url.domain_name.to_string(), url.port, config)?;
+        conn.set_scheme(url.protocol);
+        Ok(conn)
+    }
+
+    /// Closes the cached connection for `url`'s host, if there is one, so the
+    /// next request makes a new one
+    pub fn remove(&mut self, url: &ParsedUrl) -> Option<Connection> {
+        self.connections
+            .remove(&(url.domain_name.clone(), url.port))
+    }
 }
 
 #[cfg(test)]
@@ -44,7 +67,7 @@ mod tests {
         thread::{self, JoinHandle},
     };
 
-    use crate::protocol::Protocol;
+    use crate::{http::HttpMethod, protocol::Protocol};
 
     use super::*;
 
@@ -90,7 +113,7 @@ mod tests {
     #[test]
     fn creates_connection() {
         let mut conn_cache = ConnectionCache::default();
-        let config = Configuration { debug: 0 };
+        let config = Configuration::default();
         let (port, _t) = create_listener_thread();
 
         let url = ParsedUrl {
@@ -105,10 +128,30 @@ mod tests {
             .expect("Could not connect!");
     }
 
+    #[test]
+    fn requests_use_the_scheme_of_the_url() {
+        let mut conn_cache = ConnectionCache::default();
+        let config = Configuration::default();
+        let (port, _t) = create_listener_thread();
+
+        let url = ParsedUrl {
+            protocol: Protocol::Https,
+            domain_name: "localhost".to_string(),
+            port,
+            path: "/".to_string(),
+            filename: "index.html".to_string(),
+        };
+        let conn = conn_cache
+            .get_connection(&url, &config)
+            .expect("Could not connect!");
+        let request = conn.new_request(HttpMethod::Get, "/");
+        assert_eq!(request.scheme(), Protocol::Https);
+    }
+
     #[test]
     fn reuses_connection() {
         let mut conn_cache = ConnectionCache::default();
-        let config = Configuration { debug: 0 };
+        let config = Configuration::default();
         let (port, _t) = create_listener_thread();
 
         // The only way I can think of to check if two TcpStream objects are the
@@ -158,10 +201,31 @@ mod tests {
         assert_eq!(fd1, fd2);
     }
 
+    #[test]
+    fn removes_connection() {
+        let mut conn_cache = ConnectionCache::default();
+        let config = Configuration::default();
+        let (port, _t) = create_listener_thread();
+
+        let url = ParsedUrl {
+            protocol: Protocol::Http,
+            domain_name: "localhost".to_string(),
+            port,
+            path: "/".to_string(),
+            filename: "index.html".to_string(),
+        };
+        conn_cache
+            .get_connection(&url, &config)
+            .expect("Could not connect!");
+        assert!(conn_cache.remove(&url).is_some());
+        assert_eq!(conn_cache.connections.len(), 0);
+        assert!(conn_cache.remove(&url).is_none());
+    }
+
     #[test]
     fn creates_new_connection() {
         let mut conn_cache = ConnectionCache::default();
-        let config = Configuration { debug: 0 };
+        let config = Configuration::default();
         let (port1, _t1) = create_listener_thread();
         let (port2, _t2) = create_listener_thread();
 
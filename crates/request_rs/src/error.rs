@@ -2,14 +2,28 @@ use std::io;
 
 use thiserror::Error;
 
+use crate::http::HttpStatusCode;
+
 #[derive(Error, Debug)]
 pub enum WgetError {
+    /// Reading from or writing to the network failed
     #[error("IO Error: {0}")]
     Io(#[from] io::Error),
+    /// Something the server sent couldn't be parsed
     #[error("Parsing Error: {0}")]
     ParsingError(String),
     #[error("Invalid Status Code: {0}")]
     InvalidStatusCode(u16),
+    /// A URL given to us couldn't be parsed
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    /// The server sent something well formed that still made no sense, like a
+    /// redirect without a `Location`
+    #[error("Protocol Error: {0}")]
+    ProtocolError(String),
+    /// The server answered with a client or server error status
+    #[error("Server responded with {0} {1}")]
+    ErrorResponse(HttpStatusCode, String),
 }
 
 pub type WgetResult<T> = std::result::Result<T, WgetError>;
@@ -2,10 +2,11 @@ use core::fmt;
 
 use crate::error::{WgetError, WgetResult};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum HttpVersion {
     Version0_9,
     Version1_0,
+    #[default]
     Version1_1,
     Version2_0,
 }
@@ -20,9 +21,7 @@ impl TryFrom<&str> for HttpVersion {
             "HTTP/1.0" => Ok(HttpVersion::Version1_0),
             "HTTP/1.1" => Ok(HttpVersion::Version1_1),
             "HTTP/2" => Ok(HttpVersion::Version2_0),
-            _ => Err(WgetError::ParsingError(format!(
-                "Invalid Version {value}"
-            ))),
+            _ => Err(WgetError::ParsingError(format!("Invalid Version {value}"))),
         }
     }
 }
@@ -0,0 +1,305 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::error::{WgetError, WgetResult};
+
+const DAY_NAMES: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTH_NAMES: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// A timestamp as used by headers such as `Last-Modified` and `If-Modified-Since`
+///
+/// HTTP dates only have a resolution of one second, so any fraction of a
+/// second is dropped when converting from a `SystemTime`. Parsing accepts all
+/// three formats from RFC 9110: `Sun, 06 Nov 1994 08:49:37 GMT`,
+/// `Sunday, 06-Nov-94 08:49:37 GMT` and `Sun Nov  6 08:49:37 1994`, but only
+/// the first is ever produced.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HttpDate {
+    /// Seconds since the Unix epoch
+    seconds: i64,
+}
+
+impl From<SystemTime> for HttpDate {
+    fn from(time: SystemTime) -> Self {
+        let seconds = match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_secs() as i64,
+            // Round down, so times before the epoch are still truncated towards the past
+            Err(e) => -(e.duration().as_secs_f64().ceil() as i64),
+        };
+        HttpDate { seconds }
+    }
+}
+
+/// Fails for dates the platform's `SystemTime` can't represent
+impl TryFrom<HttpDate> for SystemTime {
+    type Error = WgetError;
+
+    fn try_from(date: HttpDate) -> WgetResult<Self> {
+        let offset = Duration::from_secs(date.seconds.unsigned_abs());
+        if date.seconds >= 0 {
+            UNIX_EPOCH.checked_add(offset)
+        } else {
+            UNIX_EPOCH.checked_sub(offset)
+        }
+        .ok_or_else(|| WgetError::ProtocolError(format!("Date {date} is out of range")))
+    }
+}
+
+impl FromStr for HttpDate {
+    type Err = WgetError;
+
+    fn from_str(s: &str) -> WgetResult<Self> {
+        let invalid = || WgetError::ParsingError(format!("Invalid HTTP date {s}"));
+        let parts: Vec<&str> = s.split_whitespace().collect();
+        let (year, month, day, time) = match parts[..] {
+            // Sun, 06 Nov 1994 08:49:37 GMT
+            [weekday, day, month, year, time, "GMT"] if weekday.ends_with(',') => {
+                (parse_year(year)?, month, day, time)
+            }
+            // Sunday, 06-Nov-94 08:49:37 GMT
+            [weekday, date, time, "GMT"] if weekday.ends_with(',') => {
+                let mut date = date.split('-');
+                let (Some(day), Some(month), Some(year), None) =
+                    (date.next(), date.next(), date.next(), date.next())
+                else {
+                    return Err(invalid());
+                };
+                if year.len() != 2 {
+                    return Err(invalid());
+                }
+                // Two digit years are taken to be within 50 years of 2000,
+                // which will do until HTTP/1.0 servers are long gone
+                let year = parse_number(year)?;
+                let year = if year < 50 { 2000 + year } else { 1900 + year };
+                (year, month, day, time)
+            }
+            // Sun Nov  6 08:49:37 1994
+            [_weekday, month, day, time, year] => (parse_year(year)?, month, day, time),
+            _ => return Err(invalid()),
+        };
+        let month = MONTH_NAMES
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(month))
+            .ok_or_else(invalid)? as u32
+            + 1;
+        let day = u32::try_from(parse_number(day)?).map_err(|_| invalid())?;
+        if day == 0 || day > days_in_month(year, month) {
+            return Err(invalid());
+        }
+        let mut time = time.split(':');
+        let (Some(hour), Some(minute), Some(second), None) =
+            (time.next(), time.next(), time.next(), time.next())
+        else {
+            return Err(invalid());
+        };
+        let (hour, minute, second) = (
+            parse_number(hour)?,
+            parse_number(minute)?,
+            parse_number(second)?,
+        );
+        // Allow for leap seconds, which a SystemTime can't represent anyway
+        if hour > 23 || minute > 59 || second > 60 {
+            return Err(invalid());
+        }
+        let seconds = days_from_civil(year, month, day)
+            .checked_mul(SECONDS_PER_DAY)
+            .and_then(|seconds| seconds.checked_add(hour * 60 * 60 + minute * 60 + second.min(59)))
+            .ok_or_else(invalid)?;
+        Ok(HttpDate { seconds })
+    }
+}
+
+impl fmt::Display for HttpDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = self.seconds.div_euclid(SECONDS_PER_DAY);
+        let time = self.seconds.rem_euclid(SECONDS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        // The epoch was a Thursday
+        let weekday = DAY_NAMES[(days + 4).rem_euclid(7) as usize];
+        write!(
+            f,
+            "{weekday}, {day:02} {} {year:04} {:02}:{:02}:{:02} GMT",
+            MONTH_NAMES[month as usize - 1],
+            time / (60 * 60),
+            time / 60 % 60,
+            time % 60
+        )
+    }
+}
+
+fn parse_number(s: &str) -> WgetResult<i64> {
+    let invalid = || WgetError::ParsingError(format!("Invalid number {s} in date"));
+    if !s.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    s.parse().map_err(|_| invalid())
+}
+
+/// Parses a four digit year, which is all the formats allow and keeps the
+/// arithmetic on dates from overflowing
+fn parse_year(s: &str) -> WgetResult<i64> {
+    let year = parse_number(s)?;
+    if !(1..=9999).contains(&year) {
+        return Err(WgetError::ParsingError(format!(
+            "Year {s} in date is out of range"
+        )));
+    }
+    Ok(year)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days since 1970-01-01 of the given date in the proleptic Gregorian
+/// calendar, using Howard Hinnant's algorithm
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The inverse of `days_from_civil`
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400;
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sun, 06 Nov 1994 08:49:37 GMT, the example from the RFC
+    const EXAMPLE: u64 = 784111777;
+
+    fn example() -> HttpDate {
+        HttpDate::from(UNIX_EPOCH + Duration::from_secs(EXAMPLE))
+    }
+
+    #[test]
+    fn parses_imf_fixdate() {
+        assert_eq!(
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+                .parse::<HttpDate>()
+                .expect("Could not parse date"),
+            example()
+        );
+    }
+
+    #[test]
+    fn parses_obsolete_formats() {
+        assert_eq!(
+            "Sunday, 06-Nov-94 08:49:37 GMT"
+                .parse::<HttpDate>()
+                .expect("Could not parse date"),
+            example()
+        );
+        assert_eq!(
+            "Sun Nov  6 08:49:37 1994"
+                .parse::<HttpDate>()
+                .expect("Could not parse date"),
+            example()
+        );
+    }
+
+    #[test]
+    fn formats_imf_fixdate() {
+        assert_eq!(example().to_string(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(
+            HttpDate::from(UNIX_EPOCH).to_string(),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+        assert_eq!(
+            "Tue, 29 Feb 2000 23:59:59 GMT"
+                .parse::<HttpDate>()
+                .expect("Could not parse date")
+                .to_string(),
+            "Tue, 29 Feb 2000 23:59:59 GMT"
+        );
+    }
+
+    #[test]
+    fn round_trips_dates_before_the_epoch() {
+        let date = "Sat, 31 Dec 1960 12:00:00 GMT"
+            .parse::<HttpDate>()
+            .expect("Could not parse date");
+        assert_eq!(date.to_string(), "Sat, 31 Dec 1960 12:00:00 GMT");
+        let time = SystemTime::try_from(date).expect("Date out of range");
+        assert_eq!(HttpDate::from(time), date);
+    }
+
+    #[test]
+    fn handles_the_first_and_last_four_digit_years() {
+        for date in [
+            "Mon, 01 Jan 0001 00:00:00 GMT",
+            "Fri, 31 Dec 9999 23:59:59 GMT",
+        ] {
+            let parsed = date.parse::<HttpDate>().expect("Could not parse date");
+            assert_eq!(parsed.to_string(), date);
+            let time = SystemTime::try_from(parsed).expect("Date out of range");
+            assert_eq!(HttpDate::from(time), parsed);
+        }
+    }
+
+    #[test]
+    fn drops_fractions_of_seconds() {
+        let time = UNIX_EPOCH + Duration::from_millis(EXAMPLE * 1000 + 999);
+        assert_eq!(HttpDate::from(time), example());
+    }
+
+    #[test]
+    fn rejects_invalid_dates() {
+        for date in [
+            "",
+            "yesterday",
+            "Sun, 06 Nov 1994 08:49:37 PST",
+            "Sun, 31 Nov 1994 08:49:37 GMT",
+            "Sun, 29 Feb 1900 08:49:37 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov +994 08:49:37 GMT",
+            "Sunday, 06-Nov-1994 08:49:37 GMT",
+            // Would overflow the number of seconds, or a u32 day
+            "Sun, 06 Nov 9999999999999 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 99999999999999999",
+            "Sun, 06 Nov 0 08:49:37 GMT",
+            "Sun, 4294967297 Nov 1994 08:49:37 GMT",
+        ] {
+            assert!(date.parse::<HttpDate>().is_err(), "{date}");
+        }
+    }
+}
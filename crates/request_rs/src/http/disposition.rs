@@ -0,0 +1,234 @@
+use std::str::FromStr;
+
+use crate::error::{WgetError, WgetResult};
+
+/// The value of a `Content-Disposition` header, e.g.
+/// `attachment; filename="report.pdf"`, as described by RFC 6266
+///
+/// Only the `filename` parameters are kept. When both `filename` and the
+/// RFC 5987 encoded `filename*` are given, `filename*` wins, since it is the
+/// one that can hold any character.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentDisposition {
+    /// `inline`, `attachment` or an extension, always in lowercase
+    pub disposition: String,
+    pub filename: Option<String>,
+}
+
+impl FromStr for ContentDisposition {
+    type Err = WgetError;
+
+    fn from_str(s: &str) -> WgetResult<Self> {
+        let invalid = || WgetError::ParsingError(format!("Invalid content disposition {s}"));
+        let mut parser = Parser { rest: s };
+        let disposition = parser.token().ok_or_else(invalid)?.to_ascii_lowercase();
+        let mut filename = None;
+        let mut extended_filename = None;
+        loop {
+            parser.skip_whitespace();
+            if parser.rest.is_empty() {
+                break;
+            }
+            if !parser.eat(';') {
+                return Err(invalid());
+            }
+            parser.skip_whitespace();
+            // Some servers leave a trailing `;`
+            if parser.rest.is_empty() {
+                break;
+            }
+            let name = parser.token().ok_or_else(invalid)?;
+            parser.skip_whitespace();
+            if !parser.eat('=') {
+                return Err(invalid());
+            }
+            parser.skip_whitespace();
+            let value = parser.value().ok_or_else(invalid)?;
+            if name.eq_ignore_ascii_case("filename") {
+                filename = Some(value);
+            } else if name.eq_ignore_ascii_case("filename*") {
+                // A value we can't decode is ignored, leaving `filename` to be used instead
+                extended_filename = decode_ext_value(&value);
+            }
+        }
+        Ok(ContentDisposition {
+            disposition,
+            filename: extended_filename.or(filename),
+        })
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    fn skip_whitespace(&mut self) {
+        self.rest = self.rest.trim_start_matches([' ', '\t']);
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// An RFC 9110 token, which also covers the `*` of extended parameter names
+    fn token(&mut self) -> Option<&'a str> {
+        let end = self
+            .rest
+            .find(|c: char| !is_token_char(c))
+            .unwrap_or(self.rest.len());
+        let (token, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        (!token.is_empty()).then_some(token)
+    }
+
+    /// A token or a quoted string
+    fn value(&mut self) -> Option<String> {
+        if !self.eat('"') {
+            return self.token().map(str::to_string);
+        }
+        let mut value = String::new();
+        let mut chars = self.rest.char_indices();
+        while let Some((index, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.rest = &self.rest[index + 1..];
+                    return Some(value);
+                }
+                '\\' => value.push(chars.next()?.1),
+                c => value.push(c),
+            }
+        }
+        // The closing quote is missing
+        None
+    }
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+/// Decodes an RFC 5987 `ext-value` like `UTF-8''%e2%82%ac%20rates.txt`
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+    let bytes = percent_decode(encoded)?;
+    if charset.eq_ignore_ascii_case("UTF-8") {
+        String::from_utf8(bytes).ok()
+    } else if charset.eq_ignore_ascii_case("ISO-8859-1") {
+        // The first 256 code points are exactly ISO-8859-1
+        Some(bytes.into_iter().map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+fn percent_decode(s: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut input = s.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            // `from_str_radix` would also take a sign, as in `%+f`
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filename(s: &str) -> Option<String> {
+        s.parse::<ContentDisposition>()
+            .expect("Could not parse disposition")
+            .filename
+    }
+
+    #[test]
+    fn parses_disposition_type() {
+        let disposition = "Attachment"
+            .parse::<ContentDisposition>()
+            .expect("Could not parse");
+        assert_eq!(
+            disposition,
+            ContentDisposition {
+                disposition: "attachment".to_string(),
+                filename: None
+            }
+        );
+    }
+
+    #[test]
+    fn parses_filenames() {
+        assert_eq!(
+            filename("attachment; filename=report.pdf"),
+            Some("report.pdf".to_string())
+        );
+        assert_eq!(
+            filename("attachment;filename=\"annual report; 2024.pdf\";"),
+            Some("annual report; 2024.pdf".to_string())
+        );
+        assert_eq!(
+            filename(r#"inline; FILENAME="say \"hi\".txt""#),
+            Some("say \"hi\".txt".to_string())
+        );
+    }
+
+    #[test]
+    fn prefers_extended_filenames() {
+        assert_eq!(
+            filename(
+                "attachment; filename=\"EURO rates.txt\"; filename*=UTF-8''%e2%82%ac%20rates.txt"
+            ),
+            Some("€ rates.txt".to_string())
+        );
+        assert_eq!(
+            filename("attachment; filename*=iso-8859-1'en'%A3%20rates.txt"),
+            Some("£ rates.txt".to_string())
+        );
+        // Falls back to `filename` if `filename*` can't be decoded
+        assert_eq!(
+            filename("attachment; filename*=KOI8-R''%c5; filename=fallback.txt"),
+            Some("fallback.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_percent_escapes() {
+        assert_eq!(percent_decode("%41%2f"), Some(b"A/".to_vec()));
+        for s in ["%+f", "%-1", "%4", "%g0", "% 1"] {
+            assert_eq!(percent_decode(s), None, "{s}");
+        }
+        assert_eq!(
+            filename("attachment; filename*=UTF-8''%+fa.txt; filename=fallback.txt"),
+            Some("fallback.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        for header in [
+            "",
+            "; filename=a",
+            "attachment filename=a",
+            "attachment; filename",
+            "attachment; filename=\"unterminated",
+        ] {
+            assert!(header.parse::<ContentDisposition>().is_err(), "{header}");
+        }
+    }
+}
@@ -85,8 +85,25 @@ impl<'a> IntoIterator for &'a Headers {
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
 
+    #[derive(Clone, Debug)]
+    enum Operation {
+        Add(String, String),
+        Remove(String),
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        let key = prop::sample::select(&["a", "A", "b", "Content-Type", "content-type"][..])
+            .prop_map(str::to_string);
+        prop_oneof![
+            (key.clone(), "[a-z]{0,5}").prop_map(|(key, value)| Operation::Add(key, value)),
+            key.prop_map(Operation::Remove),
+        ]
+    }
+
     #[test]
     fn can_make_default() {
         let _ = Headers::default();
@@ -123,4 +140,34 @@ mod tests {
         assert_eq!(headers.remove("My Header"), Some("My Value".to_string()));
         assert_eq!(headers.get("My Header 2"), Some("My Value 2"));
     }
+
+    proptest! {
+        #[test]
+        fn behaves_like_a_list(operations in prop::collection::vec(operation(), 0..30)) {
+            let mut headers = Headers::default();
+            let mut expected: Vec<(String, String)> = Vec::new();
+            for operation in operations {
+                match operation {
+                    Operation::Add(key, value) => {
+                        match expected.iter_mut().find(|(k, _)| k.eq_ignore_ascii_case(&key)) {
+                            Some((_, v)) => v.clone_from(&value),
+                            None => expected.push((key.clone(), value.clone())),
+                        }
+                        headers.add(key, value);
+                    }
+                    Operation::Remove(key) => {
+                        let index = expected.iter().position(|(k, _)| k.eq_ignore_ascii_case(&key));
+                        prop_assert_eq!(headers.remove(&key), index.map(|i| expected.remove(i).1));
+                    }
+                }
+                prop_assert_eq!(
+                    (&headers).into_iter().collect::<Vec<_>>(),
+                    expected.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect::<Vec<_>>()
+                );
+                for (key, value) in &expected {
+                    prop_assert_eq!(headers.get(&key.to_lowercase()), Some(value.as_str()));
+                }
+            }
+        }
+    }
 }
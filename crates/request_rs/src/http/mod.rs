@@ -1,8 +1,16 @@
 mod common;
+mod date;
+mod disposition;
 mod headers;
+mod multipart;
+mod range;
 mod request;
 mod response;
 
 pub use common::HttpVersion;
-pub use request::{HttpMethod, HttpRequest};
-pub use response::{HttpResponse, HttpStatusCode, HttpStatusFamily};
+pub use date::HttpDate;
+pub use disposition::ContentDisposition;
+pub use multipart::BodyPart;
+pub use range::{ByteRange, ContentRange, RangeSpec};
+pub use request::{Conditions, HttpMethod, HttpRequest};
+pub use response::{HeaderLimits, HttpResponse, HttpStatusCode, HttpStatusFamily};
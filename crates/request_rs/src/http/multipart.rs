@@ -0,0 +1,209 @@
+use unicase::UniCase;
+
+use super::{
+    headers::Headers,
+    response::{read_fields, require_http_line},
+    ByteRange, ContentRange,
+};
+use crate::{
+    error::{WgetError, WgetResult},
+    Configuration,
+};
+
+/// One range of a resource from a `206 Partial Content` response
+#[derive(Debug)]
+pub struct BodyPart {
+    pub range: ByteRange,
+    /// The length of the whole resource, if the server knows it
+    pub complete_length: Option<u64>,
+    headers: Headers,
+    data: Vec<u8>,
+}
+
+impl BodyPart {
+    pub(super) fn new(
+        content_range: ContentRange,
+        headers: Headers,
+        data: Vec<u8>,
+    ) -> WgetResult<Self> {
+        let range = content_range.range.ok_or_else(|| {
+            WgetError::ParsingError(format!("Partial content without a range: {content_range}"))
+        })?;
+        if data.len() as u64 != range.len() {
+            return Err(WgetError::ParsingError(format!(
+                "Got {} bytes for {content_range}",
+                data.len()
+            )));
+        }
+        Ok(BodyPart {
+            range,
+            complete_length: content_range.complete_length,
+            headers,
+            data,
+        })
+    }
+
+    /// A header of this part, which are `Content-Range` and usually
+    /// `Content-Type`
+    pub fn get_header<K>(&self, key: &K) -> Option<&str>
+    where
+        K: ?Sized,
+        K: AsRef<str>,
+    {
+        self.headers.get(key)
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// The boundary parameter of a `multipart/byteranges` content type, or `None`
+/// for any other type
+pub(super) fn byteranges_boundary(content_type: &str) -> Option<&str> {
+    let mut params = content_type.split(';');
+    let media_type = params.next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/byteranges") {
+        return None;
+    }
+    params.find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        name.trim()
+            .eq_ignore_ascii_case("boundary")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Splits a `multipart/byteranges` body into its parts, as described in
+/// appendix A of RFC 9110
+///
+/// Each part's data is taken to be as long as its `Content-Range` says rather
+/// than searched for the boundary, which binary data could contain by chance.
+pub(super) fn parse_byteranges(
+    mut body: &[u8],
+    boundary: &str,
+    config: &Configuration,
+) -> WgetResult<Vec<BodyPart>> {
+    let max_length = config.header_limits.max_line_length;
+    let delimiter = format!("--{boundary}");
+    let close_delimiter = format!("{delimiter}--");
+    // Anything before the first delimiter is a preamble to be ignored
+    while require_http_line(&mut body, max_length)?.trim_end() != delimiter {}
+
+    let mut parts = Vec::new();
+    loop {
+        let headers = read_fields(&mut body, config)?;
+        let content_range = headers
+            .get("Content-Range")
+            .ok_or_else(|| WgetError::ParsingError("Part without a Content-Range".to_string()))?
+            .parse::<ContentRange>()?;
+        let length = content_range
+            .range
+            .and_then(|range| usize::try_from(range.len()).ok())
+            .unwrap_or_default();
+        if body.len() < length {
+            return Err(WgetError::ParsingError(format!(
+                "Body ends in the middle of {content_range}"
+            )));
+        }
+        let (data, rest) = body.split_at(length);
+        body = rest;
+        parts.push(BodyPart::new(content_range, headers, data.to_vec())?);
+
+        // The line break before a delimiter belongs to the delimiter
+        if !require_http_line(&mut body, max_length)?.is_empty() {
+            return Err(WgetError::ParsingError(format!(
+                "Part is longer than {content_range}"
+            )));
+        }
+        let line = require_http_line(&mut body, max_length)?;
+        match line.trim_end() {
+            line if line == close_delimiter => return Ok(parts),
+            line if line == delimiter => {}
+            line => {
+                return Err(WgetError::ParsingError(format!(
+                    "Expected a multipart delimiter, got {line}"
+                )))
+            }
+        }
+    }
+}
+
+/// The headers of a part sent as the whole body of a response
+pub(super) fn part_headers(content_range: &str, content_type: Option<&str>) -> Headers {
+    let mut headers = Headers::default();
+    if let Some(content_type) = content_type {
+        headers.add(UniCase::new("Content-Type".to_string()), content_type);
+    }
+    headers.add(UniCase::new("Content-Range".to_string()), content_range);
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &str = concat!(
+        "This is a preamble\r\n",
+        "--THIS_STRING_SEPARATES\r\n",
+        "Content-Type: text/plain\r\n",
+        "Content-Range: bytes 0-4/20\r\n",
+        "\r\n",
+        "ab\r\nc\r\n",
+        "--THIS_STRING_SEPARATES \r\n",
+        "Content-Type: text/plain\r\n",
+        "Content-Range: bytes 15-19/20\r\n",
+        "\r\n",
+        "--THI\r\n",
+        "--THIS_STRING_SEPARATES--\r\n",
+    );
+
+    #[test]
+    fn finds_byteranges_boundary() {
+        assert_eq!(
+            byteranges_boundary("multipart/byteranges; boundary=THIS_STRING_SEPARATES"),
+            Some("THIS_STRING_SEPARATES")
+        );
+        assert_eq!(
+            byteranges_boundary("Multipart/ByteRanges;charset=x; Boundary=\"a b\""),
+            Some("a b")
+        );
+        assert_eq!(byteranges_boundary("multipart/byteranges"), None);
+        assert_eq!(byteranges_boundary("text/html; boundary=x"), None);
+    }
+
+    #[test]
+    fn parses_byteranges() {
+        let parts = parse_byteranges(
+            BODY.as_bytes(),
+            "THIS_STRING_SEPARATES",
+            &Configuration::default(),
+        )
+        .expect("Could not parse parts");
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].range, ByteRange::new(0, 4));
+        assert_eq!(parts[0].complete_length, Some(20));
+        assert_eq!(parts[0].get_header("content-type"), Some("text/plain"));
+        // Line breaks and delimiters inside the data are part of it
+        assert_eq!(parts[0].get_data(), b"ab\r\nc");
+        assert_eq!(parts[1].range, ByteRange::new(15, 19));
+        assert_eq!(parts[1].get_data(), b"--THI");
+    }
+
+    #[test]
+    fn rejects_malformed_byteranges() {
+        let config = Configuration::default();
+        for body in [
+            BODY.replace("0-4/20", "0-3/20"),
+            BODY.replace("bytes 0-4/20", "bytes */20"),
+            BODY.replace("Content-Range", "X-Range"),
+            BODY.replace("SEPARATES--", "SEPARATED--"),
+            BODY[..BODY.len() - 10].to_string(),
+        ] {
+            assert!(
+                parse_byteranges(body.as_bytes(), "THIS_STRING_SEPARATES", &config).is_err(),
+                "{body:?} was accepted"
+            );
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::{fmt, str::FromStr};
+
+use crate::error::{WgetError, WgetResult};
+
+/// An inclusive range of byte offsets, as used by the `Range` and
+/// `Content-Range` headers
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ByteRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl ByteRange {
+    pub fn new(first: u64, last: u64) -> Self {
+        ByteRange { first, last }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.last - self.first + 1
+    }
+
+    /// A `ByteRange` is inclusive at both ends, so it always holds at least one byte
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+impl FromStr for ByteRange {
+    type Err = WgetError;
+
+    fn from_str(s: &str) -> WgetResult<Self> {
+        let invalid = || WgetError::ParsingError(format!("Invalid byte range {s}"));
+        let (first, last) = s.trim().split_once('-').ok_or_else(invalid)?;
+        let first = first.trim().parse::<u64>().map_err(|_| invalid())?;
+        let last = last.trim().parse::<u64>().map_err(|_| invalid())?;
+        if last < first {
+            return Err(invalid());
+        }
+        Ok(ByteRange { first, last })
+    }
+}
+
+impl fmt::Display for ByteRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.first, self.last)
+    }
+}
+
+/// One of the ranges asked for by a `Range` header, which unlike a
+/// `ByteRange` can leave an end open for the server to fill in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RangeSpec {
+    /// An inclusive range, e.g. `0-99`
+    Bounded(ByteRange),
+    /// Everything from an offset to the end of the resource, e.g. `500-`
+    From(u64),
+    /// This many bytes at the end of the resource, e.g. `-500`
+    Suffix(u64),
+}
+
+impl From<ByteRange> for RangeSpec {
+    fn from(range: ByteRange) -> Self {
+        RangeSpec::Bounded(range)
+    }
+}
+
+impl FromStr for RangeSpec {
+    type Err = WgetError;
+
+    fn from_str(s: &str) -> WgetResult<Self> {
+        let invalid = || WgetError::ParsingError(format!("Invalid byte range {s}"));
+        match s.trim().split_once('-').ok_or_else(invalid)? {
+            ("", length) => Ok(RangeSpec::Suffix(
+                length.parse::<u64>().map_err(|_| invalid())?,
+            )),
+            (first, "") => Ok(RangeSpec::From(
+                first.parse::<u64>().map_err(|_| invalid())?,
+            )),
+            _ => Ok(RangeSpec::Bounded(s.parse()?)),
+        }
+    }
+}
+
+impl fmt::Display for RangeSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RangeSpec::Bounded(range) => write!(f, "{range}"),
+            RangeSpec::From(first) => write!(f, "{first}-"),
+            RangeSpec::Suffix(length) => write!(f, "-{length}"),
+        }
+    }
+}
+
+/// The value of a `Content-Range` header, e.g. `bytes 0-99/1234`
+///
+/// Either side of the `/` may be unknown: `bytes */1234` is sent with a
+/// `416 Range Not Satisfiable` and `bytes 0-99/*` when the server doesn't know
+/// the full length.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ContentRange {
+    pub range: Option<ByteRange>,
+    pub complete_length: Option<u64>,
+}
+
+impl FromStr for ContentRange {
+    type Err = WgetError;
+
+    fn from_str(s: &str) -> WgetResult<Self> {
+        let invalid = || WgetError::ParsingError(format!("Invalid content range {s}"));
+        let rest = s.trim().strip_prefix("bytes ").ok_or_else(invalid)?;
+        let (range_str, length_str) = rest.split_once('/').ok_or_else(invalid)?;
+        let range = match range_str.trim() {
+            "*" => None,
+            range_str => Some(range_str.parse::<ByteRange>()?),
+        };
+        let complete_length = match length_str.trim() {
+            "*" => None,
+            length_str => Some(length_str.parse::<u64>().map_err(|_| invalid())?),
+        };
+        if range.is_none() && complete_length.is_none() {
+            return Err(invalid());
+        }
+        if let (Some(range), Some(length)) = (range, complete_length) {
+            if range.last >= length {
+                return Err(invalid());
+            }
+        }
+        Ok(ContentRange {
+            range,
+            complete_length,
+        })
+    }
+}
+
+impl fmt::Display for ContentRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "bytes ")?;
+        match self.range {
+            Some(range) => write!(f, "{range}")?,
+            None => write!(f, "*")?,
+        }
+        match self.complete_length {
+            Some(length) => write!(f, "/{length}"),
+            None => write!(f, "/*"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn byte_range_length_is_inclusive() {
+        assert_eq!(ByteRange::new(0, 0).len(), 1);
+        assert_eq!(ByteRange::new(0, 99).len(), 100);
+        assert_eq!(ByteRange::new(500, 599).len(), 100);
+    }
+
+    #[test]
+    fn parses_and_displays_byte_range() {
+        assert_eq!(
+            "0-99".parse::<ByteRange>().ok(),
+            Some(ByteRange::new(0, 99))
+        );
+        assert_eq!(ByteRange::new(500, 599).to_string(), "500-599");
+        assert!(matches!(
+            "99-0".parse::<ByteRange>(),
+            Err(WgetError::ParsingError(_))
+        ));
+        assert!(matches!(
+            "abc".parse::<ByteRange>(),
+            Err(WgetError::ParsingError(_))
+        ));
+    }
+
+    #[test]
+    fn parses_and_displays_range_specs() {
+        for (value, spec) in [
+            ("0-99", RangeSpec::Bounded(ByteRange::new(0, 99))),
+            ("500-", RangeSpec::From(500)),
+            ("-500", RangeSpec::Suffix(500)),
+        ] {
+            assert_eq!(value.parse::<RangeSpec>().ok(), Some(spec));
+            assert_eq!(spec.to_string(), value);
+        }
+        for value in ["-", "a-", "-b", "5", "9-0"] {
+            assert!(
+                matches!(value.parse::<RangeSpec>(), Err(WgetError::ParsingError(_))),
+                "{value} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn parses_content_range() {
+        assert_eq!(
+            "bytes 0-99/1234".parse::<ContentRange>().ok(),
+            Some(ContentRange {
+                range: Some(ByteRange::new(0, 99)),
+                complete_length: Some(1234)
+            })
+        );
+        assert_eq!(
+            "bytes */1234".parse::<ContentRange>().ok(),
+            Some(ContentRange {
+                range: None,
+                complete_length: Some(1234)
+            })
+        );
+        assert_eq!(
+            "bytes 0-99/*".parse::<ContentRange>().ok(),
+            Some(ContentRange {
+                range: Some(ByteRange::new(0, 99)),
+                complete_length: None
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_content_range() {
+        for value in ["0-99/1234", "bytes */*", "bytes 0-99", "bytes 0-1234/1234"] {
+            assert!(
+                matches!(
+                    value.parse::<ContentRange>(),
+                    Err(WgetError::ParsingError(_))
+                ),
+                "{value} should not parse"
+            );
+        }
+    }
+
+    #[test]
+    fn content_range_round_trips() {
+        for value in ["bytes 0-99/1234", "bytes */1234", "bytes 5-9/*"] {
+            let range = value.parse::<ContentRange>().expect("Could not parse");
+            assert_eq!(range.to_string(), value);
+        }
+    }
+}
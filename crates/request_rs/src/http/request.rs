@@ -1,11 +1,24 @@
-use std::fmt;
+use std::{
+    fmt,
+    io::{self, BufRead, Read},
+    str::FromStr,
+    time::Duration,
+};
 
 use unicase::UniCase;
 
-use super::HttpVersion;
-use crate::http::headers::Headers;
+use super::{
+    response::{read_fields, require_http_line},
+    ByteRange, HttpDate, HttpVersion, RangeSpec,
+};
+use crate::{
+    error::{WgetError, WgetResult},
+    http::headers::Headers,
+    protocol::Protocol,
+    Configuration,
+};
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum HttpMethod {
     Get,
     Head,
@@ -35,12 +48,55 @@ impl fmt::Display for HttpMethod {
     }
 }
 
+impl HttpMethod {
+    /// Whether sending the request twice has the same effect as sending it
+    /// once, so it can be sent again if the connection fails before the
+    /// response arrives
+    pub fn is_idempotent(&self) -> bool {
+        matches!(
+            self,
+            HttpMethod::Get
+                | HttpMethod::Head
+                | HttpMethod::Put
+                | HttpMethod::Delete
+                | HttpMethod::Options
+                | HttpMethod::Trace
+        )
+    }
+}
+
+impl FromStr for HttpMethod {
+    type Err = WgetError;
+
+    fn from_str(s: &str) -> WgetResult<Self> {
+        match s {
+            "GET" => Ok(HttpMethod::Get),
+            "HEAD" => Ok(HttpMethod::Head),
+            "POST" => Ok(HttpMethod::Post),
+            "PUT" => Ok(HttpMethod::Put),
+            "DELETE" => Ok(HttpMethod::Delete),
+            "CONNECT" => Ok(HttpMethod::Connect),
+            "OPTIONS" => Ok(HttpMethod::Options),
+            "TRACE" => Ok(HttpMethod::Trace),
+            "PATCH" => Ok(HttpMethod::Patch),
+            _ => Err(WgetError::ParsingError(format!("Unknown method {s}"))),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct HttpRequest {
     method: HttpMethod,
     path: String,
     version: HttpVersion,
     headers: Headers,
+    body: Vec<u8>,
+    /// How long to wait for `100 Continue` before sending the body anyway,
+    /// if the server was asked to say whether it wants the body at all
+    continue_wait: Option<Duration>,
+    /// The scheme of the URL the request is for. HTTP/1 doesn't send it, but
+    /// HTTP/2 does.
+    scheme: Protocol,
 }
 
 impl HttpRequest {
@@ -50,11 +106,99 @@ impl HttpRequest {
             path: path.into(),
             version,
             headers: Default::default(),
+            body: Vec::new(),
+            continue_wait: None,
+            scheme: Protocol::Http,
         }
     }
 
+    pub fn method(&self) -> HttpMethod {
+        self.method
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn headers(&self) -> impl Iterator<Item = (&str, &str)> {
+        (&self.headers).into_iter()
+    }
+
+    /// The request as it is sent, with the body after the headers
     pub fn serialize(&self) -> Vec<u8> {
-        self.to_string().into_bytes()
+        let mut bytes = self.to_string().into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// Sends `body` with the request, and its length in `Content-Length`
+    pub fn set_body<B: Into<Vec<u8>>>(&mut self, body: B) {
+        self.body = body.into();
+        self.add_header("Content-Length", self.body.len().to_string());
+    }
+
+    /// Asks the server to answer `100 Continue` before the body is sent, via
+    /// `Expect: 100-continue`, so a large body isn't sent just to be refused.
+    /// Servers that don't know about this never answer, so the body is sent
+    /// anyway after `wait`.
+    pub fn expect_continue(&mut self, wait: Duration) {
+        self.add_header("Expect", "100-continue");
+        self.continue_wait = Some(wait);
+    }
+
+    /// How long to wait for `100 Continue`, if the request asks for it
+    pub fn continue_wait(&self) -> Option<Duration> {
+        self.continue_wait.filter(|_| !self.body.is_empty())
+    }
+
+    pub fn scheme(&self) -> Protocol {
+        self.scheme
+    }
+
+    pub fn set_scheme(&mut self, scheme: Protocol) {
+        self.scheme = scheme;
+    }
+
+    /// Reads a request, the server's side of `serialize`, with the same limits
+    /// on its head as for responses. Only bodies with a `Content-Length` are
+    /// read, as that is all we send.
+    pub fn receive_request<S>(socket: &mut S, config: &Configuration) -> WgetResult<Self>
+    where
+        S: BufRead,
+    {
+        let line = require_http_line(socket, config.header_limits.max_line_length)?;
+        crate::trace!(config, "Read request line: {}", &line);
+        let mut line_split = line.split(' ');
+        let (Some(method), Some(path), Some(version), None) = (
+            line_split.next(),
+            line_split.next(),
+            line_split.next(),
+            line_split.next(),
+        ) else {
+            return Err(WgetError::ParsingError(format!(
+                "Invalid request line {line}"
+            )));
+        };
+        let mut request = HttpRequest::new(method.parse()?, path, HttpVersion::try_from(version)?);
+        request.headers = read_fields(socket, config)?;
+        if let Some(len_str) = request.get_header("Content-Length") {
+            let length = len_str.parse::<u64>().map_err(|_| {
+                WgetError::ParsingError(format!("Invalid content length {len_str}"))
+            })?;
+            io::Read::take(&mut *socket, length).read_to_end(&mut request.body)?;
+            if (request.body.len() as u64) < length {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed in the middle of a request body",
+                )
+                .into());
+            }
+        }
+        Ok(request)
     }
 
     pub fn add_header<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
@@ -66,8 +210,7 @@ impl HttpRequest {
         K: ?Sized,
         K: AsRef<str>,
     {
-        self.headers
-            .get(&UniCase::new(key.as_ref().to_string()))
+        self.headers.get(&UniCase::new(key.as_ref().to_string()))
     }
 
     pub fn delete_header<K>(&mut self, key: &K) -> Option<String>
@@ -77,8 +220,73 @@ impl HttpRequest {
     {
         self.headers.remove(&UniCase::new(key.as_ref().to_string()))
     }
+
+    /// Asks for only `range` of the resource, via the `Range` header
+    pub fn set_range(&mut self, range: ByteRange) {
+        self.set_ranges(&[range.into()]);
+    }
+
+    /// Asks for only `ranges` of the resource, via the `Range` header. A
+    /// server that sends more than one back does so as a
+    /// `multipart/byteranges` body, see `HttpResponse::byte_ranges`.
+    pub fn set_ranges(&mut self, ranges: &[RangeSpec]) {
+        let ranges = ranges
+            .iter()
+            .map(RangeSpec::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+        self.add_header("Range", format!("bytes={ranges}"));
+    }
+
+    /// Asks for the resource only if it has changed since `date`, via the
+    /// `If-Modified-Since` header
+    pub fn set_if_modified_since(&mut self, date: HttpDate) {
+        self.add_header("If-Modified-Since", date.to_string());
+    }
+
+    /// Asks for the resource only if its entity tag is no longer `etag`, via
+    /// the `If-None-Match` header. `etag` must include its quotes.
+    pub fn set_if_none_match(&mut self, etag: &str) {
+        self.add_header("If-None-Match", etag);
+    }
+
+    /// Says that trailer fields are understood, via `TE: trailers`. Since `TE`
+    /// is only for the next hop, it is also listed in any `Connection` header.
+    pub fn accept_trailers(&mut self) {
+        self.add_header("TE", "trailers");
+        if let Some(connection) = self.get_header("Connection") {
+            let connection = format!("{connection}, TE");
+            self.add_header("Connection", connection);
+        }
+    }
+
+    /// Adds the headers for all of `conditions`
+    pub fn set_conditions(&mut self, conditions: &Conditions) {
+        if let Some(date) = conditions.if_modified_since {
+            self.set_if_modified_since(date);
+        }
+        if let Some(etag) = &conditions.if_none_match {
+            self.set_if_none_match(etag);
+        }
+    }
 }
 
+/// What a conditional request is conditional on. If the server's copy of the
+/// resource still matches, it answers `304 Not Modified` without a body.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Conditions {
+    pub if_modified_since: Option<HttpDate>,
+    pub if_none_match: Option<String>,
+}
+
+impl Conditions {
+    /// Whether there are no conditions, so the request is unconditional
+    pub fn is_empty(&self) -> bool {
+        self.if_modified_since.is_none() && self.if_none_match.is_none()
+    }
+}
+
+/// Shows the request line and headers, leaving out the body
 impl fmt::Display for HttpRequest {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{} {} {}\r\n", self.method, self.path, self.version)?;
@@ -91,8 +299,22 @@ impl fmt::Display for HttpRequest {
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
 
+    const METHODS: &[HttpMethod] = &[
+        HttpMethod::Get,
+        HttpMethod::Head,
+        HttpMethod::Post,
+        HttpMethod::Put,
+        HttpMethod::Delete,
+        HttpMethod::Connect,
+        HttpMethod::Options,
+        HttpMethod::Trace,
+        HttpMethod::Patch,
+    ];
+
     #[test]
     fn serializes_http_method() {
         assert_eq!(HttpMethod::Get.to_string(), "GET");
@@ -175,6 +397,150 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sets_range_header() {
+        let mut req = HttpRequest::new(HttpMethod::Get, "/", HttpVersion::Version1_1);
+        req.set_range(ByteRange::new(0, 99));
+        assert_eq!(req.get_header("Range"), Some("bytes=0-99"));
+    }
+
+    #[test]
+    fn sets_if_modified_since_header() {
+        let mut req = HttpRequest::new(HttpMethod::Get, "/", HttpVersion::Version1_1);
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT"
+            .parse()
+            .expect("Could not parse date");
+        req.set_if_modified_since(date);
+        assert_eq!(
+            req.get_header("If-Modified-Since"),
+            Some("Sun, 06 Nov 1994 08:49:37 GMT")
+        );
+    }
+
+    #[test]
+    fn sets_conditions() {
+        let mut req = HttpRequest::new(HttpMethod::Get, "/", HttpVersion::Version1_1);
+        req.set_conditions(&Conditions::default());
+        assert_eq!(req.get_header("If-Modified-Since"), None);
+        assert_eq!(req.get_header("If-None-Match"), None);
+
+        req.set_conditions(&Conditions {
+            if_modified_since: None,
+            if_none_match: Some("\"abc\"".to_string()),
+        });
+        assert_eq!(req.get_header("If-Modified-Since"), None);
+        assert_eq!(req.get_header("If-None-Match"), Some("\"abc\""));
+    }
+
+    #[test]
+    fn sets_ranges() {
+        let mut req = HttpRequest::new(HttpMethod::Get, "/", HttpVersion::Version1_1);
+        req.set_range(ByteRange::new(0, 99));
+        assert_eq!(req.get_header("Range"), Some("bytes=0-99"));
+        req.set_ranges(&[
+            ByteRange::new(0, 99).into(),
+            RangeSpec::From(500),
+            RangeSpec::Suffix(10),
+        ]);
+        assert_eq!(req.get_header("Range"), Some("bytes=0-99,500-,-10"));
+    }
+
+    #[test]
+    fn sends_body_after_headers() {
+        let mut req = HttpRequest::new(HttpMethod::Post, "/form", HttpVersion::Version1_1);
+        req.set_body("a=1&b=2");
+        assert_eq!(req.get_header("Content-Length"), Some("7"));
+        assert_eq!(req.continue_wait(), None);
+        req.expect_continue(Duration::from_secs(1));
+        assert_eq!(req.get_header("Expect"), Some("100-continue"));
+        assert_eq!(req.continue_wait(), Some(Duration::from_secs(1)));
+        assert_eq!(
+            req.serialize(),
+            b"POST /form HTTP/1.1\r\nContent-Length: 7\r\nExpect: 100-continue\r\n\r\na=1&b=2"
+        );
+        assert!(!req.to_string().contains("a=1"));
+    }
+
+    #[test]
+    fn accepts_trailers() {
+        let mut req = HttpRequest::new(HttpMethod::Get, "/", HttpVersion::Version1_1);
+        req.add_header("Connection", "Keep-Alive");
+        req.accept_trailers();
+        assert_eq!(req.get_header("TE"), Some("trailers"));
+        assert_eq!(req.get_header("Connection"), Some("Keep-Alive, TE"));
+    }
+
+    #[test]
+    fn parses_http_method() {
+        for method in METHODS {
+            assert_eq!(method.to_string().parse::<HttpMethod>().ok(), Some(*method));
+        }
+        assert!(matches!(
+            "get".parse::<HttpMethod>(),
+            Err(WgetError::ParsingError(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_request_lines() {
+        let config = Configuration::default();
+        for request in [
+            "GET /\r\n\r\n",
+            "GET / HTTP/1.1 extra\r\n\r\n",
+            "get / HTTP/1.1\r\n\r\n",
+        ] {
+            assert!(
+                matches!(
+                    HttpRequest::receive_request(&mut request.as_bytes(), &config),
+                    Err(WgetError::ParsingError(_))
+                ),
+                "{request:?} was accepted"
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics_on_arbitrary_bytes(data in prop::collection::vec(any::<u8>(), 0..512)) {
+            let _ = HttpRequest::receive_request(&mut &data[..], &Configuration::default());
+        }
+
+        #[test]
+        fn reads_what_it_serializes(
+            method in prop::sample::select(METHODS),
+            path in "/[!-~]{0,30}",
+            http1_0 in any::<bool>(),
+            fields in prop::collection::vec(
+                ("[A-Za-z][A-Za-z0-9-]{0,15}", "([!-~]([ -~]{0,30}[!-~])?)?")
+                    .prop_filter("The body sets the length", |(key, _)| {
+                        !key.eq_ignore_ascii_case("Content-Length")
+                    }),
+                0..10,
+            ),
+            body in prop::collection::vec(any::<u8>(), 0..256),
+        ) {
+            let version = if http1_0 {
+                HttpVersion::Version1_0
+            } else {
+                HttpVersion::Version1_1
+            };
+            let mut request = HttpRequest::new(method, path, version);
+            for (key, value) in fields {
+                request.add_header(key, value);
+            }
+            request.set_body(body);
+
+            let bytes = request.serialize();
+            let read = HttpRequest::receive_request(&mut &bytes[..], &Configuration::default())
+                .expect("Could not read request");
+            prop_assert_eq!(read.method(), request.method());
+            prop_assert_eq!(read.path(), request.path());
+            prop_assert_eq!(&read.version, &request.version);
+            prop_assert_eq!(read.headers().collect::<Vec<_>>(), request.headers().collect::<Vec<_>>());
+            prop_assert_eq!(read.body(), request.body());
+        }
+    }
+
     #[test]
     fn headers_are_case_insensitive() {
         let mut req = HttpRequest::new(HttpMethod::Get, "/index.html", HttpVersion::Version1_1);
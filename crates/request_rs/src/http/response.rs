@@ -12,7 +12,10 @@ use crate::{
     Configuration,
 };
 
-use super::HttpVersion;
+use super::{
+    multipart::{self, BodyPart},
+    ContentDisposition, ContentRange, HttpDate, HttpVersion,
+};
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum HttpStatusFamily {
@@ -28,6 +31,7 @@ pub enum HttpStatusFamily {
 pub enum HttpStatusCode {
     Continue = 100,
     SwitchingProtocols = 101,
+    EarlyHints = 103,
     Ok = 200,
     Created = 201,
     Accepted = 202,
@@ -79,12 +83,75 @@ pub enum HttpStatusCode {
     LoopDetected = 508,
     NotExtended = 510,
     NetworkAuthenticationRequired = 511,
+    /// A valid code without a variant of its own, which is treated like the
+    /// others in its family
+    Unknown(u16),
 }
 
 impl HttpStatusCode {
+    /// The number of the status, e.g. 404
+    pub fn code(&self) -> u16 {
+        match self {
+            HttpStatusCode::Continue => 100,
+            HttpStatusCode::SwitchingProtocols => 101,
+            HttpStatusCode::EarlyHints => 103,
+            HttpStatusCode::Ok => 200,
+            HttpStatusCode::Created => 201,
+            HttpStatusCode::Accepted => 202,
+            HttpStatusCode::NonAuthoritativeInformation => 203,
+            HttpStatusCode::NoContent => 204,
+            HttpStatusCode::ResetContent => 205,
+            HttpStatusCode::PartialContent => 206,
+            HttpStatusCode::MultipleChoices => 300,
+            HttpStatusCode::MovedPermanently => 301,
+            HttpStatusCode::Found => 302,
+            HttpStatusCode::SeeOther => 303,
+            HttpStatusCode::NotModified => 304,
+            HttpStatusCode::TemporaryRedirect => 307,
+            HttpStatusCode::PermanentRedirect => 308,
+            HttpStatusCode::BadRequest => 400,
+            HttpStatusCode::Unauthorized => 401,
+            HttpStatusCode::PaymentRequired => 402,
+            HttpStatusCode::Forbidden => 403,
+            HttpStatusCode::NotFound => 404,
+            HttpStatusCode::MethodNotAllowed => 405,
+            HttpStatusCode::NotAcceptable => 406,
+            HttpStatusCode::ProxyAuthenticationRequired => 407,
+            HttpStatusCode::RequestTimeout => 408,
+            HttpStatusCode::Conflict => 409,
+            HttpStatusCode::Gone => 410,
+            HttpStatusCode::LengthRequired => 411,
+            HttpStatusCode::PreconditionFailed => 412,
+            HttpStatusCode::PayloadTooLarge => 413,
+            HttpStatusCode::UriTooLong => 414,
+            HttpStatusCode::UnsupportedMediaType => 415,
+            HttpStatusCode::RangeNotSatisfiable => 416,
+            HttpStatusCode::ExpectationFailed => 417,
+            HttpStatusCode::ImATeapot => 418,
+            HttpStatusCode::UnprocessableEntity => 422,
+            HttpStatusCode::TooEarly => 425,
+            HttpStatusCode::UpgradeRequired => 426,
+            HttpStatusCode::PreconditionRequired => 428,
+            HttpStatusCode::TooManyRequests => 429,
+            HttpStatusCode::RequestHeaderFieldsTooLarge => 431,
+            HttpStatusCode::UnavailableForLegalReasons => 451,
+            HttpStatusCode::InternalServerError => 500,
+            HttpStatusCode::NotImplemented => 501,
+            HttpStatusCode::BadGateway => 502,
+            HttpStatusCode::ServiceUnavailable => 503,
+            HttpStatusCode::GatewayTimeout => 504,
+            HttpStatusCode::HttpVersionNotSupported => 505,
+            HttpStatusCode::VariantAlsoNegotiates => 506,
+            HttpStatusCode::InsufficientStorage => 507,
+            HttpStatusCode::LoopDetected => 508,
+            HttpStatusCode::NotExtended => 510,
+            HttpStatusCode::NetworkAuthenticationRequired => 511,
+            HttpStatusCode::Unknown(code) => *code,
+        }
+    }
+
     pub fn family(&self) -> HttpStatusFamily {
-        let code = *self as u16;
-        match code {
+        match self.code() {
             100..=199 => HttpStatusFamily::Informational,
             200..=299 => HttpStatusFamily::Successful,
             300..=399 => HttpStatusFamily::Redirection,
@@ -93,12 +160,80 @@ impl HttpStatusCode {
             _ => unreachable!("HttpStatusCode was outside any family!"),
         }
     }
+
+    /// The standard reason phrase for the status, for protocols like HTTP/2
+    /// that don't send one
+    pub fn reason_phrase(&self) -> &'static str {
+        match self {
+            HttpStatusCode::Continue => "Continue",
+            HttpStatusCode::SwitchingProtocols => "Switching Protocols",
+            HttpStatusCode::EarlyHints => "Early Hints",
+            HttpStatusCode::Ok => "OK",
+            HttpStatusCode::Created => "Created",
+            HttpStatusCode::Accepted => "Accepted",
+            HttpStatusCode::NonAuthoritativeInformation => "Non-Authoritative Information",
+            HttpStatusCode::NoContent => "No Content",
+            HttpStatusCode::ResetContent => "Reset Content",
+            HttpStatusCode::PartialContent => "Partial Content",
+            HttpStatusCode::MultipleChoices => "Multiple Choices",
+            HttpStatusCode::MovedPermanently => "Moved Permanently",
+            HttpStatusCode::Found => "Found",
+            HttpStatusCode::SeeOther => "See Other",
+            HttpStatusCode::NotModified => "Not Modified",
+            HttpStatusCode::TemporaryRedirect => "Temporary Redirect",
+            HttpStatusCode::PermanentRedirect => "Permanent Redirect",
+            HttpStatusCode::BadRequest => "Bad Request",
+            HttpStatusCode::Unauthorized => "Unauthorized",
+            HttpStatusCode::PaymentRequired => "Payment Required",
+            HttpStatusCode::Forbidden => "Forbidden",
+            HttpStatusCode::NotFound => "Not Found",
+            HttpStatusCode::MethodNotAllowed => "Method Not Allowed",
+            HttpStatusCode::NotAcceptable => "Not Acceptable",
+            HttpStatusCode::ProxyAuthenticationRequired => "Proxy Authentication Required",
+            HttpStatusCode::RequestTimeout => "Request Timeout",
+            HttpStatusCode::Conflict => "Conflict",
+            HttpStatusCode::Gone => "Gone",
+            HttpStatusCode::LengthRequired => "Length Required",
+            HttpStatusCode::PreconditionFailed => "Precondition Failed",
+            HttpStatusCode::PayloadTooLarge => "Content Too Large",
+            HttpStatusCode::UriTooLong => "URI Too Long",
+            HttpStatusCode::UnsupportedMediaType => "Unsupported Media Type",
+            HttpStatusCode::RangeNotSatisfiable => "Range Not Satisfiable",
+            HttpStatusCode::ExpectationFailed => "Expectation Failed",
+            HttpStatusCode::ImATeapot => "I'm a teapot",
+            HttpStatusCode::UnprocessableEntity => "Unprocessable Content",
+            HttpStatusCode::TooEarly => "Too Early",
+            HttpStatusCode::UpgradeRequired => "Upgrade Required",
+            HttpStatusCode::PreconditionRequired => "Precondition Required",
+            HttpStatusCode::TooManyRequests => "Too Many Requests",
+            HttpStatusCode::RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            HttpStatusCode::UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+            HttpStatusCode::InternalServerError => "Internal Server Error",
+            HttpStatusCode::NotImplemented => "Not Implemented",
+            HttpStatusCode::BadGateway => "Bad Gateway",
+            HttpStatusCode::ServiceUnavailable => "Service Unavailable",
+            HttpStatusCode::GatewayTimeout => "Gateway Timeout",
+            HttpStatusCode::HttpVersionNotSupported => "HTTP Version Not Supported",
+            HttpStatusCode::VariantAlsoNegotiates => "Variant Also Negotiates",
+            HttpStatusCode::InsufficientStorage => "Insufficient Storage",
+            HttpStatusCode::LoopDetected => "Loop Detected",
+            HttpStatusCode::NotExtended => "Not Extended",
+            HttpStatusCode::NetworkAuthenticationRequired => "Network Authentication Required",
+            HttpStatusCode::Unknown(_) => "",
+        }
+    }
 }
 
 impl FromStr for HttpStatusCode {
     type Err = WgetError;
 
     fn from_str(s: &str) -> WgetResult<Self> {
+        // Exactly three digits, where `u16::from_str` would also allow a sign
+        if s.len() != 3 || !s.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(WgetError::ParsingError(format!(
+                "Status Code not a number: {s}"
+            )));
+        }
         let status_code = s
             .parse::<u16>()
             .map_err(|_| WgetError::ParsingError(format!("Status Code not a number: {s}")))?;
@@ -114,6 +249,7 @@ impl TryFrom<u16> for HttpStatusCode {
         match value {
             100 => Ok(HttpStatusCode::Continue),
             101 => Ok(HttpStatusCode::SwitchingProtocols),
+            103 => Ok(HttpStatusCode::EarlyHints),
             200 => Ok(HttpStatusCode::Ok),
             201 => Ok(HttpStatusCode::Created),
             202 => Ok(HttpStatusCode::Accepted),
@@ -165,6 +301,7 @@ impl TryFrom<u16> for HttpStatusCode {
             508 => Ok(HttpStatusCode::LoopDetected),
             510 => Ok(HttpStatusCode::NotExtended),
             511 => Ok(HttpStatusCode::NetworkAuthenticationRequired),
+            _ if (100..=599).contains(&value) => Ok(HttpStatusCode::Unknown(value)),
             _ => Err(WgetError::InvalidStatusCode(value)),
         }
     }
@@ -172,23 +309,88 @@ impl TryFrom<u16> for HttpStatusCode {
 
 impl fmt::Display for HttpStatusCode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", *self as u16)
+        write!(f, "{}", self.code())
+    }
+}
+
+/// Limits on what a server may send in the head of a response, so a broken
+/// or hostile one can't make us buffer without end
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HeaderLimits {
+    /// The longest a single line may be, not counting its ending
+    pub max_line_length: usize,
+    /// The most header fields a response may have, and separately the most
+    /// trailer fields
+    pub max_fields: usize,
+    /// The most bytes all header lines together may take
+    pub max_size: usize,
+}
+
+impl Default for HeaderLimits {
+    fn default() -> Self {
+        HeaderLimits {
+            max_line_length: 16 * 1024,
+            max_fields: 100,
+            max_size: 64 * 1024,
+        }
     }
 }
 
-fn read_http_line<S>(reader: &mut S) -> io::Result<String>
+/// Whitespace allowed around field values
+const OWS: [char; 2] = [' ', '\t'];
+
+/// The most memory reserved up front for a body, whatever its
+/// `Content-Length` claims
+const MAX_BODY_PREALLOCATION: usize = 1024 * 1024;
+
+/// Reads a line ending in CRLF or a bare LF and returns it without the ending,
+/// or `None` if the connection was closed before anything was read
+///
+/// Bytes that are not UTF-8 are replaced rather than rejected, as headers are
+/// only meant to be ASCII anyway.
+fn read_http_line<S>(reader: &mut S, max_length: usize) -> WgetResult<Option<String>>
 where
     S: BufRead,
 {
-    let mut line = String::new();
-    reader.read_line(&mut line)?;
-    if let Some('\n') = line.chars().last() {
-        line.pop();
+    let mut line = Vec::new();
+    // Room for the line ending on top of the longest allowed line
+    io::Read::take(reader, max_length as u64 + 2).read_until(b'\n', &mut line)?;
+    if line.is_empty() {
+        return Ok(None);
     }
-    if let Some('\r') = line.chars().last() {
+    if line.last() == Some(&b'\n') {
         line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+    } else if line.len() <= max_length {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Connection closed in the middle of a line",
+        )
+        .into());
+    }
+    if line.len() > max_length {
+        return Err(WgetError::ParsingError(format!(
+            "Line longer than {max_length} bytes"
+        )));
     }
-    Ok(line)
+    Ok(Some(String::from_utf8_lossy(&line).into_owned()))
+}
+
+/// Reads a line like `read_http_line`, where the connection closing instead
+/// is an error
+pub(super) fn require_http_line<S>(reader: &mut S, max_length: usize) -> WgetResult<String>
+where
+    S: BufRead,
+{
+    read_http_line(reader, max_length)?.ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "Connection closed in the middle of a message",
+        )
+        .into()
+    })
 }
 
 #[derive(Debug)]
@@ -197,6 +399,8 @@ pub struct HttpResponse {
     pub status_code: HttpStatusCode,
     pub status_message: String,
     headers: Headers,
+    /// Fields sent after the body, by chunked responses
+    trailers: Headers,
     data: Vec<u8>,
 }
 
@@ -207,6 +411,7 @@ impl HttpResponse {
             status_code,
             status_message,
             headers: Default::default(),
+            trailers: Default::default(),
             data: Vec::new(),
         }
     }
@@ -215,9 +420,26 @@ impl HttpResponse {
         self.status_code.family()
     }
 
+    /// Whether the server closes the connection after this response, because
+    /// it says so with `Connection: close` or speaks HTTP/1.0 without
+    /// `Connection: keep-alive`
+    pub fn closes_connection(&self) -> bool {
+        let has_option = |option: &str| {
+            self.get_header("Connection").is_some_and(|value| {
+                value
+                    .split(',')
+                    .any(|token| token.trim().eq_ignore_ascii_case(option))
+            })
+        };
+        match self.version {
+            HttpVersion::Version0_9 => true,
+            HttpVersion::Version1_0 => !has_option("keep-alive"),
+            _ => has_option("close"),
+        }
+    }
+
     pub fn add_header<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
-        self.headers
-            .add(UniCase::new(key.into()), value.into());
+        self.headers.add(UniCase::new(key.into()), value.into());
     }
 
     pub fn set_data<D: Into<Vec<u8>>>(&mut self, data: D) {
@@ -240,121 +462,492 @@ impl HttpResponse {
         self.headers.remove(&UniCase::new(key.as_ref().to_string()))
     }
 
+    pub fn add_trailer<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.trailers.add(UniCase::new(key.into()), value.into());
+    }
+
+    /// A field from the trailer section, which chunked responses can send
+    /// after the body for things only known once it has been sent
+    pub fn get_trailer<K>(&self, key: &K) -> Option<&str>
+    where
+        K: ?Sized,
+        K: AsRef<str>,
+    {
+        self.trailers.get(key)
+    }
+
     pub fn get_data(&self) -> &[u8] {
         &self.data
     }
 
+    /// The `Last-Modified` header, if there is one and it is a valid date
+    pub fn last_modified(&self) -> Option<HttpDate> {
+        self.get_header("Last-Modified")?.parse().ok()
+    }
+
+    /// The `Content-Disposition` header, if there is one and it can be parsed
+    pub fn content_disposition(&self) -> Option<ContentDisposition> {
+        self.get_header("Content-Disposition")?.parse().ok()
+    }
+
+    /// The `Content-Length` header, if there is one and it is a valid length
+    pub fn content_length(&self) -> Option<u64> {
+        self.get_header("Content-Length")?.trim().parse().ok()
+    }
+
+    /// The `Content-Range` header, if there is one and it can be parsed
+    pub fn content_range(&self) -> Option<ContentRange> {
+        self.get_header("Content-Range")?.parse().ok()
+    }
+
+    /// The ranges of the resource in a `206 Partial Content` response, which
+    /// is either a single range described by `Content-Range` or a
+    /// `multipart/byteranges` body with several
+    pub fn byte_ranges(&self, config: &Configuration) -> WgetResult<Vec<BodyPart>> {
+        if self.status_code != HttpStatusCode::PartialContent {
+            return Err(WgetError::ProtocolError(format!(
+                "Expected {}, got {} {}",
+                HttpStatusCode::PartialContent,
+                self.status_code,
+                self.status_message
+            )));
+        }
+        let content_type = self.get_header("Content-Type");
+        if let Some(boundary) = content_type.and_then(multipart::byteranges_boundary) {
+            return multipart::parse_byteranges(&self.data, boundary, config);
+        }
+        let content_range = self.get_header("Content-Range").ok_or_else(|| {
+            WgetError::ProtocolError("No Content-Range in partial response".to_string())
+        })?;
+        let headers = multipart::part_headers(content_range, content_type);
+        let part = BodyPart::new(content_range.parse()?, headers, self.data.clone())?;
+        Ok(vec![part])
+    }
+
+    /// The response as it was sent, where the body is kept as it is rather
+    /// than made into text like by `to_string`
     pub fn serialize(&self) -> Vec<u8> {
-        self.to_string().into_bytes()
+        let mut bytes = self.head().into_bytes();
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// The status line and headers, with the empty line that ends them. This
+    /// is what `Display` shows before the body.
+    pub fn head(&self) -> String {
+        let mut head = format!(
+            "{} {} {}\r\n",
+            self.version, self.status_code, self.status_message
+        );
+        for (key, value) in &self.headers {
+            head += &format!("{key}: {value}\r\n");
+        }
+        head + "\r\n"
+    }
+
+    /// Reads a whole response, skipping any interim `1xx` responses before it
+    ///
+    /// A `101 Switching Protocols` response is returned as it is, since what
+    /// follows it is no longer HTTP.
+    pub fn receive_response<S>(socket: &mut S, config: &Configuration) -> WgetResult<Self>
+    where
+        S: BufRead,
+    {
+        let mut response = Self::receive_final_head(socket, config, &mut |_| {})?;
+        if response.status_code != HttpStatusCode::SwitchingProtocols {
+            response.receive_body(socket, config)?;
+        }
+        Ok(response)
+    }
+
+    /// Reads response heads until the final one, passing every interim `1xx`
+    /// response to `interim`, like `103 Early Hints` with links the final
+    /// response will need, or the `100 Continue` a request might wait for
+    pub fn receive_final_head<S>(
+        socket: &mut S,
+        config: &Configuration,
+        interim: &mut dyn FnMut(&HttpResponse),
+    ) -> WgetResult<Self>
+    where
+        S: BufRead,
+    {
+        loop {
+            let response = Self::receive_response_head(socket, config)?;
+            if response.status_family() != HttpStatusFamily::Informational
+                || response.status_code == HttpStatusCode::SwitchingProtocols
+            {
+                return Ok(response);
+            }
+            crate::debug!(
+                config,
+                "Received interim response {} {}",
+                response.status_code,
+                response.status_message
+            );
+            interim(&response);
+        }
     }
 
-    pub fn receive_response<S>(mut socket: &mut S, config: &Configuration) -> WgetResult<Self>
+    /// Reads the head of the first response on a connection like
+    /// `receive_final_head`. With `Configuration::accept_http09`, a response
+    /// that doesn't start with a status line is taken to be HTTP/0.9, which
+    /// has no head. Only the first response can be, since an HTTP/0.9 server
+    /// closes the connection after it.
+    pub fn receive_first_head<S>(
+        socket: &mut S,
+        config: &Configuration,
+        interim: &mut dyn FnMut(&HttpResponse),
+    ) -> WgetResult<Self>
     where
         S: BufRead,
     {
+        if config.accept_http09 && !starts_with_status_line(socket)? {
+            crate::debug!(config, "No status line, taking the response to be HTTP/0.9");
+            return Ok(HttpResponse::new(
+                HttpVersion::Version0_9,
+                HttpStatusCode::Ok,
+                String::new(),
+            ));
+        }
+        Self::receive_final_head(socket, config, interim)
+    }
+
+    /// Reads the status line and headers of a response, leaving the body unread
+    ///
+    /// This is all there is to read for responses to `HEAD` requests.
+    pub fn receive_response_head<S>(socket: &mut S, config: &Configuration) -> WgetResult<Self>
+    where
+        S: BufRead,
+    {
+        let max_length = config.header_limits.max_line_length;
         let mut response = {
-            let line = read_http_line(&mut socket)?;
-            if config.debug > 1 {
-                println!("Read status line: {}", &line);
+            let mut line = read_http_line(socket, max_length)?;
+            // An empty line left over from the previous message is ignored
+            if line.as_deref() == Some("") {
+                line = read_http_line(socket, max_length)?;
             }
-            let mut line_split = line.split(' ');
+            let line = line.ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "Connection closed before a response was received",
+                )
+            })?;
+            crate::trace!(config, "Read status line: {}", &line);
 
-            let version_str = line_split
-                .next()
-                .ok_or_else(|| WgetError::ParsingError("No Version in response".to_string()))?;
+            let (version_str, rest) = line
+                .split_once(' ')
+                .ok_or_else(|| WgetError::ParsingError(format!("Invalid status line {line}")))?;
             let version = HttpVersion::try_from(version_str)?;
 
-            let status_code_str = line_split
-                .next()
-                .ok_or_else(|| WgetError::ParsingError("No status code".to_string()))?;
+            // The reason phrase may be empty, and some servers leave out the
+            // space before it too
+            let rest = rest.trim_start_matches(' ');
+            let (status_code_str, status_message) = rest.split_once(' ').unwrap_or((rest, ""));
             let status_code = status_code_str.parse::<HttpStatusCode>()?;
 
-            let status_message = line_split.collect::<Vec<_>>().join(" ");
-
-            HttpResponse::new(version, status_code, status_message)
+            HttpResponse::new(version, status_code, status_message.to_string())
         };
+        response.headers = read_fields(socket, config)?;
+        Ok(response)
+    }
 
-        loop {
-            let line = read_http_line(&mut socket)?;
-            if line.is_empty() {
-                if config.debug > 1 {
-                    println!("Finished reading headers");
+    /// Reads the body of a response whose head was read by `receive_response_head`
+    pub fn receive_body<S>(&mut self, socket: &mut S, config: &Configuration) -> WgetResult<()>
+    where
+        S: BufRead,
+    {
+        self.receive_body_with_progress(socket, config, &mut |_, _| {})
+    }
+
+    /// Reads the body of a response like `receive_body`, calling `progress` as
+    /// data arrives with the number of body bytes received so far and the
+    /// length of the whole body, if the server sent it
+    pub fn receive_body_with_progress<S>(
+        &mut self,
+        socket: &mut S,
+        config: &Configuration,
+        progress: &mut dyn FnMut(u64, Option<u64>),
+    ) -> WgetResult<()>
+    where
+        S: BufRead,
+    {
+        match self.body_framing()? {
+            BodyFraming::Length(length) => {
+                crate::trace!(config, "receiving normal file of length {length}");
+                let mut buf = Vec::with_capacity(length.min(MAX_BODY_PREALLOCATION));
+                read_body_data(socket, &mut buf, length, Some(length as u64), progress)?;
+                self.set_data(buf);
+            }
+            BodyFraming::Chunked => {
+                let max_length = config.header_limits.max_line_length;
+                let mut data: Vec<u8> = Vec::new();
+                loop {
+                    let line = require_http_line(socket, max_length)?;
+                    // Chunk extensions are allowed after the length, but mean nothing to us
+                    let len_str = line.split(';').next().unwrap_or_default().trim_matches(OWS);
+                    crate::trace!(config, "receiving chunk of length 0x{len_str}");
+                    let length = parse_chunk_length(len_str)?;
+                    if length == 0 {
+                        break;
+                    }
+                    read_body_data(socket, &mut data, length, None, progress)?;
+                    if !require_http_line(socket, max_length)?.is_empty() {
+                        return Err(WgetError::ParsingError("Invalid chunk ending".to_string()));
+                    }
                 }
-                break;
+                crate::trace!(config, "All chunks received");
+                self.set_data(data);
+                // The trailer section has to be read even if empty, or its final
+                // line would be taken as the start of the next response
+                self.trailers = read_fields(socket, config)?;
             }
-            if config.debug > 1 {
-                println!("Read header line: {}", &line);
+            BodyFraming::UntilClose => {
+                crate::trace!(config, "receiving file until the connection closes");
+                let mut data = Vec::new();
+                read_body_to_end(socket, &mut data, progress)?;
+                self.set_data(data);
             }
-            let mut line_split = line.split(": ");
-            let key = line_split
-                .next()
-                .ok_or_else(|| WgetError::ParsingError("No header key".to_string()))?;
-            let value = line_split
-                .next()
-                .ok_or_else(|| WgetError::ParsingError("No header value".to_string()))?;
-            response.add_header(key, value);
-        }
-
-        if let Some(len_str) = response.get_header("Content-Length") {
-            let length = len_str.parse::<usize>().map_err(|_| {
-                WgetError::ParsingError(format!("Invalid content length {len_str}"))
-            })?;
-            if config.debug > 1 {
-                println!("receiving normal file of length {length}");
+            BodyFraming::None => {}
+        }
+
+        Ok(())
+    }
+
+    /// Whether the body ends when the server closes the connection, because
+    /// neither its length nor a transfer coding was given
+    pub(crate) fn is_close_delimited(&self) -> bool {
+        matches!(self.body_framing(), Ok(BodyFraming::UntilClose))
+    }
+
+    /// Works out from the headers how the body is delimited, refusing
+    /// combinations a request smuggler could use to make us disagree with a
+    /// proxy about where the response ends
+    fn body_framing(&self) -> WgetResult<BodyFraming> {
+        match (
+            self.get_header("Transfer-Encoding"),
+            self.get_header("Content-Length"),
+        ) {
+            (Some(_), Some(_)) => Err(WgetError::ParsingError(
+                "Response has both Transfer-Encoding and Content-Length".to_string(),
+            )),
+            (Some(coding), None) if coding.trim_matches(OWS).eq_ignore_ascii_case("chunked") => {
+                Ok(BodyFraming::Chunked)
             }
-            let mut buf = vec![0; length];
-            socket.read_exact(&mut buf)?;
-            response.set_data(buf);
-        } else if let Some("chunked") = response.get_header("Transfer-Encoding") {
-            let mut data: Vec<u8> = Vec::new();
-            loop {
-                let len_str = read_http_line(&mut socket)?;
-                if config.debug > 1 {
-                    println!("receiving chunk of length 0x{len_str}");
+            (Some(coding), None) => Err(WgetError::ProtocolError(format!(
+                "Unsupported transfer coding {coding}"
+            ))),
+            (None, Some(len_str)) => {
+                if !len_str.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(WgetError::ParsingError(format!(
+                        "Invalid content length {len_str}"
+                    )));
                 }
-                let length: usize = usize::from_str_radix(&len_str, 16).map_err(|_| {
-                    WgetError::ParsingError(format!("Invalid chunk length {len_str}"))
+                let length = len_str.parse::<usize>().map_err(|_| {
+                    WgetError::ParsingError(format!("Invalid content length {len_str}"))
                 })?;
-                if length == 0 {
-                    break;
-                }
-                let mut chunk = vec![0u8; length];
-                socket.read_exact(&mut chunk)?;
-                data.extend(chunk);
-                let mut ending = [0u8, 0u8];
-                socket.read_exact(&mut ending)?;
-                if &ending != b"\r\n" {
-                    panic!("Invalid chunk ending");
-                }
+                Ok(BodyFraming::Length(length))
             }
-            if config.debug > 1 {
-                println!("All chunks received");
+            // These never have a body, while any other response without a
+            // length goes on until the connection closes
+            (None, None)
+                if self.status_family() == HttpStatusFamily::Informational
+                    || self.status_code == HttpStatusCode::NoContent
+                    || self.status_code == HttpStatusCode::NotModified =>
+            {
+                Ok(BodyFraming::None)
             }
-            response.set_data(data);
-            // TODO parse trailers?
+            (None, None) => Ok(BodyFraming::UntilClose),
         }
+    }
+}
 
-        Ok(response)
+/// How the end of a body is found
+enum BodyFraming {
+    Length(usize),
+    Chunked,
+    /// The server closes the connection after the body, as in HTTP/1.0
+    UntilClose,
+    None,
+}
+
+/// Whether what the server sent so far looks like the start of a status line,
+/// without consuming it. Only what has already arrived is looked at, so a
+/// status line split after fewer than 5 bytes is still recognised. An empty
+/// line before it is skipped, like in `receive_response_head`.
+fn starts_with_status_line<S: BufRead>(socket: &mut S) -> io::Result<bool> {
+    const PREFIX: &[u8] = b"HTTP/";
+    let buf = socket.fill_buf()?;
+    let buf = buf
+        .strip_prefix(b"\r\n")
+        .or_else(|| buf.strip_prefix(b"\n"))
+        .unwrap_or(buf);
+    let len = buf.len().min(PREFIX.len());
+    // At the end of the stream, reading the status line reports the
+    // connection closing
+    Ok(buf[..len] == PREFIX[..len])
+}
+
+/// Parses the hexadecimal length at the start of a chunk, which unlike
+/// `usize::from_str_radix` does not allow a sign
+fn parse_chunk_length(len_str: &str) -> WgetResult<usize> {
+    if len_str.is_empty() || !len_str.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(WgetError::ParsingError(format!(
+            "Invalid chunk length {len_str}"
+        )));
+    }
+    usize::from_str_radix(len_str, 16)
+        .map_err(|_| WgetError::ParsingError(format!("Invalid chunk length {len_str}")))
+}
+
+/// Reads header lines up to the empty line that ends them
+pub(super) fn read_fields<S>(socket: &mut S, config: &Configuration) -> WgetResult<Headers>
+where
+    S: BufRead,
+{
+    let limits = &config.header_limits;
+    let mut fields: Vec<(String, String)> = Vec::new();
+    let mut size = 0;
+    loop {
+        let line = require_http_line(socket, limits.max_line_length)?;
+        size += line.len() + 2;
+        if size > limits.max_size {
+            return Err(WgetError::ParsingError(format!(
+                "Headers longer than {} bytes",
+                limits.max_size
+            )));
+        }
+        if line.is_empty() {
+            crate::trace!(config, "Finished reading headers");
+            break;
+        }
+        crate::trace!(config, "Read header line: {}", &line);
+        if line.starts_with(OWS) {
+            // An obsolete line folding, which continues the previous value
+            let (_, value) = fields.last_mut().ok_or_else(|| {
+                WgetError::ParsingError("Continuation line before any header".to_string())
+            })?;
+            value.push(' ');
+            value.push_str(line.trim_matches(OWS));
+            continue;
+        }
+        if fields.len() == limits.max_fields {
+            return Err(WgetError::ParsingError(format!(
+                "More than {} header fields",
+                limits.max_fields
+            )));
+        }
+        let (key, value) = line
+            .split_once(':')
+            .ok_or_else(|| WgetError::ParsingError(format!("Invalid header line {line}")))?;
+        let key = key.trim_end_matches(OWS);
+        if key.is_empty() {
+            return Err(WgetError::ParsingError(format!("No header key in {line}")));
+        }
+        fields.push((key.to_string(), value.trim_matches(OWS).to_string()));
+    }
+
+    check_content_length(&mut fields)?;
+    let mut headers = Headers::default();
+    for (key, value) in fields {
+        headers.add(UniCase::new(key), value);
+    }
+    Ok(headers)
+}
+
+/// Makes sure every `Content-Length` a response sent agrees, as some servers
+/// repeat it, and collapses the repeats into one value
+fn check_content_length(fields: &mut [(String, String)]) -> WgetResult<()> {
+    let is_length = |key: &str| key.eq_ignore_ascii_case("Content-Length");
+    let mut lengths = fields
+        .iter()
+        .filter(|(key, _)| is_length(key))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|length| length.trim_matches(OWS));
+    let Some(first) = lengths.next() else {
+        return Ok(());
+    };
+    if lengths.any(|length| length != first) {
+        return Err(WgetError::ParsingError(
+            "Response has conflicting Content-Length values".to_string(),
+        ));
+    }
+    let first = first.to_string();
+    for (_, value) in fields.iter_mut().filter(|(key, _)| is_length(key)) {
+        value.clone_from(&first);
+    }
+    Ok(())
+}
+
+/// How much body data is read between calls to the progress callback
+const BODY_READ_SIZE: usize = 16 * 1024;
+
+/// Appends exactly `length` bytes from `socket` to `data`, reporting progress
+/// after every read
+fn read_body_data<S>(
+    socket: &mut S,
+    data: &mut Vec<u8>,
+    length: usize,
+    total: Option<u64>,
+    progress: &mut dyn FnMut(u64, Option<u64>),
+) -> io::Result<()>
+where
+    S: BufRead,
+{
+    let mut remaining = length;
+    while remaining > 0 {
+        let start = data.len();
+        let read_size = remaining.min(BODY_READ_SIZE);
+        data.resize(start + read_size, 0);
+        socket.read_exact(&mut data[start..])?;
+        remaining -= read_size;
+        progress(data.len() as u64, total);
+    }
+    Ok(())
+}
+
+/// Reads everything up to the end of the stream into `data`
+fn read_body_to_end<S>(
+    socket: &mut S,
+    data: &mut Vec<u8>,
+    progress: &mut dyn FnMut(u64, Option<u64>),
+) -> io::Result<()>
+where
+    S: BufRead,
+{
+    loop {
+        let start = data.len();
+        data.resize(start + BODY_READ_SIZE, 0);
+        match socket.read(&mut data[start..]) {
+            Ok(0) => {
+                data.truncate(start);
+                return Ok(());
+            }
+            Ok(read) => {
+                data.truncate(start + read);
+                progress(data.len() as u64, None);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => data.truncate(start),
+            Err(e) => {
+                data.truncate(start);
+                return Err(e);
+            }
+        }
     }
 }
 
+/// Shows `head` followed by the body as text, with nothing after the body
 impl fmt::Display for HttpResponse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{} {} {}\r\n",
-            self.version, self.status_code, self.status_message
-        )?;
-        for (key, value) in &self.headers {
-            write!(f, "{key}: {value}\r\n")?;
-        }
-        write!(f, "{}\r\n\r\n", String::from_utf8_lossy(&self.data))
+        write!(f, "{}{}", self.head(), String::from_utf8_lossy(&self.data))
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use proptest::prelude::*;
+
     use super::*;
+    use crate::http::ByteRange;
 
     #[test]
     fn can_parse_http_status() {
@@ -367,6 +960,37 @@ mod tests {
             "Not a status".parse::<HttpStatusCode>(),
             Err(WgetError::ParsingError(_))
         ));
+        assert!(matches!(
+            "+20".parse::<HttpStatusCode>(),
+            Err(WgetError::ParsingError(_))
+        ));
+        assert!(matches!(
+            "600".parse::<HttpStatusCode>(),
+            Err(WgetError::InvalidStatusCode(600))
+        ));
+    }
+
+    #[test]
+    fn keeps_unknown_status_codes() {
+        let status = "299"
+            .parse::<HttpStatusCode>()
+            .expect("Could not parse 299");
+        assert_eq!(status, HttpStatusCode::Unknown(299));
+        assert_eq!(status.family(), HttpStatusFamily::Successful);
+        assert_eq!(status.to_string(), "299");
+        assert_eq!(status.reason_phrase(), "");
+
+        let status = "520"
+            .parse::<HttpStatusCode>()
+            .expect("Could not parse 520");
+        assert_eq!(status.family(), HttpStatusFamily::ServerError);
+        assert_eq!(HttpStatusCode::NotFound.code(), 404);
+    }
+
+    #[test]
+    fn has_reason_phrases() {
+        assert_eq!(HttpStatusCode::Ok.reason_phrase(), "OK");
+        assert_eq!(HttpStatusCode::NotFound.reason_phrase(), "Not Found");
     }
 
     #[test]
@@ -439,6 +1063,23 @@ mod tests {
         assert_eq!(resp.get_header("non existant"), None);
     }
 
+    #[test]
+    fn knows_when_connection_closes() {
+        let response = |version, connection: Option<&str>| {
+            let mut resp = HttpResponse::new(version, HttpStatusCode::Ok, "Ok".to_string());
+            if let Some(connection) = connection {
+                resp.add_header("Connection", connection);
+            }
+            resp.closes_connection()
+        };
+        assert!(!response(HttpVersion::Version1_1, None));
+        assert!(response(HttpVersion::Version1_1, Some("Close")));
+        assert!(response(HttpVersion::Version1_1, Some("upgrade, close")));
+        assert!(response(HttpVersion::Version1_0, None));
+        assert!(!response(HttpVersion::Version1_0, Some("Keep-Alive")));
+        assert!(response(HttpVersion::Version0_9, None));
+    }
+
     #[test]
     fn can_delete_headers() {
         let mut resp = HttpResponse::new(
@@ -502,7 +1143,7 @@ mod tests {
     #[test]
     fn can_read_response() {
         let mut sample_response = "HTTP/1.1 200 Ok\r\nmy header: my value\r\nmy header 2: my value 2\r\nContent-Length: 5\r\n\r\nabcde".as_bytes();
-        let config = Configuration { debug: 0 };
+        let config = Configuration::default();
 
         let response = HttpResponse::receive_response(&mut sample_response, &config)
             .expect("Could not read response!");
@@ -517,6 +1158,469 @@ mod tests {
         assert_eq!(response.get_header("not a key"), None);
     }
 
+    #[test]
+    fn reads_status_lines_with_odd_spacing() {
+        let config = Configuration::default();
+        for (status_line, status_code, status_message) in [
+            ("HTTP/1.1 200 OK", HttpStatusCode::Ok, "OK"),
+            ("HTTP/1.1 200", HttpStatusCode::Ok, ""),
+            ("HTTP/1.1 200 ", HttpStatusCode::Ok, ""),
+            // Two spaces after the version, with or without a reason phrase
+            ("HTTP/1.1  200", HttpStatusCode::Ok, ""),
+            (
+                "HTTP/1.1  404 Not Found",
+                HttpStatusCode::NotFound,
+                "Not Found",
+            ),
+        ] {
+            let head = format!("{status_line}\r\nContent-Length: 0\r\n\r\n");
+            let response = HttpResponse::receive_response(&mut head.as_bytes(), &config)
+                .expect("Could not read response");
+            assert_eq!(response.status_code, status_code, "{status_line}");
+            assert_eq!(response.status_message, status_message, "{status_line}");
+        }
+    }
+
+    #[test]
+    fn displays_the_response_as_sent() {
+        let mut response = HttpResponse::new(
+            HttpVersion::Version1_1,
+            HttpStatusCode::Ok,
+            "OK".to_string(),
+        );
+        response.add_header("Content-Length", "2");
+        response.set_data("hi");
+        assert_eq!(
+            response.to_string(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi"
+        );
+    }
+
+    #[test]
+    fn reports_progress_while_reading_body() {
+        let body = vec![b'a'; BODY_READ_SIZE * 2 + 10];
+        let mut sample_response =
+            format!("HTTP/1.1 200 Ok\r\nContent-Length: {}\r\n\r\n", body.len()).into_bytes();
+        sample_response.extend(&body);
+        let config = Configuration::default();
+
+        let mut reader = &sample_response[..];
+        let mut response = HttpResponse::receive_response_head(&mut reader, &config)
+            .expect("Could not read response head!");
+        let mut updates = Vec::new();
+        response
+            .receive_body_with_progress(&mut reader, &config, &mut |received, total| {
+                updates.push((received, total))
+            })
+            .expect("Could not read response body!");
+
+        let total = Some(body.len() as u64);
+        assert_eq!(
+            updates,
+            vec![
+                (BODY_READ_SIZE as u64, total),
+                (BODY_READ_SIZE as u64 * 2, total),
+                (body.len() as u64, total)
+            ]
+        );
+        assert_eq!(response.get_data(), &body[..]);
+    }
+
+    #[test]
+    fn reports_progress_for_chunked_body() {
+        let mut sample_response =
+            "HTTP/1.1 200 Ok\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nabcde\r\n3\r\nfgh\r\n0\r\n\r\n"
+                .as_bytes();
+        let config = Configuration::default();
+
+        let mut response = HttpResponse::receive_response_head(&mut sample_response, &config)
+            .expect("Could not read response head!");
+        let mut updates = Vec::new();
+        response
+            .receive_body_with_progress(&mut sample_response, &config, &mut |received, total| {
+                updates.push((received, total))
+            })
+            .expect("Could not read response body!");
+
+        assert_eq!(updates, vec![(5, None), (8, None)]);
+        assert_eq!(response.get_data(), b"abcdefgh");
+    }
+
+    #[test]
+    fn reads_trailers_between_chunked_responses() {
+        let mut stream = concat!(
+            "HTTP/1.1 200 Ok\r\nTransfer-Encoding: chunked\r\nTrailer: Checksum\r\n\r\n",
+            "5;name=value\r\nabcde\r\n0\r\nChecksum: abc\r\nExpires: never\r\n\r\n",
+            "HTTP/1.1 200 Ok\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "3\r\nfgh\r\n0;last\r\nChecksum: def\r\n\r\n",
+        )
+        .as_bytes();
+        let config = Configuration::default();
+
+        let first = HttpResponse::receive_response(&mut stream, &config)
+            .expect("Could not read first response!");
+        assert_eq!(first.get_data(), b"abcde");
+        assert_eq!(first.get_trailer("Checksum"), Some("abc"));
+        assert_eq!(first.get_trailer("Expires"), Some("never"));
+        assert_eq!(first.get_header("Checksum"), None);
+
+        let second = HttpResponse::receive_response(&mut stream, &config)
+            .expect("Could not read second response!");
+        assert_eq!(second.get_data(), b"fgh");
+        assert_eq!(second.get_trailer("checksum"), Some("def"));
+        assert_eq!(second.get_trailer("Expires"), None);
+        assert!(stream.is_empty());
+    }
+
+    fn parse_response(response: &str) -> WgetResult<HttpResponse> {
+        let config = Configuration {
+            header_limits: HeaderLimits {
+                max_line_length: 40,
+                max_fields: 4,
+                max_size: 100,
+            },
+            ..Default::default()
+        };
+        HttpResponse::receive_response(&mut response.as_bytes(), &config)
+    }
+
+    #[test]
+    fn tolerates_loose_header_syntax() {
+        let response = parse_response(concat!(
+            "\r\nHTTP/1.1 200\n",
+            "Content-Length:3\r\n",
+            "X-Folded: first\r\n",
+            " \tsecond \r\n",
+            "X-Spaced \t:  \tvalue\t\r\n",
+            "\r\nabc",
+        ))
+        .expect("Could not read response");
+        assert_eq!(response.status_code, HttpStatusCode::Ok);
+        assert_eq!(response.status_message, "");
+        assert_eq!(response.get_header("X-Folded"), Some("first second"));
+        assert_eq!(response.get_header("X-Spaced"), Some("value"));
+        assert_eq!(response.get_data(), b"abc");
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        for response in [
+            "HTTP/1.1 200 OK\r\nNo colon\r\n\r\n",
+            "HTTP/1.1 200 OK\r\n: no key\r\n\r\n",
+            "HTTP/1.1 200 OK\r\n folded: first\r\n\r\n",
+            "HTTP/1.1 OK\r\n\r\n",
+            "HTTP/1.1\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse_response(response), Err(WgetError::ParsingError(_))),
+                "{response:?} was accepted"
+            );
+        }
+    }
+
+    #[test]
+    fn enforces_header_limits() {
+        let long_line = format!("HTTP/1.1 200 OK\r\nX-Long: {}\r\n\r\n", "a".repeat(40));
+        let many_fields = format!("HTTP/1.1 200 OK\r\n{}\r\n", "A: b\r\n".repeat(5));
+        let large_head = format!(
+            "HTTP/1.1 200 OK\r\n{}\r\n",
+            "A: bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb\r\n".repeat(3)
+        );
+        for response in [long_line, many_fields, large_head] {
+            assert!(
+                matches!(parse_response(&response), Err(WgetError::ParsingError(_))),
+                "{response:?} was accepted"
+            );
+        }
+        let folds = format!("HTTP/1.1 200 OK\r\nA: b\r\n{}\r\n", " c\r\n".repeat(5));
+        parse_response(&folds).expect("Continuation lines counted as fields");
+    }
+
+    #[test]
+    fn checks_body_framing() {
+        let response = parse_response(
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\nContent-Length: 3, 3\r\n\r\nabc",
+        )
+        .expect("Repeated Content-Length was rejected");
+        assert_eq!(response.content_length(), Some(3));
+
+        for response in [
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\nContent-Length: 4\r\n\r\nabcd",
+            "HTTP/1.1 200 OK\r\nContent-Length: 3, 4\r\n\r\nabcd",
+            "HTTP/1.1 200 OK\r\nContent-Length: +3\r\n\r\nabc",
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n-3\r\nabc\r\n0\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabcd\r\n0\r\n\r\n",
+        ] {
+            assert!(
+                matches!(parse_response(response), Err(WgetError::ParsingError(_))),
+                "{response:?} was accepted"
+            );
+        }
+        assert!(matches!(
+            parse_response("HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\n\r\n"),
+            Err(WgetError::ProtocolError(_))
+        ));
+        let response = parse_response(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: Chunked \r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+        )
+        .expect("Could not read chunked response");
+        assert_eq!(response.get_data(), b"abc");
+    }
+
+    #[test]
+    fn reads_close_delimited_bodies() {
+        let response = parse_response("HTTP/1.0 200 OK\r\nServer: old\r\n\r\nab\r\ncd")
+            .expect("Could not read response");
+        assert!(response.is_close_delimited());
+        assert_eq!(response.get_data(), b"ab\r\ncd");
+
+        let mut socket =
+            "HTTP/1.1 204 No Content\r\n\r\nHTTP/1.1 304 Not Modified\r\n\r\n".as_bytes();
+        let config = Configuration::default();
+        for status in [HttpStatusCode::NoContent, HttpStatusCode::NotModified] {
+            let response = HttpResponse::receive_response(&mut socket, &config)
+                .expect("Could not read response");
+            assert_eq!(response.status_code, status);
+            assert!(response.get_data().is_empty());
+        }
+    }
+
+    #[test]
+    fn reads_http09_responses_only_when_accepted() {
+        let body = "<html>Hello</html>\r\n";
+        assert!(parse_response(body).is_err());
+
+        let config = Configuration {
+            accept_http09: true,
+            ..Default::default()
+        };
+        let receive_first = |mut socket: &[u8]| {
+            let mut response = HttpResponse::receive_first_head(&mut socket, &config, &mut |_| {})
+                .expect("Could not read response");
+            response
+                .receive_body(&mut socket, &config)
+                .expect("Could not read body");
+            response
+        };
+        let response = receive_first(body.as_bytes());
+        assert_eq!(response.version, HttpVersion::Version0_9);
+        assert_eq!(response.status_code, HttpStatusCode::Ok);
+        assert_eq!(response.get_data(), body.as_bytes());
+        assert!(response.closes_connection());
+        // Only the first response on a connection can be HTTP/0.9
+        assert!(HttpResponse::receive_response(&mut body.as_bytes(), &config).is_err());
+
+        for response in [
+            "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi",
+            // With the end of an earlier message left over
+            "\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi",
+            "\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi",
+        ] {
+            let response = receive_first(response.as_bytes());
+            assert_eq!(response.version, HttpVersion::Version1_1);
+            assert_eq!(response.get_data(), b"hi");
+        }
+    }
+
+    #[test]
+    fn reads_byte_ranges() {
+        let config = Configuration::default();
+        let single = parse_response(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 5-7/10\r\nContent-Length: 3\r\n\r\nfgh",
+        )
+        .expect("Could not read response");
+        assert_eq!(
+            single.content_range().and_then(|range| range.range),
+            Some(ByteRange::new(5, 7))
+        );
+        let parts = single.byte_ranges(&config).expect("Could not read range");
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].range, ByteRange::new(5, 7));
+        assert_eq!(parts[0].get_data(), b"fgh");
+
+        let body = "--b\r\nContent-Range: bytes 0-1/10\r\n\r\nab\r\n--b\r\nContent-Range: bytes 8-9/10\r\n\r\nij\r\n--b--\r\n";
+        let mut multipart = HttpResponse::new(
+            HttpVersion::Version1_1,
+            HttpStatusCode::PartialContent,
+            "Partial Content".to_string(),
+        );
+        multipart.add_header("Content-Type", "multipart/byteranges; boundary=b");
+        multipart.set_data(body);
+        let parts = multipart
+            .byte_ranges(&config)
+            .expect("Could not read ranges");
+        let ranges = parts
+            .iter()
+            .map(|part| (part.range, part.get_data()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            ranges,
+            [
+                (ByteRange::new(0, 1), &b"ab"[..]),
+                (ByteRange::new(8, 9), &b"ij"[..])
+            ]
+        );
+
+        let whole = parse_response("HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc")
+            .expect("Could not read response");
+        assert!(matches!(
+            whole.byte_ranges(&config),
+            Err(WgetError::ProtocolError(_))
+        ));
+    }
+
+    #[test]
+    fn skips_interim_responses() {
+        let mut stream = concat!(
+            "HTTP/1.1 100 Continue\r\n\r\n",
+            "HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nabc",
+        )
+        .as_bytes();
+        let config = Configuration::default();
+        let mut hints = Vec::new();
+        let mut response = HttpResponse::receive_final_head(&mut stream, &config, &mut |interim| {
+            hints.push((
+                interim.status_code,
+                interim.get_header("Link").map(str::to_string),
+            ))
+        })
+        .expect("Could not read response");
+        response
+            .receive_body(&mut stream, &config)
+            .expect("Could not read body");
+        assert_eq!(
+            hints,
+            [
+                (HttpStatusCode::Continue, None),
+                (
+                    HttpStatusCode::EarlyHints,
+                    Some("</style.css>; rel=preload".to_string())
+                )
+            ]
+        );
+        assert_eq!(response.status_code, HttpStatusCode::Ok);
+        assert_eq!(response.get_data(), b"abc");
+    }
+
+    #[test]
+    fn stops_at_switching_protocols() {
+        let mut stream = concat!(
+            "HTTP/1.1 100 Continue\r\n\r\n",
+            "HTTP/1.1 101 Switching Protocols\r\nUpgrade: example\r\n\r\n",
+            "not http",
+        )
+        .as_bytes();
+        let response = HttpResponse::receive_response(&mut stream, &Configuration::default())
+            .expect("Could not read response");
+        assert_eq!(response.status_code, HttpStatusCode::SwitchingProtocols);
+        assert_eq!(stream, b"not http");
+    }
+
+    #[test]
+    fn truncated_chunk_is_an_io_error() {
+        let result =
+            parse_response("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc");
+        assert!(
+            matches!(result, Err(WgetError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof)
+        );
+    }
+
+    /// Pieces of responses, which put together at random reach further into
+    /// the parser than random bytes do
+    const RESPONSE_PARTS: &[&str] = &[
+        "HTTP/1.1 ",
+        "HTTP/1.0 ",
+        "200",
+        "999",
+        " OK",
+        "\r\n",
+        "\n",
+        "Content-Length",
+        "Transfer-Encoding",
+        "chunked",
+        ": ",
+        ":",
+        " ",
+        "\t",
+        ";",
+        ",",
+        "0",
+        "5",
+        "ffffffffffffffff",
+        "abc",
+    ];
+
+    fn field() -> impl Strategy<Value = (String, String)> {
+        ("[A-Za-z][A-Za-z0-9-]{0,15}", "([!-~]([ -~]{0,30}[!-~])?)?").prop_filter(
+            "The framing headers are set separately",
+            |(key, _)| {
+                !key.eq_ignore_ascii_case("Content-Length")
+                    && !key.eq_ignore_ascii_case("Transfer-Encoding")
+            },
+        )
+    }
+
+    proptest! {
+        #[test]
+        fn never_panics_on_arbitrary_bytes(data in prop::collection::vec(any::<u8>(), 0..512)) {
+            let _ = HttpResponse::receive_response(&mut &data[..], &Configuration::default());
+        }
+
+        #[test]
+        fn never_panics_on_response_like_bytes(
+            parts in prop::collection::vec(prop::sample::select(RESPONSE_PARTS), 0..50)
+        ) {
+            let _ = parse_response(&parts.concat());
+        }
+
+        #[test]
+        fn reads_what_it_serializes(
+            status in 200u16..600,
+            message in "([A-Za-z][A-Za-z ]{0,20})?",
+            http1_0 in any::<bool>(),
+            fields in prop::collection::vec(field(), 0..10),
+            data in prop::collection::vec(any::<u8>(), 0..256),
+        ) {
+            let version = if http1_0 {
+                HttpVersion::Version1_0
+            } else {
+                HttpVersion::Version1_1
+            };
+            let status_code = HttpStatusCode::try_from(status).expect("Status code out of range");
+            let mut response = HttpResponse::new(version, status_code, message);
+            for (key, value) in fields {
+                response.add_header(key, value);
+            }
+            response.add_header("Content-Length", data.len().to_string());
+            response.set_data(data);
+
+            let bytes = response.serialize();
+            let read = HttpResponse::receive_response(&mut &bytes[..], &Configuration::default())
+                .expect("Could not read response");
+            prop_assert_eq!(&read.version, &response.version);
+            prop_assert_eq!(read.status_code, response.status_code);
+            prop_assert_eq!(&read.status_message, &response.status_message);
+            prop_assert_eq!(
+                (&read.headers).into_iter().collect::<Vec<_>>(),
+                (&response.headers).into_iter().collect::<Vec<_>>()
+            );
+            prop_assert_eq!(read.get_data(), response.get_data());
+        }
+    }
+
+    #[test]
+    fn closed_connection_is_an_io_error() {
+        let mut sample_response = "".as_bytes();
+        let config = Configuration::default();
+
+        let result = HttpResponse::receive_response(&mut sample_response, &config);
+        assert!(
+            matches!(result, Err(WgetError::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof)
+        );
+    }
+
     #[test]
     fn headers_are_case_insensitive() {
         let mut res = HttpResponse::new(
@@ -0,0 +1,390 @@
+use std::{fmt, io::Read};
+
+use crate::error::{WgetError, WgetResult};
+
+/// The length of the header every frame starts with
+pub const HEADER_LENGTH: usize = 9;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Data = 0x0,
+    Headers = 0x1,
+    Priority = 0x2,
+    RstStream = 0x3,
+    Settings = 0x4,
+    PushPromise = 0x5,
+    Ping = 0x6,
+    GoAway = 0x7,
+    WindowUpdate = 0x8,
+    Continuation = 0x9,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = WgetError;
+
+    fn try_from(value: u8) -> WgetResult<Self> {
+        match value {
+            0x0 => Ok(FrameKind::Data),
+            0x1 => Ok(FrameKind::Headers),
+            0x2 => Ok(FrameKind::Priority),
+            0x3 => Ok(FrameKind::RstStream),
+            0x4 => Ok(FrameKind::Settings),
+            0x5 => Ok(FrameKind::PushPromise),
+            0x6 => Ok(FrameKind::Ping),
+            0x7 => Ok(FrameKind::GoAway),
+            0x8 => Ok(FrameKind::WindowUpdate),
+            0x9 => Ok(FrameKind::Continuation),
+            _ => Err(WgetError::ProtocolError(format!(
+                "Unknown frame type {value:#x}"
+            ))),
+        }
+    }
+}
+
+/// Why a stream or connection was closed, as sent in `RST_STREAM` and
+/// `GOAWAY` frames
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ErrorCode(pub u32);
+
+impl ErrorCode {
+    pub const NO_ERROR: ErrorCode = ErrorCode(0x0);
+    pub const PROTOCOL_ERROR: ErrorCode = ErrorCode(0x1);
+    pub const INTERNAL_ERROR: ErrorCode = ErrorCode(0x2);
+    pub const FLOW_CONTROL_ERROR: ErrorCode = ErrorCode(0x3);
+    pub const SETTINGS_TIMEOUT: ErrorCode = ErrorCode(0x4);
+    pub const STREAM_CLOSED: ErrorCode = ErrorCode(0x5);
+    pub const FRAME_SIZE_ERROR: ErrorCode = ErrorCode(0x6);
+    pub const REFUSED_STREAM: ErrorCode = ErrorCode(0x7);
+    pub const CANCEL: ErrorCode = ErrorCode(0x8);
+    pub const COMPRESSION_ERROR: ErrorCode = ErrorCode(0x9);
+    pub const CONNECT_ERROR: ErrorCode = ErrorCode(0xa);
+    pub const ENHANCE_YOUR_CALM: ErrorCode = ErrorCode(0xb);
+    pub const INADEQUATE_SECURITY: ErrorCode = ErrorCode(0xc);
+    pub const HTTP_1_1_REQUIRED: ErrorCode = ErrorCode(0xd);
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match *self {
+            ErrorCode::NO_ERROR => "NO_ERROR",
+            ErrorCode::PROTOCOL_ERROR => "PROTOCOL_ERROR",
+            ErrorCode::INTERNAL_ERROR => "INTERNAL_ERROR",
+            ErrorCode::FLOW_CONTROL_ERROR => "FLOW_CONTROL_ERROR",
+            ErrorCode::SETTINGS_TIMEOUT => "SETTINGS_TIMEOUT",
+            ErrorCode::STREAM_CLOSED => "STREAM_CLOSED",
+            ErrorCode::FRAME_SIZE_ERROR => "FRAME_SIZE_ERROR",
+            ErrorCode::REFUSED_STREAM => "REFUSED_STREAM",
+            ErrorCode::CANCEL => "CANCEL",
+            ErrorCode::COMPRESSION_ERROR => "COMPRESSION_ERROR",
+            ErrorCode::CONNECT_ERROR => "CONNECT_ERROR",
+            ErrorCode::ENHANCE_YOUR_CALM => "ENHANCE_YOUR_CALM",
+            ErrorCode::INADEQUATE_SECURITY => "INADEQUATE_SECURITY",
+            ErrorCode::HTTP_1_1_REQUIRED => "HTTP_1_1_REQUIRED",
+            ErrorCode(code) => return write!(f, "error {code:#x}"),
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub const END_STREAM: u8 = 0x1;
+    pub const ACK: u8 = 0x1;
+    pub const END_HEADERS: u8 = 0x4;
+    pub const PADDED: u8 = 0x8;
+    pub const PRIORITY: u8 = 0x20;
+
+    pub fn new(kind: FrameKind, flags: u8, stream_id: u32, payload: Vec<u8>) -> Self {
+        Frame {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        }
+    }
+
+    pub fn settings(settings: &[(u16, u32)]) -> Self {
+        let payload = settings
+            .iter()
+            .flat_map(|(id, value)| [&id.to_be_bytes()[..], &value.to_be_bytes()].concat())
+            .collect();
+        Frame::new(FrameKind::Settings, 0, 0, payload)
+    }
+
+    pub fn settings_ack() -> Self {
+        Frame::new(FrameKind::Settings, Frame::ACK, 0, Vec::new())
+    }
+
+    pub fn ping_ack(data: Vec<u8>) -> Self {
+        Frame::new(FrameKind::Ping, Frame::ACK, 0, data)
+    }
+
+    pub fn window_update(stream_id: u32, increment: u32) -> Self {
+        Frame::new(
+            FrameKind::WindowUpdate,
+            0,
+            stream_id,
+            increment.to_be_bytes().to_vec(),
+        )
+    }
+
+    pub fn rst_stream(stream_id: u32, error: ErrorCode) -> Self {
+        Frame::new(
+            FrameKind::RstStream,
+            0,
+            stream_id,
+            error.0.to_be_bytes().to_vec(),
+        )
+    }
+
+    pub fn goaway(last_stream_id: u32, error: ErrorCode) -> Self {
+        Frame::new(
+            FrameKind::GoAway,
+            0,
+            0,
+            [last_stream_id.to_be_bytes(), error.0.to_be_bytes()].concat(),
+        )
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// Reads the next frame, skipping any of unknown types as RFC 9113
+    /// requires. Frames with payloads over `max_size` are an error.
+    pub fn read<R: Read>(reader: &mut R, max_size: u32) -> WgetResult<Self> {
+        loop {
+            let mut header = [0u8; HEADER_LENGTH];
+            reader.read_exact(&mut header)?;
+            let length = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+            if length > max_size {
+                return Err(WgetError::ProtocolError(format!(
+                    "Frame of {length} bytes is over the maximum of {max_size}"
+                )));
+            }
+            let mut payload = vec![0u8; length as usize];
+            reader.read_exact(&mut payload)?;
+            let stream_id =
+                u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+            if let Ok(kind) = FrameKind::try_from(header[3]) {
+                return Ok(Frame::new(kind, header[4], stream_id, payload));
+            }
+        }
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        let length = (self.payload.len() as u32).to_be_bytes();
+        let mut bytes = Vec::with_capacity(HEADER_LENGTH + self.payload.len());
+        bytes.extend(&length[1..]);
+        bytes.push(self.kind as u8);
+        bytes.push(self.flags);
+        bytes.extend(self.stream_id.to_be_bytes());
+        bytes.extend(&self.payload);
+        bytes
+    }
+
+    /// The payload of a `DATA`, `HEADERS` or `PUSH_PROMISE` frame without any
+    /// padding, and for `HEADERS` without any priority fields either
+    pub fn content(&self) -> WgetResult<&[u8]> {
+        let mut content = &self.payload[..];
+        if self.has_flag(Frame::PADDED) {
+            let (&padding, rest) = content.split_first().ok_or_else(|| padding_error(self))?;
+            let length = rest
+                .len()
+                .checked_sub(padding as usize)
+                .ok_or_else(|| padding_error(self))?;
+            content = &rest[..length];
+        }
+        if self.kind == FrameKind::Headers && self.has_flag(Frame::PRIORITY) {
+            content = content.get(5..).ok_or_else(|| {
+                WgetError::ProtocolError("HEADERS frame too short for its priority".to_string())
+            })?;
+        }
+        Ok(content)
+    }
+
+    /// Reads a 31 bit stream ID or window increment from the payload
+    pub fn read_u31(&self, offset: usize) -> WgetResult<u32> {
+        self.read_u32(offset).map(|value| value & 0x7fff_ffff)
+    }
+
+    pub fn read_u32(&self, offset: usize) -> WgetResult<u32> {
+        self.payload
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .ok_or_else(|| WgetError::ProtocolError(format!("{:?} frame is too short", self.kind)))
+    }
+}
+
+fn padding_error(frame: &Frame) -> WgetError {
+    WgetError::ProtocolError(format!("{:?} frame has invalid padding", frame.kind))
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} frame on stream {}, {} bytes, flags {:#x}",
+            self.kind,
+            self.stream_id,
+            self.payload.len(),
+            self.flags
+        )
+    }
+}
+
+/// The settings one side of a connection sent, which the other must respect
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Settings {
+    pub header_table_size: u32,
+    pub enable_push: bool,
+    pub max_concurrent_streams: Option<u32>,
+    pub initial_window_size: u32,
+    pub max_frame_size: u32,
+    pub max_header_list_size: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            header_table_size: 4096,
+            enable_push: true,
+            max_concurrent_streams: None,
+            initial_window_size: 65_535,
+            max_frame_size: 16_384,
+            max_header_list_size: None,
+        }
+    }
+}
+
+impl Settings {
+    pub const HEADER_TABLE_SIZE: u16 = 0x1;
+    pub const ENABLE_PUSH: u16 = 0x2;
+    pub const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+    pub const INITIAL_WINDOW_SIZE: u16 = 0x4;
+    pub const MAX_FRAME_SIZE: u16 = 0x5;
+    pub const MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+    /// Updates the settings from the payload of a `SETTINGS` frame. Unknown
+    /// settings are ignored.
+    pub fn apply(&mut self, payload: &[u8]) -> WgetResult<()> {
+        if !payload.len().is_multiple_of(6) {
+            return Err(WgetError::ProtocolError(format!(
+                "SETTINGS frame of {} bytes",
+                payload.len()
+            )));
+        }
+        for setting in payload.chunks_exact(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                Settings::HEADER_TABLE_SIZE => self.header_table_size = value,
+                Settings::ENABLE_PUSH if value <= 1 => self.enable_push = value == 1,
+                Settings::MAX_CONCURRENT_STREAMS => self.max_concurrent_streams = Some(value),
+                Settings::INITIAL_WINDOW_SIZE if value <= 0x7fff_ffff => {
+                    self.initial_window_size = value
+                }
+                Settings::MAX_FRAME_SIZE if (16_384..=0xff_ffff).contains(&value) => {
+                    self.max_frame_size = value
+                }
+                Settings::MAX_HEADER_LIST_SIZE => self.max_header_list_size = Some(value),
+                Settings::ENABLE_PUSH
+                | Settings::INITIAL_WINDOW_SIZE
+                | Settings::MAX_FRAME_SIZE => {
+                    return Err(WgetError::ProtocolError(format!(
+                        "Invalid value {value} for setting {id:#x}"
+                    )))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_frames() {
+        let frame = Frame::new(FrameKind::Headers, Frame::END_HEADERS, 3, b"abc".to_vec());
+        let bytes = frame.serialize();
+        assert_eq!(bytes, b"\x00\x00\x03\x01\x04\x00\x00\x00\x03abc");
+        assert_eq!(
+            Frame::read(&mut &bytes[..], 16_384).expect("Could not read frame"),
+            frame
+        );
+    }
+
+    #[test]
+    fn skips_unknown_frames() {
+        let mut bytes = b"\x00\x00\x02\xfa\x00\x00\x00\x00\x01xy".to_vec();
+        bytes.extend(Frame::window_update(1, 100).serialize());
+        let frame = Frame::read(&mut &bytes[..], 16_384).expect("Could not read frame");
+        assert_eq!(frame.kind, FrameKind::WindowUpdate);
+        assert_eq!(frame.read_u31(0).expect("No increment"), 100);
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let bytes = Frame::new(FrameKind::Data, 0, 1, vec![0; 100]).serialize();
+        assert!(matches!(
+            Frame::read(&mut &bytes[..], 99),
+            Err(WgetError::ProtocolError(_))
+        ));
+    }
+
+    #[test]
+    fn strips_padding_and_priority() {
+        let frame = Frame::new(
+            FrameKind::Data,
+            Frame::PADDED,
+            1,
+            b"\x02data\x00\x00".to_vec(),
+        );
+        assert_eq!(frame.content().expect("Invalid padding"), b"data");
+
+        let frame = Frame::new(
+            FrameKind::Headers,
+            Frame::PADDED | Frame::PRIORITY,
+            1,
+            b"\x01\x00\x00\x00\x03\x10block\x00".to_vec(),
+        );
+        assert_eq!(frame.content().expect("Invalid padding"), b"block");
+
+        let frame = Frame::new(FrameKind::Data, Frame::PADDED, 1, b"\x05abc".to_vec());
+        assert!(frame.content().is_err());
+    }
+
+    #[test]
+    fn applies_settings() {
+        let mut settings = Settings::default();
+        let frame = Frame::settings(&[
+            (Settings::MAX_CONCURRENT_STREAMS, 10),
+            (Settings::MAX_FRAME_SIZE, 32_768),
+            (0xf0, 1),
+        ]);
+        settings.apply(&frame.payload).expect("Invalid settings");
+        assert_eq!(settings.max_concurrent_streams, Some(10));
+        assert_eq!(settings.max_frame_size, 32_768);
+        assert_eq!(settings.initial_window_size, 65_535);
+
+        let frame = Frame::settings(&[(Settings::MAX_FRAME_SIZE, 100)]);
+        assert!(settings.apply(&frame.payload).is_err());
+        assert!(settings.apply(&[0, 1, 0]).is_err());
+    }
+
+    #[test]
+    fn names_error_codes() {
+        assert_eq!(ErrorCode::REFUSED_STREAM.to_string(), "REFUSED_STREAM");
+        assert_eq!(ErrorCode(0x42).to_string(), "error 0x42");
+    }
+}
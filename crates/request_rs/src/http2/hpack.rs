@@ -0,0 +1,563 @@
+use std::collections::VecDeque;
+
+use crate::{
+    error::{WgetError, WgetResult},
+    http::HeaderLimits,
+};
+
+use super::huffman;
+
+/// The headers every HPACK table starts with, from RFC 7541 appendix A. They
+/// are numbered from 1.
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// The size of the dynamic table until the peer's settings say otherwise
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Encodes header fields into a header block
+///
+/// Nothing is added to the peer's dynamic table, so the encoder needs no state
+/// and the peer's table size never matters. Fields in the static table are
+/// still referred to by index, and strings are Huffman encoded when shorter.
+pub fn encode<'a>(fields: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<u8> {
+    let mut block = Vec::new();
+    for (name, value) in fields {
+        let exact = STATIC_TABLE
+            .iter()
+            .position(|entry| *entry == (name, value));
+        let named = STATIC_TABLE.iter().position(|entry| entry.0 == name);
+        match (exact, named) {
+            (Some(index), _) => encode_integer(&mut block, 0x80, 7, index + 1),
+            // A literal header field without indexing, with an indexed name
+            (None, Some(index)) => {
+                encode_integer(&mut block, 0x00, 4, index + 1);
+                encode_string(&mut block, value.as_bytes());
+            }
+            (None, None) => {
+                block.push(0x00);
+                encode_string(&mut block, name.as_bytes());
+                encode_string(&mut block, value.as_bytes());
+            }
+        }
+    }
+    block
+}
+
+/// Writes `value` with an `prefix_bits` bit prefix, the rest of the first
+/// byte being `first_byte`
+fn encode_integer(block: &mut Vec<u8>, first_byte: u8, prefix_bits: u8, value: usize) {
+    let max_prefix = (1usize << prefix_bits) - 1;
+    if value < max_prefix {
+        block.push(first_byte | value as u8);
+        return;
+    }
+    block.push(first_byte | max_prefix as u8);
+    let mut rest = value - max_prefix;
+    while rest >= 0x80 {
+        block.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    block.push(rest as u8);
+}
+
+fn encode_string(block: &mut Vec<u8>, string: &[u8]) {
+    let huffman_len = huffman::encoded_len(string);
+    if huffman_len < string.len() {
+        encode_integer(block, 0x80, 7, huffman_len);
+        block.extend(huffman::encode(string));
+    } else {
+        encode_integer(block, 0x00, 7, string.len());
+        block.extend(string);
+    }
+}
+
+/// The headers the peer has added to the table as it sent them, newest first
+#[derive(Debug)]
+struct DynamicTable {
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl DynamicTable {
+    fn new(max_size: usize) -> Self {
+        DynamicTable {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    /// The size RFC 7541 counts for an entry, including 32 bytes of overhead
+    fn entry_size(name: &str, value: &str) -> usize {
+        name.len() + value.len() + 32
+    }
+
+    fn get(&self, index: usize) -> Option<&(String, String)> {
+        self.entries.get(index)
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = Self::entry_size(&name, &value);
+        // An entry too large for the table just empties it
+        self.evict_to(self.max_size.saturating_sub(size));
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict_to(max_size);
+    }
+
+    fn evict_to(&mut self, size: usize) {
+        while self.size > size {
+            let (name, value) = self
+                .entries
+                .pop_back()
+                .expect("Table has a size but no entries");
+            self.size -= Self::entry_size(&name, &value);
+        }
+    }
+}
+
+/// Decodes the header blocks sent by the peer, keeping the dynamic table
+/// they build up. Every block must be decoded, in the order it was received,
+/// or the table gets out of sync.
+#[derive(Debug)]
+pub struct Decoder {
+    table: DynamicTable,
+    /// The largest the peer may make the table, as given in our settings
+    size_limit: usize,
+    /// How many fields, and how many bytes of them, a single block may
+    /// decode to. A few bytes of block can stand for a large table entry,
+    /// so this is checked while decoding rather than after.
+    limits: HeaderLimits,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new(DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl Decoder {
+    pub fn new(size_limit: usize) -> Self {
+        Decoder {
+            table: DynamicTable::new(size_limit),
+            size_limit,
+            limits: HeaderLimits::default(),
+        }
+    }
+
+    pub fn with_limits(mut self, limits: HeaderLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn decode(&mut self, block: &[u8]) -> WgetResult<Vec<(String, String)>> {
+        let mut fields: Vec<(String, String)> = Vec::new();
+        let mut size = 0;
+        let mut reader = BlockReader { block, position: 0 };
+        while let Some(&first) = reader.peek() {
+            if first & 0x80 != 0 {
+                let index = reader.integer(7)?;
+                let (name, value) = self.lookup(index)?;
+                fields.push((name.to_string(), value.to_string()));
+            } else if first & 0x40 != 0 {
+                let (name, value) = self.literal(&mut reader, 6)?;
+                self.table.insert(name.clone(), value.clone());
+                fields.push((name, value));
+            } else if first & 0x20 != 0 {
+                if !fields.is_empty() {
+                    return Err(WgetError::ParsingError(
+                        "Table size update after header fields".to_string(),
+                    ));
+                }
+                let size = reader.integer(5)?;
+                if size > self.size_limit {
+                    return Err(WgetError::ParsingError(format!(
+                        "Table size update to {size} is over the limit of {}",
+                        self.size_limit
+                    )));
+                }
+                self.table.set_max_size(size);
+            } else {
+                // Without indexing or never indexed, which are the same to us
+                fields.push(self.literal(&mut reader, 4)?);
+            }
+            // Each field counted as it would be in an HTTP/1 head. Table size
+            // updates come before any fields, so only fields get here.
+            if let Some((name, value)) = fields.last() {
+                size += name.len() + value.len() + 4;
+            }
+            if fields.len() > self.limits.max_fields || size > self.limits.max_size {
+                return Err(WgetError::ParsingError(format!(
+                    "Header block decodes to more than {} fields or {} bytes",
+                    self.limits.max_fields, self.limits.max_size
+                )));
+            }
+        }
+        Ok(fields)
+    }
+
+    /// The header at `index`, counting through the static table and then the
+    /// dynamic one
+    fn lookup(&self, index: usize) -> WgetResult<(&str, &str)> {
+        let entry = match index {
+            0 => None,
+            1..=61 => Some(STATIC_TABLE[index - 1]),
+            _ => self
+                .table
+                .get(index - 62)
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        };
+        entry.ok_or_else(|| WgetError::ParsingError(format!("Invalid header table index {index}")))
+    }
+
+    /// Reads a literal header field whose name index has `prefix_bits` bits
+    fn literal(&self, reader: &mut BlockReader, prefix_bits: u8) -> WgetResult<(String, String)> {
+        let name = match reader.integer(prefix_bits)? {
+            0 => reader.string()?,
+            index => self.lookup(index)?.0.to_string(),
+        };
+        let value = reader.string()?;
+        Ok((name, value))
+    }
+}
+
+struct BlockReader<'a> {
+    block: &'a [u8],
+    position: usize,
+}
+
+impl BlockReader<'_> {
+    fn peek(&self) -> Option<&u8> {
+        self.block.get(self.position)
+    }
+
+    fn next(&mut self) -> WgetResult<u8> {
+        let byte = *self
+            .peek()
+            .ok_or_else(|| WgetError::ParsingError("Header block ended early".to_string()))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn integer(&mut self, prefix_bits: u8) -> WgetResult<usize> {
+        let max_prefix = (1usize << prefix_bits) - 1;
+        let mut value = self.next()? as usize & max_prefix;
+        if value < max_prefix {
+            return Ok(value);
+        }
+        let mut shift = 0;
+        loop {
+            let byte = self.next()?;
+            // Anything past 28 bits is far larger than any sane header block
+            if shift > 21 {
+                return Err(WgetError::ParsingError(
+                    "Integer too large in header block".to_string(),
+                ));
+            }
+            value += ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn string(&mut self) -> WgetResult<String> {
+        let huffman_encoded = self.peek().is_some_and(|byte| byte & 0x80 != 0);
+        let length = self.integer(7)?;
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.block.len())
+            .ok_or_else(|| WgetError::ParsingError("Header block ended early".to_string()))?;
+        let bytes = &self.block[self.position..end];
+        self.position = end;
+        let bytes = if huffman_encoded {
+            huffman::decode(bytes)?
+        } else {
+            bytes.to_vec()
+        };
+        String::from_utf8(bytes)
+            .map_err(|_| WgetError::ParsingError("Header is not valid UTF-8".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).expect("Invalid hex"))
+            .collect()
+    }
+
+    fn fields(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn encodes_integers() {
+        // From RFC 7541 appendix C.1
+        let mut block = Vec::new();
+        encode_integer(&mut block, 0, 5, 10);
+        assert_eq!(block, [0b01010]);
+        block.clear();
+        encode_integer(&mut block, 0, 5, 1337);
+        assert_eq!(block, [0b11111, 0b10011010, 0b00001010]);
+        block.clear();
+        encode_integer(&mut block, 0, 8, 42);
+        assert_eq!(block, [42]);
+
+        let mut reader = BlockReader {
+            block: &[0b11111, 0b10011010, 0b00001010],
+            position: 0,
+        };
+        assert_eq!(reader.integer(5).expect("Could not decode"), 1337);
+    }
+
+    #[test]
+    fn decodes_rfc_requests() {
+        // From RFC 7541 appendix C.3 and C.4, without and with Huffman coding
+        for blocks in [
+            [
+                "828684410f7777772e6578616d706c652e636f6d",
+                "828684be58086e6f2d6361636865",
+                "828785bf400a637573746f6d2d6b65790c637573746f6d2d76616c7565",
+            ],
+            [
+                "828684418cf1e3c2e5f23a6ba0ab90f4ff",
+                "828684be5886a8eb10649cbf",
+                "828785bf408825a849e95ba97d7f8925a849e95bb8e8b4bf",
+            ],
+        ] {
+            let mut decoder = Decoder::default();
+            let decoded =
+                blocks.map(|block| decoder.decode(&hex(block)).expect("Could not decode"));
+            assert_eq!(
+                decoded[0],
+                fields(&[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                ])
+            );
+            assert_eq!(
+                decoded[1],
+                fields(&[
+                    (":method", "GET"),
+                    (":scheme", "http"),
+                    (":path", "/"),
+                    (":authority", "www.example.com"),
+                    ("cache-control", "no-cache"),
+                ])
+            );
+            assert_eq!(
+                decoded[2],
+                fields(&[
+                    (":method", "GET"),
+                    (":scheme", "https"),
+                    (":path", "/index.html"),
+                    (":authority", "www.example.com"),
+                    ("custom-key", "custom-value"),
+                ])
+            );
+            assert_eq!(decoder.table.size, 164);
+        }
+    }
+
+    #[test]
+    fn evicts_from_a_full_table() {
+        // From RFC 7541 appendix C.5, with a 256 byte table
+        let mut decoder = Decoder::new(256);
+        let date = |second| format!("Mon, 21 Oct 2013 20:13:{second} GMT");
+        let first = decoder.decode(&hex("4803333032580770726976617465611d4d6f6e2c203231204f637420323031332032303a31333a323120474d546e1768747470733a2f2f7777772e6578616d706c652e636f6d")).expect("Could not decode");
+        assert_eq!(
+            first,
+            fields(&[
+                (":status", "302"),
+                ("cache-control", "private"),
+                ("date", &date(21)),
+                ("location", "https://www.example.com"),
+            ])
+        );
+        assert_eq!(decoder.table.size, 222);
+
+        let second = decoder
+            .decode(&hex("4803333037c1c0bf"))
+            .expect("Could not decode");
+        assert_eq!(
+            second,
+            fields(&[
+                (":status", "307"),
+                ("cache-control", "private"),
+                ("date", &date(21)),
+                ("location", "https://www.example.com"),
+            ])
+        );
+        assert_eq!(decoder.table.size, 222);
+
+        let third = decoder.decode(&hex("88c1611d4d6f6e2c203231204f637420323031332032303a31333a323220474d54c05a04677a69707738666f6f3d4153444a4b48514b425a584f5157454f50495541585157454f49553b206d61782d6167653d333630303b2076657273696f6e3d31")).expect("Could not decode");
+        assert_eq!(
+            third,
+            fields(&[
+                (":status", "200"),
+                ("cache-control", "private"),
+                ("date", &date(22)),
+                ("location", "https://www.example.com"),
+                ("content-encoding", "gzip"),
+                (
+                    "set-cookie",
+                    "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1"
+                ),
+            ])
+        );
+        assert_eq!(decoder.table.size, 215);
+        assert_eq!(decoder.table.entries.len(), 3);
+    }
+
+    #[test]
+    fn round_trips_fields() {
+        let sent = [
+            (":method", "GET"),
+            (":path", "/some/file.txt"),
+            ("accept", "*/*"),
+            ("x-custom", "Some Value"),
+            ("user-agent", "Wget/1.21.3"),
+        ];
+        let block = encode(sent);
+        assert_eq!(
+            Decoder::default().decode(&block).expect("Could not decode"),
+            fields(&sent)
+        );
+    }
+
+    #[test]
+    fn rejects_bad_blocks() {
+        let mut decoder = Decoder::default();
+        // Index 0 and past the end of both tables
+        assert!(decoder.decode(&[0x80]).is_err());
+        assert!(decoder.decode(&[0xbe]).is_err());
+        // A string longer than the block
+        assert!(decoder.decode(&[0x00, 0x05, b'a']).is_err());
+        // Growing the table past what we allowed
+        assert!(decoder.decode(&hex("3fe21f")).is_err());
+        // An integer that never ends
+        assert!(decoder
+            .decode(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff])
+            .is_err());
+    }
+
+    #[test]
+    fn limits_what_blocks_decode_to() {
+        let limits = HeaderLimits {
+            max_fields: 10,
+            max_size: 1000,
+            ..HeaderLimits::default()
+        };
+        // A field added to the table, then referred to with a byte each
+        let mut block = vec![0x40];
+        encode_string(&mut block, b"x");
+        encode_string(&mut block, &[b'a'; 100]);
+        let refer = |count| {
+            let mut block = block.clone();
+            block.extend(std::iter::repeat_n(0xbe, count));
+            block
+        };
+        let decoded = Decoder::default()
+            .with_limits(limits)
+            .decode(&refer(8))
+            .expect("Could not decode");
+        assert_eq!(decoded.len(), 9);
+        assert!(Decoder::default()
+            .with_limits(limits)
+            .decode(&refer(9))
+            .is_err());
+        assert!(Decoder::default()
+            .with_limits(HeaderLimits {
+                max_fields: 100,
+                ..limits
+            })
+            .decode(&refer(9))
+            .is_err());
+        let fields = encode((0..11).map(|_| ("a", "b")));
+        assert!(Decoder::default()
+            .with_limits(limits)
+            .decode(&fields)
+            .is_err());
+    }
+}
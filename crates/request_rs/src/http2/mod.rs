@@ -0,0 +1,10 @@
+mod frame;
+mod hpack;
+mod huffman;
+mod session;
+
+pub use frame::{ErrorCode, Frame, FrameKind, Settings};
+pub use session::Session;
+
+#[cfg(test)]
+pub use session::tests;
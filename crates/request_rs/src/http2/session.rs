@@ -0,0 +1,1087 @@
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    error::{WgetError, WgetResult},
+    http::{HttpRequest, HttpResponse, HttpStatusCode, HttpStatusFamily, HttpVersion},
+    Configuration,
+};
+
+use super::{
+    frame::{ErrorCode, Frame, FrameKind, Settings},
+    hpack::{self, Decoder},
+};
+
+/// What every HTTP/2 client sends first, so servers can tell it from HTTP/1
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// How much response data the server may send ahead of what has been read,
+/// on the connection and on each stream
+const RECEIVE_WINDOW: u32 = 1 << 20;
+
+/// The flow control window every connection starts with
+const DEFAULT_WINDOW: u32 = 65_535;
+
+/// The largest frame the server may send, which is the default since we
+/// never raise it
+const MAX_FRAME_SIZE: u32 = 16_384;
+
+/// Headers that only mean something to a single HTTP/1 connection, which
+/// HTTP/2 forbids
+const CONNECTION_HEADERS: [&str; 6] = [
+    "connection",
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// The state of an HTTP/2 connection, for sending requests over a socket that
+/// has been through `Session::handshake`
+#[derive(Debug)]
+pub struct Session {
+    decoder: Decoder,
+    peer_settings: Settings,
+    next_stream_id: u32,
+    /// How much more data the server may send on the whole connection
+    receive_window: u32,
+    /// How much more request body data may be sent on the whole connection
+    send_window: i64,
+    /// Set once either side has said it is closing the connection
+    closing: bool,
+}
+
+/// A request that has been sent, waiting for its response
+#[derive(Debug)]
+struct Stream {
+    /// Where the request was in the list given to `Session::exchange`
+    index: usize,
+    response: Option<HttpResponse>,
+    data: Vec<u8>,
+    receive_window: u32,
+    /// How much of the request body has been sent
+    body_sent: usize,
+    /// How much more of the request body may be sent, which the server can
+    /// push below zero by lowering its initial window size
+    send_window: i64,
+}
+
+/// The streams of one call to `Session::exchange`
+struct Exchange<'a> {
+    requests: &'a [HttpRequest],
+    streams: HashMap<u32, Stream>,
+    results: Vec<Option<WgetResult<HttpResponse>>>,
+    progress: &'a mut dyn FnMut(usize, u64, Option<u64>),
+}
+
+impl Exchange<'_> {
+    /// Ends a stream, with its response if it got one
+    fn finish(&mut self, stream_id: u32) {
+        if let Some(stream) = self.streams.remove(&stream_id) {
+            let result = match stream.response {
+                Some(mut response) => {
+                    response.set_data(stream.data);
+                    Ok(response)
+                }
+                None => Err(WgetError::ProtocolError(
+                    "Stream ended without a response".to_string(),
+                )),
+            };
+            self.results[stream.index] = Some(result);
+        }
+    }
+
+    fn fail(&mut self, stream_id: u32, error: WgetError) {
+        if let Some(stream) = self.streams.remove(&stream_id) {
+            self.results[stream.index] = Some(Err(error));
+        }
+    }
+}
+
+impl Session {
+    /// Starts HTTP/2 on a new connection, by sending the preface and our
+    /// settings, and reading the server's
+    pub fn handshake<R: Read, W: Write>(
+        reader: &mut R,
+        writer: &mut W,
+        config: &Configuration,
+    ) -> WgetResult<Self> {
+        let mut preface = PREFACE.to_vec();
+        preface.extend(
+            Frame::settings(&[
+                (Settings::ENABLE_PUSH, 0),
+                (Settings::INITIAL_WINDOW_SIZE, RECEIVE_WINDOW),
+            ])
+            .serialize(),
+        );
+        preface.extend(Frame::window_update(0, RECEIVE_WINDOW - DEFAULT_WINDOW).serialize());
+        writer.write_all(&preface)?;
+        writer.flush()?;
+
+        // A server that doesn't speak HTTP/2 answers with something that
+        // can't be read as a frame, or isn't the SETTINGS frame it must send
+        let frame = match Self::read_frame(reader, config) {
+            Ok(frame) if frame.kind == FrameKind::Settings && !frame.has_flag(Frame::ACK) => frame,
+            Ok(_) | Err(WgetError::ProtocolError(_)) => {
+                return Err(WgetError::ProtocolError(
+                    "Server does not speak HTTP/2".to_string(),
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        let mut session = Session {
+            decoder: Decoder::default().with_limits(config.header_limits),
+            peer_settings: Settings::default(),
+            next_stream_id: 1,
+            receive_window: RECEIVE_WINDOW,
+            send_window: DEFAULT_WINDOW.into(),
+            closing: false,
+        };
+        session.apply_settings(&frame, writer, config)?;
+        Ok(session)
+    }
+
+    /// Whether the connection is closing, so no more requests can be sent on it
+    pub fn is_closing(&self) -> bool {
+        self.closing
+    }
+
+    /// Sends all of `requests` on separate streams, as many at once as the
+    /// server allows, and waits for their responses, which are returned in
+    /// the same order. `progress` is called with the index of a request as
+    /// its response body arrives, like in
+    /// `HttpResponse::receive_body_with_progress`.
+    ///
+    /// Errors that only affect one stream, like the server resetting it, are
+    /// the result for that request. Errors that break the whole connection are
+    /// returned instead of any results.
+    pub fn exchange<R: Read, W: Write>(
+        &mut self,
+        reader: &mut R,
+        writer: &mut W,
+        requests: &[HttpRequest],
+        config: &Configuration,
+        progress: &mut dyn FnMut(usize, u64, Option<u64>),
+    ) -> WgetResult<Vec<WgetResult<HttpResponse>>> {
+        let mut exchange = Exchange {
+            requests,
+            streams: HashMap::new(),
+            results: requests.iter().map(|_| None).collect(),
+            progress,
+        };
+        let mut unsent = requests.iter().enumerate();
+        loop {
+            let limit = self
+                .peer_settings
+                .max_concurrent_streams
+                .map_or(usize::MAX, |limit| limit as usize);
+            while !self.closing && exchange.streams.len() < limit {
+                let Some((index, request)) = unsent.next() else {
+                    break;
+                };
+                let stream_id = self.open(writer, request, config)?;
+                exchange.streams.insert(
+                    stream_id,
+                    Stream {
+                        index,
+                        response: None,
+                        data: Vec::new(),
+                        receive_window: RECEIVE_WINDOW,
+                        body_sent: 0,
+                        send_window: self.peer_settings.initial_window_size.into(),
+                    },
+                );
+            }
+            if exchange.streams.is_empty() {
+                break;
+            }
+            let result = self
+                .send_bodies(writer, &mut exchange, config)
+                .and_then(|_| {
+                    let frame = Self::read_frame(reader, config)?;
+                    self.handle(frame, reader, writer, &mut exchange, config)
+                });
+            if let Err(error) = result {
+                self.closing = true;
+                if !matches!(error, WgetError::Io(_)) {
+                    // Let the server know why, if it is still listening
+                    let _ = Self::send(
+                        writer,
+                        &[Frame::goaway(0, ErrorCode::PROTOCOL_ERROR)],
+                        config,
+                    );
+                }
+                return Err(error);
+            }
+        }
+        // Anything left was never sent, since the connection is closing
+        Ok(exchange
+            .results
+            .into_iter()
+            .map(|result| result.unwrap_or_else(|| Err(not_handled())))
+            .collect())
+    }
+
+    /// Sends the headers of `request` on a new stream, returning its ID
+    fn open<W: Write>(
+        &mut self,
+        writer: &mut W,
+        request: &HttpRequest,
+        config: &Configuration,
+    ) -> WgetResult<u32> {
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 2;
+
+        let mut fields = vec![
+            (":method".to_string(), request.method().to_string()),
+            (":scheme".to_string(), request.scheme().to_string()),
+            (
+                ":authority".to_string(),
+                request.get_header("Host").unwrap_or_default().to_string(),
+            ),
+            (":path".to_string(), request.path().to_string()),
+        ];
+        for (name, value) in request.headers() {
+            let name = name.to_ascii_lowercase();
+            // `TE` is only allowed to say that trailers are accepted
+            let allowed = !CONNECTION_HEADERS.contains(&name.as_str())
+                && (name != "te" || value.eq_ignore_ascii_case("trailers"));
+            if allowed {
+                fields.push((name, value.to_string()));
+            }
+        }
+        let block = hpack::encode(
+            fields
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str())),
+        );
+
+        // Blocks too big for one frame carry on in CONTINUATION frames
+        let mut chunks = block
+            .chunks(self.peer_settings.max_frame_size as usize)
+            .peekable();
+        let mut frames = Vec::new();
+        // A body follows in DATA frames, the last of which ends the stream
+        let (mut kind, mut flags) = if request.body().is_empty() {
+            (FrameKind::Headers, Frame::END_STREAM)
+        } else {
+            (FrameKind::Headers, 0)
+        };
+        while let Some(chunk) = chunks.next() {
+            if chunks.peek().is_none() {
+                flags |= Frame::END_HEADERS;
+            }
+            frames.push(Frame::new(kind, flags, stream_id, chunk.to_vec()));
+            (kind, flags) = (FrameKind::Continuation, 0);
+        }
+        Self::send(writer, &frames, config)?;
+        Ok(stream_id)
+    }
+
+    /// Sends as much of the request bodies still to go as the server's flow
+    /// control windows allow
+    fn send_bodies<W: Write>(
+        &mut self,
+        writer: &mut W,
+        exchange: &mut Exchange,
+        config: &Configuration,
+    ) -> WgetResult<()> {
+        let mut stream_ids: Vec<u32> = exchange.streams.keys().copied().collect();
+        stream_ids.sort_unstable();
+        let mut frames = Vec::new();
+        for stream_id in stream_ids {
+            let Some(stream) = exchange.streams.get_mut(&stream_id) else {
+                continue;
+            };
+            let body = exchange.requests[stream.index].body();
+            while stream.body_sent < body.len() {
+                let allowed = self.send_window.min(stream.send_window);
+                let size = (body.len() - stream.body_sent)
+                    .min(self.peer_settings.max_frame_size as usize)
+                    .min(usize::try_from(allowed).unwrap_or_default());
+                if size == 0 {
+                    break;
+                }
+                let chunk = &body[stream.body_sent..stream.body_sent + size];
+                stream.body_sent += size;
+                self.send_window -= size as i64;
+                stream.send_window -= size as i64;
+                let flags = if stream.body_sent == body.len() {
+                    Frame::END_STREAM
+                } else {
+                    0
+                };
+                frames.push(Frame::new(
+                    FrameKind::Data,
+                    flags,
+                    stream_id,
+                    chunk.to_vec(),
+                ));
+            }
+        }
+        if frames.is_empty() {
+            Ok(())
+        } else {
+            Self::send(writer, &frames, config)
+        }
+    }
+
+    fn handle<R: Read, W: Write>(
+        &mut self,
+        frame: Frame,
+        reader: &mut R,
+        writer: &mut W,
+        exchange: &mut Exchange,
+        config: &Configuration,
+    ) -> WgetResult<()> {
+        match frame.kind {
+            FrameKind::Data => self.receive_data(frame, writer, exchange, config),
+            FrameKind::Headers => {
+                let mut block = frame.content()?.to_vec();
+                let mut end_headers = frame.has_flag(Frame::END_HEADERS);
+                // Nothing else may come between HEADERS and its CONTINUATIONs
+                while !end_headers {
+                    let next = Self::read_frame(reader, config)?;
+                    if next.kind != FrameKind::Continuation || next.stream_id != frame.stream_id {
+                        return Err(WgetError::ProtocolError(format!(
+                            "Expected CONTINUATION, got {next}"
+                        )));
+                    }
+                    block.extend(&next.payload);
+                    // Or a server could keep us buffering CONTINUATIONs forever
+                    let max_size = config.header_limits.max_size;
+                    if block.len() > max_size {
+                        return Err(WgetError::ProtocolError(format!(
+                            "Header block longer than {max_size} bytes"
+                        )));
+                    }
+                    end_headers = next.has_flag(Frame::END_HEADERS);
+                }
+                let end_stream = frame.has_flag(Frame::END_STREAM);
+                self.receive_headers(
+                    frame.stream_id,
+                    &block,
+                    end_stream,
+                    writer,
+                    exchange,
+                    config,
+                )
+            }
+            FrameKind::Priority => Ok(()),
+            FrameKind::RstStream => {
+                let error = ErrorCode(frame.read_u32(0)?);
+                crate::debug!(
+                    config,
+                    "Server reset stream {} with {error}",
+                    frame.stream_id
+                );
+                let error = if error == ErrorCode::REFUSED_STREAM {
+                    not_handled()
+                } else {
+                    WgetError::ProtocolError(format!("Server reset the stream with {error}"))
+                };
+                exchange.fail(frame.stream_id, error);
+                Ok(())
+            }
+            FrameKind::Settings => {
+                let initial_window = self.peer_settings.initial_window_size;
+                self.apply_settings(&frame, writer, config)?;
+                // Changing the initial window changes the window of every
+                // stream already open by as much
+                let change =
+                    i64::from(self.peer_settings.initial_window_size) - i64::from(initial_window);
+                for stream in exchange.streams.values_mut() {
+                    stream.send_window += change;
+                }
+                Ok(())
+            }
+            FrameKind::PushPromise => Err(WgetError::ProtocolError(
+                "Server pushed a response, which was disabled".to_string(),
+            )),
+            FrameKind::Ping if frame.has_flag(Frame::ACK) => Ok(()),
+            FrameKind::Ping => Self::send(writer, &[Frame::ping_ack(frame.payload)], config),
+            FrameKind::GoAway => {
+                let last_stream_id = frame.read_u31(0)?;
+                let error = ErrorCode(frame.read_u32(4)?);
+                crate::debug!(
+                    config,
+                    "Server is closing the connection after stream {last_stream_id} with {error}"
+                );
+                self.closing = true;
+                let unhandled: Vec<u32> = exchange
+                    .streams
+                    .keys()
+                    .copied()
+                    .filter(|stream_id| *stream_id > last_stream_id)
+                    .collect();
+                for stream_id in unhandled {
+                    exchange.fail(stream_id, not_handled());
+                }
+                Ok(())
+            }
+            FrameKind::WindowUpdate => {
+                let increment = i64::from(frame.read_u31(0)?);
+                let window = if frame.stream_id == 0 {
+                    &mut self.send_window
+                } else if let Some(stream) = exchange.streams.get_mut(&frame.stream_id) {
+                    &mut stream.send_window
+                } else {
+                    return Ok(());
+                };
+                *window += increment;
+                if increment == 0 || *window > i64::from(i32::MAX) {
+                    return Err(WgetError::ProtocolError(format!(
+                        "Invalid window update of {increment}"
+                    )));
+                }
+                Ok(())
+            }
+            FrameKind::Continuation => Err(WgetError::ProtocolError(
+                "CONTINUATION frame without HEADERS".to_string(),
+            )),
+        }
+    }
+
+    fn receive_data<W: Write>(
+        &mut self,
+        frame: Frame,
+        writer: &mut W,
+        exchange: &mut Exchange,
+        config: &Configuration,
+    ) -> WgetResult<()> {
+        // Padding counts towards flow control too
+        let length = frame.payload.len() as u32;
+        self.receive_window = self
+            .receive_window
+            .checked_sub(length)
+            .ok_or_else(flow_control_error)?;
+        let mut updates = Vec::new();
+        if self.receive_window < RECEIVE_WINDOW / 2 {
+            updates.push(Frame::window_update(
+                0,
+                RECEIVE_WINDOW - self.receive_window,
+            ));
+            self.receive_window = RECEIVE_WINDOW;
+        }
+
+        // Data for streams that were reset is dropped, but still had to be
+        // counted for the connection
+        if let Some(stream) = exchange.streams.get_mut(&frame.stream_id) {
+            stream.receive_window = stream
+                .receive_window
+                .checked_sub(length)
+                .ok_or_else(flow_control_error)?;
+            let total = stream
+                .response
+                .as_ref()
+                .ok_or_else(|| WgetError::ProtocolError("DATA before HEADERS".to_string()))?
+                .content_length();
+            stream.data.extend(frame.content()?);
+            (exchange.progress)(stream.index, stream.data.len() as u64, total);
+            if frame.has_flag(Frame::END_STREAM) {
+                exchange.finish(frame.stream_id);
+            } else if stream.receive_window < RECEIVE_WINDOW / 2 {
+                updates.push(Frame::window_update(
+                    frame.stream_id,
+                    RECEIVE_WINDOW - stream.receive_window,
+                ));
+                stream.receive_window = RECEIVE_WINDOW;
+            }
+        }
+        if updates.is_empty() {
+            Ok(())
+        } else {
+            Self::send(writer, &updates, config)
+        }
+    }
+
+    fn receive_headers<W: Write>(
+        &mut self,
+        stream_id: u32,
+        block: &[u8],
+        end_stream: bool,
+        writer: &mut W,
+        exchange: &mut Exchange,
+        config: &Configuration,
+    ) -> WgetResult<()> {
+        // Every block changes the decoder's table, even ones for streams that
+        // are already gone
+        let fields = self.decoder.decode(block)?;
+        let Some(stream) = exchange.streams.get_mut(&stream_id) else {
+            return Ok(());
+        };
+        if let Some(response) = &mut stream.response {
+            // Headers after the response are its trailers
+            for (name, value) in fields {
+                response.add_trailer(name, value);
+            }
+        } else {
+            match response_from(fields) {
+                Ok(response) if response.status_family() == HttpStatusFamily::Informational => {
+                    crate::connection::log_head(&response, config);
+                    crate::debug!(
+                        config,
+                        "Skipping informational response {}",
+                        response.status_code
+                    );
+                    return Ok(());
+                }
+                Ok(response) => stream.response = Some(response),
+                Err(error) => {
+                    // A malformed response only breaks its own stream
+                    exchange.fail(stream_id, error);
+                    return Self::send(
+                        writer,
+                        &[Frame::rst_stream(stream_id, ErrorCode::PROTOCOL_ERROR)],
+                        config,
+                    );
+                }
+            }
+        }
+        if end_stream {
+            exchange.finish(stream_id);
+        }
+        Ok(())
+    }
+
+    fn apply_settings<W: Write>(
+        &mut self,
+        frame: &Frame,
+        writer: &mut W,
+        config: &Configuration,
+    ) -> WgetResult<()> {
+        if frame.has_flag(Frame::ACK) {
+            return Ok(());
+        }
+        self.peer_settings.apply(&frame.payload)?;
+        crate::trace!(config, "Server settings: {:?}", self.peer_settings);
+        Self::send(writer, &[Frame::settings_ack()], config)
+    }
+
+    fn read_frame<R: Read>(reader: &mut R, config: &Configuration) -> WgetResult<Frame> {
+        let frame = Frame::read(reader, MAX_FRAME_SIZE)?;
+        crate::trace!(config, "Received {frame}");
+        Ok(frame)
+    }
+
+    fn send<W: Write>(writer: &mut W, frames: &[Frame], config: &Configuration) -> WgetResult<()> {
+        let mut bytes = Vec::new();
+        for frame in frames {
+            crate::trace!(config, "Sending {frame}");
+            bytes.extend(frame.serialize());
+        }
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+fn response_from(fields: Vec<(String, String)>) -> WgetResult<HttpResponse> {
+    let status_code = fields
+        .iter()
+        .find(|(name, _)| name == ":status")
+        .ok_or_else(|| WgetError::ProtocolError("Response has no :status".to_string()))?
+        .1
+        .parse::<HttpStatusCode>()?;
+    let mut response = HttpResponse::new(
+        HttpVersion::Version2_0,
+        status_code,
+        status_code.reason_phrase().to_string(),
+    );
+    for (name, value) in fields {
+        if !name.starts_with(':') {
+            response.add_header(name, value);
+        }
+    }
+    Ok(response)
+}
+
+/// The error for a request the server closed the stream of without handling
+/// it, which is safe to send again on a new connection
+fn not_handled() -> WgetError {
+    io::Error::new(
+        io::ErrorKind::ConnectionAborted,
+        "Server closed the stream without handling the request",
+    )
+    .into()
+}
+
+fn flow_control_error() -> WgetError {
+    WgetError::ProtocolError("Server sent more data than the flow control window".to_string())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use std::{os::unix::net::UnixStream, thread, time::Duration};
+
+    use crate::{http::HttpMethod, protocol::Protocol};
+
+    use super::*;
+
+    /// The server end of an HTTP/2 connection, played by a test
+    pub struct Peer<S> {
+        socket: S,
+        decoder: Decoder,
+    }
+
+    impl<S: Read + Write> Peer<S> {
+        /// Reads the client's preface, and sends `settings` as the server's
+        pub fn accept(mut socket: S, settings: &[(u16, u32)]) -> Self {
+            let mut preface = [0u8; PREFACE.len()];
+            socket
+                .read_exact(&mut preface)
+                .expect("Could not read preface");
+            assert_eq!(preface, PREFACE);
+            let mut peer = Peer {
+                socket,
+                decoder: Decoder::default(),
+            };
+            peer.send(Frame::settings(settings));
+            peer
+        }
+
+        pub fn send(&mut self, frame: Frame) {
+            self.socket
+                .write_all(&frame.serialize())
+                .expect("Could not send frame");
+        }
+
+        pub fn read_frame(&mut self) -> Frame {
+            Frame::read(&mut self.socket, MAX_FRAME_SIZE).expect("Could not read frame")
+        }
+
+        /// Reads frames up to the next request, returning its stream and
+        /// header fields
+        pub fn read_request(&mut self) -> (u32, Vec<(String, String)>) {
+            loop {
+                let frame = self.read_frame();
+                match frame.kind {
+                    FrameKind::Headers => {
+                        let mut block = frame.content().expect("Invalid HEADERS").to_vec();
+                        let mut end_headers = frame.has_flag(Frame::END_HEADERS);
+                        while !end_headers {
+                            let next = self.read_frame();
+                            assert_eq!(next.kind, FrameKind::Continuation);
+                            block.extend(&next.payload);
+                            end_headers = next.has_flag(Frame::END_HEADERS);
+                        }
+                        let fields = self.decoder.decode(&block).expect("Invalid header block");
+                        return (frame.stream_id, fields);
+                    }
+                    FrameKind::Settings if !frame.has_flag(Frame::ACK) => {
+                        self.send(Frame::settings_ack())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        pub fn send_headers(&mut self, stream_id: u32, fields: &[(&str, &str)], end_stream: bool) {
+            let mut flags = Frame::END_HEADERS;
+            if end_stream {
+                flags |= Frame::END_STREAM;
+            }
+            let block = hpack::encode(fields.iter().copied());
+            self.send(Frame::new(FrameKind::Headers, flags, stream_id, block));
+        }
+
+        pub fn send_data(&mut self, stream_id: u32, data: &[u8], end_stream: bool) {
+            let flags = if end_stream { Frame::END_STREAM } else { 0 };
+            self.send(Frame::new(FrameKind::Data, flags, stream_id, data.to_vec()));
+        }
+
+        pub fn respond(&mut self, stream_id: u32, status: &str, body: &[u8]) {
+            let length = body.len().to_string();
+            self.send_headers(
+                stream_id,
+                &[(":status", status), ("content-length", &length)],
+                false,
+            );
+            self.send_data(stream_id, body, true);
+        }
+    }
+
+    /// Runs `serve` as the server on one end of a socket pair, and returns a
+    /// session on the other
+    fn connect<F>(
+        settings: &'static [(u16, u32)],
+        serve: F,
+    ) -> (Session, UnixStream, thread::JoinHandle<()>)
+    where
+        F: FnOnce(Peer<UnixStream>) + Send + 'static,
+    {
+        let (client, server) = UnixStream::pair().expect("Could not create sockets");
+        let server = thread::spawn(move || serve(Peer::accept(server, settings)));
+        let session = Session::handshake(&mut &client, &mut &client, &Configuration::default())
+            .expect("Handshake failed");
+        (session, client, server)
+    }
+
+    fn get(path: &str) -> HttpRequest {
+        let mut request = HttpRequest::new(HttpMethod::Get, path, HttpVersion::Version2_0);
+        request.add_header("Host", "example.com");
+        request.add_header("Accept", "*/*");
+        request
+    }
+
+    fn exchange(
+        session: &mut Session,
+        client: &UnixStream,
+        requests: &[HttpRequest],
+    ) -> WgetResult<Vec<WgetResult<HttpResponse>>> {
+        session.exchange(
+            &mut &*client,
+            &mut &*client,
+            requests,
+            &Configuration::default(),
+            &mut |_, _, _| {},
+        )
+    }
+
+    fn body(result: &WgetResult<HttpResponse>) -> &[u8] {
+        result.as_ref().expect("Request failed").get_data()
+    }
+
+    #[test]
+    fn sends_request_and_reads_response() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            let (stream_id, fields) = peer.read_request();
+            assert_eq!(stream_id, 1);
+            let field = |name: &str| {
+                fields
+                    .iter()
+                    .find(|(field_name, _)| field_name == name)
+                    .map(|(_, value)| value.as_str())
+            };
+            assert_eq!(field(":method"), Some("GET"));
+            assert_eq!(field(":scheme"), Some("http"));
+            assert_eq!(field(":authority"), Some("example.com"));
+            assert_eq!(field(":path"), Some("/file"));
+            assert_eq!(field("accept"), Some("*/*"));
+            assert_eq!(field("connection"), None);
+            assert_eq!(field("te"), Some("trailers"));
+            assert_eq!(field("host"), None);
+
+            peer.send_headers(
+                1,
+                &[
+                    (":status", "200"),
+                    ("content-length", "10"),
+                    ("content-type", "text/plain"),
+                ],
+                false,
+            );
+            peer.send_data(1, b"hello", false);
+            peer.send_data(1, b"world", true);
+        });
+        let mut request = get("/file");
+        request.add_header("Connection", "Keep-Alive");
+        request.accept_trailers();
+        let mut updates = Vec::new();
+        let mut responses = session
+            .exchange(
+                &mut &client,
+                &mut &client,
+                &[request],
+                &Configuration::default(),
+                &mut |index, received, total| updates.push((index, received, total)),
+            )
+            .expect("Connection failed");
+        server.join().expect("Server failed");
+
+        let response = responses.remove(0).expect("Request failed");
+        assert_eq!(response.version, HttpVersion::Version2_0);
+        assert_eq!(response.status_code, HttpStatusCode::Ok);
+        assert_eq!(response.status_message, "OK");
+        assert_eq!(response.get_header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.get_data(), b"helloworld");
+        assert_eq!(updates, [(0, 5, Some(10)), (0, 10, Some(10))]);
+    }
+
+    #[test]
+    fn sends_the_scheme_of_the_url() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            let (_, fields) = peer.read_request();
+            assert!(fields.contains(&(":scheme".to_string(), "https".to_string())));
+            peer.send_headers(1, &[(":status", "200")], true);
+        });
+        let mut request = get("/file");
+        request.set_scheme(Protocol::Https);
+        exchange(&mut session, &client, &[request]).expect("Connection failed");
+        server.join().expect("Server failed");
+    }
+
+    #[test]
+    fn multiplexes_requests() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            let requests: Vec<u32> = (0..3).map(|_| peer.read_request().0).collect();
+            assert_eq!(requests, [1, 3, 5]);
+            // Answered out of order, with the bodies interleaved
+            peer.send_headers(5, &[(":status", "200")], false);
+            peer.send_headers(1, &[(":status", "200")], false);
+            peer.send_data(1, b"fir", false);
+            peer.send_data(5, b"third", true);
+            peer.send_headers(3, &[(":status", "404")], true);
+            peer.send_data(1, b"st", true);
+        });
+        let responses = exchange(&mut session, &client, &[get("/1"), get("/2"), get("/3")])
+            .expect("Connection failed");
+        server.join().expect("Server failed");
+
+        assert_eq!(body(&responses[0]), b"first");
+        assert_eq!(
+            responses[1].as_ref().expect("Request failed").status_code,
+            HttpStatusCode::NotFound
+        );
+        assert_eq!(body(&responses[2]), b"third");
+    }
+
+    #[test]
+    fn keeps_under_the_servers_stream_limit() {
+        const SETTINGS: &[(u16, u32)] = &[(Settings::MAX_CONCURRENT_STREAMS, 1)];
+        let (mut session, client, server) = connect(SETTINGS, |mut peer| {
+            for stream_id in [1, 3] {
+                assert_eq!(peer.read_request().0, stream_id);
+                // The next request must wait until this one is answered
+                thread::sleep(Duration::from_millis(50));
+                peer.socket
+                    .set_nonblocking(true)
+                    .expect("Could not set non-blocking");
+                let pending = peer.socket.read(&mut [0u8]);
+                assert!(
+                    matches!(pending, Err(ref e) if e.kind() == io::ErrorKind::WouldBlock),
+                    "Client sent more than allowed"
+                );
+                peer.socket
+                    .set_nonblocking(false)
+                    .expect("Could not set blocking");
+                peer.respond(stream_id, "200", b"ok");
+            }
+        });
+        let responses =
+            exchange(&mut session, &client, &[get("/1"), get("/2")]).expect("Connection failed");
+        server.join().expect("Server failed");
+        assert!(responses.iter().all(|response| body(response) == b"ok"));
+    }
+
+    #[test]
+    fn keeps_the_window_open_and_answers_pings() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            let (stream_id, _) = peer.read_request();
+            peer.send(Frame::new(FrameKind::Ping, 0, 0, b"12345678".to_vec()));
+            peer.send_headers(stream_id, &[(":status", "200")], false);
+            // All the data the window allows, which can't be sent again until
+            // the client makes room
+            for _ in 0..RECEIVE_WINDOW / MAX_FRAME_SIZE {
+                peer.send_data(stream_id, &[b'a'; MAX_FRAME_SIZE as usize], false);
+            }
+            let (mut pinged, mut connection_update, mut stream_update) = (false, false, false);
+            while !(pinged && connection_update && stream_update) {
+                let frame = peer.read_frame();
+                match frame.kind {
+                    FrameKind::Ping => {
+                        assert!(frame.has_flag(Frame::ACK));
+                        assert_eq!(frame.payload, b"12345678");
+                        pinged = true;
+                    }
+                    FrameKind::WindowUpdate if frame.stream_id == 0 => connection_update = true,
+                    FrameKind::WindowUpdate => stream_update = true,
+                    _ => {}
+                }
+            }
+            peer.send_data(stream_id, b"end", true);
+        });
+        let responses = exchange(&mut session, &client, &[get("/big")]).expect("Connection failed");
+        server.join().expect("Server failed");
+        assert_eq!(body(&responses[0]).len(), RECEIVE_WINDOW as usize + 3);
+    }
+
+    #[test]
+    fn sends_bodies_within_the_servers_windows() {
+        let (mut session, client, server) =
+            connect(&[(Settings::INITIAL_WINDOW_SIZE, 10)], |mut peer| {
+                let (stream_id, fields) = peer.read_request();
+                assert!(fields.contains(&(":method".to_string(), "POST".to_string())));
+                let mut body: Vec<u8> = Vec::new();
+                let mut window = 10;
+                let mut ended = false;
+                while !ended {
+                    let frame = peer.read_frame();
+                    if frame.kind != FrameKind::Data {
+                        continue;
+                    }
+                    body.extend(frame.content().expect("Invalid DATA"));
+                    ended = frame.has_flag(Frame::END_STREAM);
+                    // Nothing more than the stream's window until the server
+                    // makes room
+                    assert!(body.len() <= window);
+                    if body.len() == window && !ended {
+                        peer.send(Frame::window_update(stream_id, 15));
+                        window += 15;
+                    }
+                }
+                assert_eq!(body, b"abcdefghijklmnopqrstuvwxy");
+                peer.respond(stream_id, "201", b"");
+            });
+        let mut request = HttpRequest::new(HttpMethod::Post, "/upload", HttpVersion::Version2_0);
+        request.add_header("Host", "example.com");
+        request.set_body(b"abcdefghijklmnopqrstuvwxy".to_vec());
+        let responses = exchange(&mut session, &client, &[request]).expect("Connection failed");
+        server.join().expect("Server failed");
+        let response = responses[0].as_ref().expect("Request failed");
+        assert_eq!(response.status_code, HttpStatusCode::Created);
+    }
+
+    #[test]
+    fn rejects_empty_window_updates() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            let (stream_id, _) = peer.read_request();
+            peer.send(Frame::window_update(stream_id, 0));
+        });
+        assert!(exchange(&mut session, &client, &[get("/")]).is_err());
+        server.join().expect("Server failed");
+    }
+
+    #[test]
+    fn reset_streams_fail_alone() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            peer.read_request();
+            peer.read_request();
+            peer.send(Frame::rst_stream(1, ErrorCode::INTERNAL_ERROR));
+            peer.respond(3, "200", b"ok");
+        });
+        let responses =
+            exchange(&mut session, &client, &[get("/1"), get("/2")]).expect("Connection failed");
+        server.join().expect("Server failed");
+        assert!(
+            matches!(&responses[0], Err(WgetError::ProtocolError(message)) if message.contains("INTERNAL_ERROR"))
+        );
+        assert_eq!(body(&responses[1]), b"ok");
+        assert!(!session.is_closing());
+    }
+
+    #[test]
+    fn goaway_refuses_unhandled_streams() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            peer.read_request();
+            peer.read_request();
+            peer.send(Frame::goaway(1, ErrorCode::NO_ERROR));
+            peer.respond(1, "200", b"ok");
+        });
+        let responses =
+            exchange(&mut session, &client, &[get("/1"), get("/2")]).expect("Connection failed");
+        server.join().expect("Server failed");
+        let refused = |result: &WgetResult<HttpResponse>| matches!(result, Err(WgetError::Io(e)) if e.kind() == io::ErrorKind::ConnectionAborted);
+        assert_eq!(body(&responses[0]), b"ok");
+        assert!(refused(&responses[1]));
+        assert!(session.is_closing());
+
+        let responses = exchange(&mut session, &client, &[get("/3")]).expect("Connection failed");
+        assert!(refused(&responses[0]));
+    }
+
+    #[test]
+    fn reads_continuations_trailers_and_skips_informational_responses() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            let (stream_id, _) = peer.read_request();
+            peer.send_headers(
+                stream_id,
+                &[(":status", "103"), ("link", "</a.css>")],
+                false,
+            );
+            let block = hpack::encode([
+                (":status", "200"),
+                ("content-type", "text/html"),
+                ("x-long", "a fairly long header value"),
+            ]);
+            let (first, rest) = block.split_at(block.len() / 2);
+            peer.send(Frame::new(FrameKind::Headers, 0, stream_id, first.to_vec()));
+            peer.send(Frame::new(
+                FrameKind::Continuation,
+                Frame::END_HEADERS,
+                stream_id,
+                rest.to_vec(),
+            ));
+            peer.send_data(stream_id, b"<html>", false);
+            peer.send_headers(stream_id, &[("checksum", "abc")], true);
+        });
+        let responses = exchange(&mut session, &client, &[get("/")]).expect("Connection failed");
+        server.join().expect("Server failed");
+        let response = responses[0].as_ref().expect("Request failed");
+        assert_eq!(response.status_code, HttpStatusCode::Ok);
+        assert_eq!(response.get_header("content-type"), Some("text/html"));
+        assert_eq!(
+            response.get_header("x-long"),
+            Some("a fairly long header value")
+        );
+        assert_eq!(response.get_header("link"), None);
+        assert_eq!(response.get_data(), b"<html>");
+        assert_eq!(response.get_trailer("checksum"), Some("abc"));
+    }
+
+    #[test]
+    fn pushes_break_the_connection() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            let (stream_id, _) = peer.read_request();
+            peer.send(Frame::new(
+                FrameKind::PushPromise,
+                Frame::END_HEADERS,
+                stream_id,
+                vec![0, 0, 0, 2],
+            ));
+            loop {
+                let frame = peer.read_frame();
+                if frame.kind == FrameKind::GoAway {
+                    assert_eq!(
+                        frame.read_u32(4).expect("No error code"),
+                        ErrorCode::PROTOCOL_ERROR.0
+                    );
+                    break;
+                }
+            }
+        });
+        let result = exchange(&mut session, &client, &[get("/")]);
+        server.join().expect("Server failed");
+        assert!(matches!(result, Err(WgetError::ProtocolError(_))));
+        assert!(session.is_closing());
+    }
+
+    #[test]
+    fn limits_continuations() {
+        let (mut session, client, server) = connect(&[], |mut peer| {
+            let (stream_id, _) = peer.read_request();
+            let block = hpack::encode([(":status", "200")]);
+            peer.send(Frame::new(FrameKind::Headers, 0, stream_id, block));
+            // Never ending the header block
+            for _ in 0..5 {
+                peer.send(Frame::new(
+                    FrameKind::Continuation,
+                    0,
+                    stream_id,
+                    vec![0; 16 * 1024],
+                ));
+            }
+            while peer.read_frame().kind != FrameKind::GoAway {}
+        });
+        let result = exchange(&mut session, &client, &[get("/")]);
+        server.join().expect("Server failed");
+        assert!(
+            matches!(result, Err(WgetError::ProtocolError(message)) if message.contains("longer than"))
+        );
+    }
+
+    #[test]
+    fn handshake_fails_for_http1_servers() {
+        let (client, mut server) = UnixStream::pair().expect("Could not create sockets");
+        server
+            .write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")
+            .expect("Could not write response");
+        let result = Session::handshake(&mut &client, &mut &client, &Configuration::default());
+        assert!(
+            matches!(result, Err(WgetError::ProtocolError(message)) if message.contains("HTTP/2"))
+        );
+    }
+}
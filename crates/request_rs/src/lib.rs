@@ -2,10 +2,43 @@ pub mod connection;
 pub mod connection_cache;
 pub mod error;
 pub mod http;
+pub mod http2;
+pub mod log;
 pub mod protocol;
+pub mod rate_limit;
 pub mod url;
 
+use std::sync::Arc;
+
+use http::{HeaderLimits, HttpVersion};
+use log::Logger;
+use rate_limit::RateLimiter;
+
 #[derive(Debug, Default)]
 pub struct Configuration {
-    pub debug: u8,
+    /// Where diagnostics go, and how many of them
+    pub logger: Arc<Logger>,
+    /// Limits the download speed of every connection made with this configuration, together
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+    /// Speak HTTP/2 on new connections straight away, without negotiating it,
+    /// for servers known to support it
+    pub http2_prior_knowledge: bool,
+    /// How much of a response head is read before giving up on the server
+    pub header_limits: HeaderLimits,
+    /// The version of HTTP/1 requests are sent as, 1.0 or 1.1
+    pub http_version: HttpVersion,
+    /// Ask servers to close each connection after one response, instead of
+    /// keeping it open for the next request
+    pub no_keep_alive: bool,
+    /// Take a first response on a connection that doesn't start with a
+    /// status line to be an HTTP/0.9 response, which is nothing but a body ended by closing the
+    /// connection. Without this such responses are errors, as something that
+    /// isn't HTTP at all would otherwise be taken for a download.
+    pub accept_http09: bool,
+    /// Headers added to every request, each replacing the default header of
+    /// the same name, or removing it if the value is empty
+    pub headers: Vec<(String, String)>,
+    /// Log the status line and headers of every response, without turning on
+    /// debug output
+    pub server_response: bool,
 }
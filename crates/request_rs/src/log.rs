@@ -0,0 +1,247 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+/// How important a message is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    /// Errors and warnings, shown unless quiet
+    Error,
+    /// One line reports of what happened, like a file being saved. Only shown
+    /// when not verbose, since verbose output already says the same at length.
+    Brief,
+    /// Everything else a user would want to see
+    Info,
+    Debug,
+    /// Details of every line sent and received
+    Trace,
+}
+
+/// How much to log, from wget's `-q`, `-nv`, `-v` and `-d`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Verbosity {
+    Quiet,
+    NotVerbose,
+    #[default]
+    Verbose,
+    /// Debug output, with trace output too if given more than once
+    Debug(u8),
+}
+
+impl Verbosity {
+    pub fn shows(self, level: Level) -> bool {
+        match (self, level) {
+            (Verbosity::Quiet, _) => false,
+            (_, Level::Error) => true,
+            (Verbosity::NotVerbose, level) => level == Level::Brief,
+            (_, Level::Brief) => false,
+            (Verbosity::Verbose, level) => level == Level::Info,
+            (Verbosity::Debug(count), Level::Trace) => count > 1,
+            (Verbosity::Debug(_), _) => true,
+        }
+    }
+}
+
+/// Where messages go and which are kept. Each message comes from a target,
+/// the path of the module that logged it, and targets can be given their own
+/// verbosity to single out the parts of interest.
+pub struct Logger {
+    verbosity: Verbosity,
+    /// Verbosities for targets starting with each prefix, overriding `verbosity`
+    targets: Vec<(String, Verbosity)>,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Logger::new(Verbosity::default(), Box::new(io::stderr()))
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logger")
+            .field("verbosity", &self.verbosity)
+            .field("targets", &self.targets)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Logger {
+    pub fn new(verbosity: Verbosity, output: Box<dyn Write + Send>) -> Self {
+        Logger {
+            verbosity,
+            targets: Vec::new(),
+            output: Mutex::new(output),
+        }
+    }
+
+    /// Logs messages from `target` and the modules within it at `verbosity`
+    /// instead. The longest matching target wins.
+    pub fn set_target_verbosity(&mut self, target: &str, verbosity: Verbosity) {
+        self.targets.push((target.to_string(), verbosity));
+    }
+
+    pub fn verbosity(&self) -> Verbosity {
+        self.verbosity
+    }
+
+    /// Whether a message at `level` from `target` would be logged
+    pub fn enabled(&self, level: Level, target: &str) -> bool {
+        self.targets
+            .iter()
+            .filter(|(prefix, _)| is_within(target, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map_or(self.verbosity, |(_, verbosity)| *verbosity)
+            .shows(level)
+    }
+
+    /// Writes a line, whatever its level. Use the macros like `info!` instead,
+    /// which check the level first.
+    pub fn log(&self, message: fmt::Arguments) {
+        let mut output = self.output.lock().expect("Log output lock poisoned");
+        // There is nowhere left to report a failure to log
+        let _ = writeln!(output, "{message}").and_then(|_| output.flush());
+    }
+
+    fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut output = self.output.lock().expect("Log output lock poisoned");
+        output.write_all(bytes)?;
+        output.flush()
+    }
+}
+
+/// Whether the module path `target` is `prefix` or inside it
+fn is_within(target: &str, prefix: &str) -> bool {
+    target
+        .strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+/// Writes straight to a logger's output, for text that isn't made of whole
+/// lines, like a progress bar
+#[derive(Debug, Clone)]
+pub struct LogWriter(pub Arc<Logger>);
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Logs a message at a level through the logger of a `Configuration`, e.g.
+/// `log!(config, Level::Info, "Connecting to {host}")`
+#[macro_export]
+macro_rules! log {
+    ($config:expr, $level:expr, $($arg:tt)+) => {{
+        let logger = &$config.logger;
+        if logger.enabled($level, module_path!()) {
+            logger.log(format_args!($($arg)+));
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! error {
+    ($config:expr, $($arg:tt)+) => { $crate::log!($config, $crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! brief {
+    ($config:expr, $($arg:tt)+) => { $crate::log!($config, $crate::log::Level::Brief, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($config:expr, $($arg:tt)+) => { $crate::log!($config, $crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($config:expr, $($arg:tt)+) => { $crate::log!($config, $crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($config:expr, $($arg:tt)+) => { $crate::log!($config, $crate::log::Level::Trace, $($arg)+) };
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Collects everything logged, to check afterwards
+    #[derive(Clone, Default)]
+    pub struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().expect("Lock poisoned").extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        pub fn text(&self) -> String {
+            String::from_utf8(self.0.lock().expect("Lock poisoned").clone()).expect("Not UTF-8")
+        }
+    }
+
+    #[test]
+    fn verbosity_decides_what_is_shown() {
+        use Level::*;
+        let shown = |verbosity: Verbosity| {
+            [Error, Brief, Info, Debug, Trace]
+                .into_iter()
+                .filter(|level| verbosity.shows(*level))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(shown(Verbosity::Quiet), []);
+        assert_eq!(shown(Verbosity::NotVerbose), [Error, Brief]);
+        assert_eq!(shown(Verbosity::Verbose), [Error, Info]);
+        assert_eq!(shown(Verbosity::Debug(1)), [Error, Info, Debug]);
+        assert_eq!(shown(Verbosity::Debug(2)), [Error, Info, Debug, Trace]);
+    }
+
+    #[test]
+    fn targets_override_verbosity() {
+        let mut logger = Logger::new(Verbosity::Verbose, Box::new(io::sink()));
+        logger.set_target_verbosity("request_rs::http", Verbosity::Debug(2));
+        logger.set_target_verbosity("request_rs::http::response", Verbosity::Quiet);
+        assert!(!logger.enabled(Level::Debug, "request_rs::connection"));
+        assert!(logger.enabled(Level::Trace, "request_rs::http::request"));
+        assert!(!logger.enabled(Level::Error, "request_rs::http::response"));
+        assert!(!logger.enabled(Level::Debug, "request_rs::httpx"));
+    }
+
+    #[test]
+    fn logs_enabled_messages() {
+        #[derive(Debug)]
+        struct Config {
+            logger: Logger,
+        }
+        let captured = Captured::default();
+        let config = Config {
+            logger: Logger::new(Verbosity::NotVerbose, Box::new(captured.clone())),
+        };
+        crate::info!(config, "Connecting to {}", "example.com");
+        crate::brief!(config, "Saved {}", "index.html");
+        let mut writer = LogWriter(Arc::new(Logger::new(
+            Verbosity::Quiet,
+            Box::new(captured.clone()),
+        )));
+        write!(writer, "[===>   ]\r").expect("Could not write");
+        assert_eq!(captured.text(), "Saved index.html\n[===>   ]\r");
+    }
+}
@@ -1,8 +1,8 @@
-use std::str::FromStr;
+use std::{fmt, str::FromStr};
 
 use crate::error::{WgetError, WgetResult};
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Protocol {
     // TODO allow https?
     Http,
@@ -33,6 +33,16 @@ impl FromStr for Protocol {
     }
 }
 
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Protocol::Http => write!(f, "http"),
+            Protocol::Https => write!(f, "https"),
+            Protocol::Ftp => write!(f, "ftp"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -54,4 +64,14 @@ mod tests {
             Err(WgetError::ParsingError(_))
         ));
     }
+
+    #[test]
+    fn displays_protocol_as_it_is_parsed() {
+        for protocol in [Protocol::Http, Protocol::Https, Protocol::Ftp] {
+            assert_eq!(
+                protocol.to_string().parse::<Protocol>().ok(),
+                Some(protocol)
+            );
+        }
+    }
 }
@@ -0,0 +1,239 @@
+use std::{
+    fmt,
+    io::{self, Read},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// A source of time for a `RateLimiter`, so that tests don't have to wait
+pub trait Clock: Send + Sync + fmt::Debug {
+    /// The time elapsed since some fixed point, which must never go backwards
+    fn now(&self) -> Duration;
+    /// Blocks until `now()` has reached `deadline`
+    fn sleep_until(&self, deadline: Duration);
+}
+
+/// The real clock, measured from when it was created
+#[derive(Debug)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn sleep_until(&self, deadline: Duration) {
+        thread::sleep(deadline.saturating_sub(self.now()))
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// May go negative, when readers have taken more than was available and
+    /// are sleeping off the debt
+    tokens: f64,
+    last_refill: Duration,
+}
+
+/// A token bucket limiting how many bytes per second may be read
+///
+/// A single limiter can be shared between any number of connections and
+/// threads, in which case the limit applies to all of them together.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_second: u64,
+    capacity: f64,
+    clock: Arc<dyn Clock>,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self::with_clock(bytes_per_second, Arc::new(SystemClock::default()))
+    }
+
+    pub fn with_clock(bytes_per_second: u64, clock: Arc<dyn Clock>) -> Self {
+        let bytes_per_second = bytes_per_second.max(1);
+        let now = clock.now();
+        RateLimiter {
+            bytes_per_second,
+            // Allow bursts of up to a tenth of a second's worth of data, so
+            // reads are spread out evenly rather than arriving once a second
+            capacity: (bytes_per_second as f64 / 10.0).max(1.0),
+            clock,
+            bucket: Mutex::new(Bucket {
+                tokens: 0.0,
+                last_refill: now,
+            }),
+        }
+    }
+
+    pub fn bytes_per_second(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /// The most that should be read at once, so a single read doesn't turn
+    /// into one long sleep
+    pub fn max_read_size(&self) -> usize {
+        self.capacity as usize
+    }
+
+    /// Takes `bytes` from the bucket, sleeping until they've been paid for
+    pub fn consume(&self, bytes: usize) {
+        let (now, wait) = {
+            let mut bucket = self.bucket.lock().expect("Rate limiter lock poisoned");
+            let now = self.clock.now();
+            let elapsed = now.saturating_sub(bucket.last_refill);
+            bucket.last_refill = now;
+            bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.bytes_per_second as f64)
+                .min(self.capacity);
+            bucket.tokens -= bytes as f64;
+            if bucket.tokens < 0.0 {
+                let wait = Duration::from_secs_f64(-bucket.tokens / self.bytes_per_second as f64);
+                (now, wait)
+            } else {
+                (now, Duration::ZERO)
+            }
+        };
+        if !wait.is_zero() {
+            self.clock.sleep_until(now + wait);
+        }
+    }
+}
+
+/// Wraps a reader so that reads are limited by a `RateLimiter`, if there is one
+#[derive(Debug)]
+pub struct ThrottledReader<R> {
+    inner: R,
+    limiter: Option<Arc<RateLimiter>>,
+}
+
+impl<R: Read> ThrottledReader<R> {
+    pub fn new(inner: R, limiter: Option<Arc<RateLimiter>>) -> Self {
+        ThrottledReader { inner, limiter }
+    }
+}
+
+impl<R: Read> Read for ThrottledReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &self.limiter {
+            Some(limiter) => {
+                let max = buf.len().min(limiter.max_read_size());
+                let read = self.inner.read(&mut buf[..max])?;
+                limiter.consume(read);
+                Ok(read)
+            }
+            None => self.inner.read(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A clock where sleeping just moves time forward. Threads sleeping at
+    /// the same time overlap, just like they would with a real clock.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockClock {
+        now: Mutex<Duration>,
+    }
+
+    impl MockClock {
+        pub(crate) fn advance(&self, duration: Duration) {
+            *self.now.lock().expect("Clock lock poisoned") += duration;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Duration {
+            *self.now.lock().expect("Clock lock poisoned")
+        }
+
+        fn sleep_until(&self, deadline: Duration) {
+            let mut now = self.now.lock().expect("Clock lock poisoned");
+            *now = (*now).max(deadline);
+        }
+    }
+
+    /// Checks that moving `bytes` took as long as `bytes_per_second` allows,
+    /// give or take the burst the bucket allows
+    pub(crate) fn assert_throughput(clock: &MockClock, bytes: usize, bytes_per_second: u64) {
+        let elapsed = clock.now().as_secs_f64();
+        let expected = bytes as f64 / bytes_per_second as f64;
+        assert!(
+            (expected - 0.1..=expected + 0.001).contains(&elapsed),
+            "{bytes} bytes at {bytes_per_second}B/s took {elapsed}s, expected {expected}s"
+        );
+    }
+
+    #[test]
+    fn does_not_wait_within_capacity() {
+        let clock = Arc::new(MockClock::default());
+        let limiter = RateLimiter::with_clock(1000, clock.clone());
+        // Let the bucket fill up
+        clock.advance(Duration::from_secs(1));
+        limiter.consume(limiter.max_read_size());
+        assert_eq!(clock.now(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn waits_for_tokens() {
+        let clock = Arc::new(MockClock::default());
+        let limiter = RateLimiter::with_clock(1000, clock.clone());
+        limiter.consume(500);
+        assert_eq!(clock.now(), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn limits_reads() {
+        let clock = Arc::new(MockClock::default());
+        let limiter = Arc::new(RateLimiter::with_clock(1000, clock.clone()));
+        let data = vec![0u8; 5000];
+        let mut reader = ThrottledReader::new(&data[..], Some(limiter));
+        let mut out = Vec::new();
+        reader.read_to_end(&mut out).expect("Could not read");
+        assert_eq!(out, data);
+        assert_throughput(&clock, data.len(), 1000);
+    }
+
+    #[test]
+    fn limit_is_shared_between_readers() {
+        let clock = Arc::new(MockClock::default());
+        let limiter = Arc::new(RateLimiter::with_clock(1000, clock.clone()));
+        let data = vec![0u8; 2000];
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let limiter = limiter.clone();
+                let data = data.clone();
+                thread::spawn(move || {
+                    let mut reader = ThrottledReader::new(&data[..], Some(limiter));
+                    io::copy(&mut reader, &mut io::sink()).expect("Could not read")
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("Reader panicked");
+        }
+        assert_throughput(&clock, data.len() * 2, 1000);
+    }
+
+    #[test]
+    fn reads_without_limiter_are_untouched() {
+        let data = vec![1u8; 100_000];
+        let mut reader = ThrottledReader::new(&data[..], None);
+        let mut buf = vec![0u8; data.len()];
+        assert_eq!(reader.read(&mut buf).expect("Could not read"), data.len());
+    }
+}
@@ -0,0 +1,60 @@
+use std::ffi::OsString;
+
+/// wget's short options that are more than one letter long, which clap can't
+/// parse, along with the long option each is short for
+const MULTI_LETTER_OPTIONS: &[(&str, &str)] = &[
+    ("-nc", "--no-clobber"),
+    ("-nd", "--no-directories"),
+    ("-nH", "--no-host-directories"),
+    ("-nv", "--no-verbose"),
+];
+
+/// Replaces wget's multi-letter short options like `-nc` in `args` with their
+/// long forms. Anything after `--` is left alone, since it can only be a URL.
+pub fn expand_short_options<I>(args: I) -> Vec<OsString>
+where
+    I: IntoIterator<Item = OsString>,
+{
+    let mut options_ended = false;
+    args.into_iter()
+        .map(|arg| {
+            if options_ended {
+                return arg;
+            }
+            if arg == "--" {
+                options_ended = true;
+                return arg;
+            }
+            MULTI_LETTER_OPTIONS
+                .iter()
+                .find(|(short, _)| arg == *short)
+                .map_or(arg, |(_, long)| OsString::from(long))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(args: &[&str]) -> Vec<OsString> {
+        expand_short_options(args.iter().map(OsString::from))
+    }
+
+    #[test]
+    fn expands_multi_letter_options() {
+        assert_eq!(
+            expand(&["rust_wget", "-nc", "http://example.com"]),
+            expand(&["rust_wget", "--no-clobber", "http://example.com"])
+        );
+    }
+
+    #[test]
+    fn leaves_other_arguments_alone() {
+        let args = ["rust_wget", "-n", "-ncx", "-d", "--", "-nc"];
+        assert_eq!(
+            expand(&args),
+            args.iter().map(OsString::from).collect::<Vec<_>>()
+        );
+    }
+}
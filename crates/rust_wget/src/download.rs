@@ -0,0 +1,903 @@
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io,
+    io::Write,
+    path::Path,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::{anyhow, Context};
+
+use crate::{
+    html,
+    metadata::FileMetadata,
+    output::{ExistingFiles, PendingFile},
+    path_mapping::{NameHints, PathMapping},
+    progress::{self, Progress, ProgressStyle, RateUnit},
+    robots::{Robots, RobotsCache},
+    segmented::{self, Probe, Segmentation},
+    stats::SessionStats,
+    timestamping::LocalFile,
+    wait::WaitPolicy,
+};
+
+use request_rs::{
+    brief,
+    connection::Connection,
+    connection_cache::ConnectionCache,
+    debug, error,
+    error::{WgetError, WgetResult},
+    http::{
+        Conditions, HttpMethod, HttpRequest, HttpResponse, HttpStatusCode, HttpStatusFamily,
+        RangeSpec,
+    },
+    info,
+    protocol::Protocol,
+    trace,
+    url::ParsedUrl,
+    Configuration,
+};
+
+/// Bodies at least this large are only sent once the server agrees to take them
+const EXPECT_CONTINUE_SIZE: usize = 1024 * 1024;
+
+/// Settings controlling how each URL is downloaded and saved
+#[derive(Debug, Default)]
+pub struct DownloadOptions {
+    /// Return documents to be concatenated into a single output file, instead
+    /// of saving each under its own file name
+    pub concatenate: bool,
+    /// Download files in byte ranges over several connections, if the server allows it
+    pub segmentation: Option<Segmentation>,
+    /// How to display the progress of each transfer, if at all
+    pub progress: Option<ProgressStyle>,
+    /// The unit to show transfer rates in
+    pub rate_unit: RateUnit,
+    /// Stop starting new downloads once this many bytes have been downloaded, 0 for no limit
+    pub quota: u64,
+    /// How long to pause between requests
+    pub wait: WaitPolicy,
+    /// How many times to attempt each request when the network fails, 0 for no limit
+    pub tries: u32,
+    /// Only download files that are newer than the local copy
+    pub timestamping: bool,
+    /// When timestamping, ask the server with `If-Modified-Since` instead of
+    /// sending a `HEAD` request first
+    pub if_modified_since: bool,
+    /// Set the modification time of saved files from `Last-Modified`
+    pub use_server_timestamps: bool,
+    /// Store each file's `ETag` and only download it again if that has changed
+    pub etag: bool,
+    /// What to do when a file would be saved over an existing one
+    pub existing_files: ExistingFiles,
+    /// Where each file is saved
+    pub paths: PathMapping,
+    /// Find the links in HTML documents, so they can be downloaded as well
+    pub follow_links: bool,
+    /// Only check that each URL exists, without saving anything, from `--spider`
+    pub spider: bool,
+    /// Only download each file from this byte offset on, from `--start-pos`
+    pub start_pos: u64,
+    /// Send this body in a POST instead of a GET, from `--post-data` or
+    /// `--post-file`
+    pub post_data: Option<Vec<u8>>,
+    /// Pipeline the GETs of downloads from the same server, from `--pipeline`
+    pub pipelining: bool,
+    /// How long to wait for the server to accept a large body before
+    /// sending it anyway, from `--continue-wait`. Zero sends it straight away.
+    pub continue_wait: Duration,
+    /// Save the head of the response before the document, from
+    /// `--save-headers`
+    pub save_headers: bool,
+}
+
+impl DownloadOptions {
+    /// Whether every download starts with a plain GET, so the first requests
+    /// of several downloads from one server can be sent at once
+    pub fn can_batch(&self) -> bool {
+        !self.spider
+            && !self.timestamping
+            && !self.etag
+            && self.segmentation.is_none()
+            && self.start_pos == 0
+            && self.post_data.is_none()
+            && self.wait.wait.is_zero()
+            && self.quota == 0
+    }
+}
+
+/// What downloading a URL produced, besides the saved file
+#[derive(Debug, Default)]
+pub struct Downloaded {
+    /// The document, when it is to be appended to the single output file
+    pub data: Option<Vec<u8>>,
+    /// The links in the document to other documents on the same host, when
+    /// following links
+    pub links: Vec<String>,
+}
+
+/// Downloads each of `urls`, which are all on the same server, like
+/// `download`. When `options` allow it, their GETs are first sent together
+/// with `Connection::send_all`, so over HTTP/2 or with pipelining they don't
+/// wait for each other. Any that fail are sent again alone.
+///
+/// With `robots`, links are only returned if the `robots.txt` of their
+/// server allows them.
+pub fn download_batch(
+    urls: &[&str],
+    connection_cache: &mut ConnectionCache,
+    config: &Configuration,
+    options: &DownloadOptions,
+    stats: &SessionStats,
+    robots: Option<&RobotsCache>,
+) -> Vec<anyhow::Result<Downloaded>> {
+    let mut prefetched = if urls.len() > 1 && options.can_batch() {
+        prefetch(urls, connection_cache, config, options, stats)
+    } else {
+        Vec::new()
+    }
+    .into_iter();
+    urls.iter()
+        .map(|url| {
+            let response = prefetched.next().flatten();
+            let mut result = download(url, response, connection_cache, config, options, stats);
+            if let (Ok(downloaded), Some(robots)) = (&mut result, robots) {
+                obey_robots(&mut downloaded.links, robots, connection_cache, config);
+            }
+            result
+        })
+        .collect()
+}
+
+/// Drops the `links` their server's `robots.txt` forbids, fetching it first
+/// if it hasn't been yet
+fn obey_robots(
+    links: &mut Vec<String>,
+    robots: &RobotsCache,
+    connection_cache: &mut ConnectionCache,
+    config: &Configuration,
+) {
+    links.retain(|link| {
+        let Ok(url) = ParsedUrl::parse(link, config) else {
+            return true;
+        };
+        let server = server(&url);
+        let rules = match robots.get(&server) {
+            Some(rules) => rules,
+            None => robots.insert(server, fetch_robots(&url, connection_cache, config)),
+        };
+        let allowed = rules.allows(&url.path);
+        if !allowed {
+            debug!(config, "Not following {link}, which robots.txt forbids");
+        }
+        allowed
+    });
+}
+
+/// The `robots.txt` of `url`'s server. A server without one, or that can't
+/// be asked, allows everything.
+fn fetch_robots(
+    url: &ParsedUrl,
+    connection_cache: &mut ConnectionCache,
+    config: &Configuration,
+) -> Robots {
+    debug!(config, "Fetching robots.txt from {}", url.domain_name);
+    let response = connection_cache
+        .get_connection(url, config)
+        .and_then(|conn| {
+            let request = conn.new_request(HttpMethod::Get, "/robots.txt");
+            conn.send(&request, config)
+        });
+    match response {
+        Ok(response) if response.status_family() == HttpStatusFamily::Successful => {
+            Robots::parse(&String::from_utf8_lossy(response.get_data()))
+        }
+        Ok(_) => Robots::default(),
+        Err(e) => {
+            debug!(config, "Could not fetch robots.txt: {e}");
+            connection_cache.remove(url);
+            Robots::default()
+        }
+    }
+}
+
+/// The server `url` is downloaded from, which downloads must share to be
+/// batched together, or `None` if it can't be parsed
+pub fn server_of(url: &str, config: &Configuration) -> Option<String> {
+    ParsedUrl::parse(url, config).ok().map(|url| server(&url))
+}
+
+fn server(url: &ParsedUrl) -> String {
+    format!("{}:{}", url.domain_name, url.port)
+}
+
+/// Sends a GET for each of `urls` at once, returning the responses that
+/// arrived. Files that would be skipped anyway aren't asked for.
+fn prefetch(
+    urls: &[&str],
+    connection_cache: &mut ConnectionCache,
+    config: &Configuration,
+    options: &DownloadOptions,
+    stats: &SessionStats,
+) -> Vec<Option<HttpResponse>> {
+    let Ok(parsed_urls) = urls
+        .iter()
+        .map(|url| ParsedUrl::parse(url, config))
+        .collect::<WgetResult<Vec<_>>>()
+    else {
+        return Vec::new();
+    };
+    let Ok(conn) = connection_cache.get_connection(&parsed_urls[0], config) else {
+        return Vec::new();
+    };
+    // A connection that has been used already may have found the server
+    // can't handle pipelining, and turned it off
+    if options.pipelining && conn.requests_sent() == 0 {
+        conn.set_pipelining(true);
+    }
+    let wanted: Vec<bool> = parsed_urls
+        .iter()
+        .map(|url| {
+            options.concatenate
+                || options.paths.content_disposition
+                || options.paths.adjust_extension
+                || !options.existing_files.skips(&options.paths.local_path(url))
+        })
+        .collect();
+    let requests: Vec<HttpRequest> = parsed_urls
+        .iter()
+        .zip(&wanted)
+        .filter(|(_, wanted)| **wanted)
+        .map(|(url, _)| conn.new_request(HttpMethod::Get, &url.path))
+        .collect();
+    stats.wait_for_turn(&options.wait);
+    match conn.send_all(&requests, config) {
+        Ok(responses) => {
+            let mut responses = responses.into_iter();
+            wanted
+                .into_iter()
+                .map(|wanted| {
+                    wanted
+                        .then(|| responses.next().and_then(Result::ok))
+                        .flatten()
+                })
+                .collect()
+        }
+        Err(e) => {
+            debug!(
+                config,
+                "Sending {} requests at once failed ({e}), sending them one at a time",
+                requests.len()
+            );
+            connection_cache.remove(&parsed_urls[0]);
+            Vec::new()
+        }
+    }
+}
+
+/// Fetches `url`, following any redirects.
+///
+/// If `options.concatenate` is set the document is returned so the caller can
+/// append it to the single output file, otherwise it is saved under its own
+/// file name. In spider mode nothing is saved, and an error is returned if
+/// the URL is broken.
+///
+/// `prefetched` is the response to the first GET, if it has already been
+/// fetched.
+fn download(
+    url: &str,
+    mut prefetched: Option<HttpResponse>,
+    connection_cache: &mut ConnectionCache,
+    config: &Configuration,
+    options: &DownloadOptions,
+    stats: &SessionStats,
+) -> anyhow::Result<Downloaded> {
+    if stats.quota_exceeded(options.quota) {
+        if stats.should_report_quota() {
+            error!(
+                config,
+                "Download quota of {} EXCEEDED!",
+                progress::format_size(options.quota)
+            );
+        }
+        return Ok(Downloaded::default());
+    }
+    // Links are followed on the host that was asked for, even once it
+    // redirects somewhere else
+    let scope = ParsedUrl::parse(url, config)?;
+    let mut current_url = url.to_string();
+    // Dropped once a redirect turns the POST into a GET
+    let mut post_data = options.post_data.as_deref();
+    loop {
+        let parsed_url = ParsedUrl::parse(&current_url, config)?;
+        debug!(config, "{parsed_url:?}");
+        if parsed_url.protocol != Protocol::Http {
+            return Err(anyhow!("Protocols other than HTTP are not yet implemented"));
+        }
+        if options.spider {
+            match spider(
+                &parsed_url,
+                &current_url,
+                &scope,
+                connection_cache,
+                config,
+                options,
+                stats,
+            )? {
+                Spidered::Redirect(new_url) => {
+                    current_url = new_url;
+                    continue;
+                }
+                Spidered::Exists { links } => {
+                    info!(config, "{current_url}: Remote file exists.");
+                    return Ok(Downloaded { data: None, links });
+                }
+            }
+        }
+        let path = &options.paths.local_path(&parsed_url);
+        // If the response can change the name, existing files can only be
+        // checked for once it has arrived
+        let name_from_response =
+            options.paths.content_disposition || options.paths.adjust_extension;
+        if !options.concatenate && !name_from_response && options.existing_files.skips(path) {
+            report_already_there(path, config);
+            return Ok(Downloaded::default());
+        }
+        // Shown until the file is saved and its final name is known
+        let name = path.to_string_lossy();
+        // Conditional downloads compare against the file's own name, which
+        // there isn't one of when documents are concatenated
+        let local_file = if (options.timestamping || options.etag) && !options.concatenate {
+            LocalFile::find(path).context("Could not check local file")?
+        } else {
+            None
+        };
+        let mut conditions = Conditions::default();
+        if let Some(local_file) = local_file {
+            if options.etag {
+                conditions.if_none_match = FileMetadata::load(path)
+                    .context("Could not read file metadata")?
+                    .filter(|metadata| metadata.url.as_deref() == Some(current_url.as_str()))
+                    .and_then(|metadata| metadata.etag);
+            }
+            // Segmented downloads don't send a single GET to make conditional
+            let send_if_modified_since =
+                options.if_modified_since && options.segmentation.is_none();
+            if options.timestamping && send_if_modified_since {
+                conditions.if_modified_since = Some(local_file.modified);
+            } else if options.timestamping && post_data.is_none() {
+                let (response, _) = fetch(
+                    &parsed_url,
+                    &name,
+                    |conn| conn.new_request(HttpMethod::Head, &parsed_url.path),
+                    connection_cache,
+                    config,
+                    options,
+                    stats,
+                )?;
+                match response.status_family() {
+                    HttpStatusFamily::Redirection => {
+                        if let Some(new_url) = response.get_header("Location") {
+                            current_url = new_url.to_string();
+                            continue;
+                        }
+                    }
+                    HttpStatusFamily::Successful => {
+                        let comparison = local_file.compare(&response);
+                        if comparison.is_up_to_date() {
+                            report_not_modified(path, config);
+                            return Ok(Downloaded::default());
+                        }
+                        info!(config, "{comparison}");
+                    }
+                    // Let the GET report whatever went wrong
+                    _ => {}
+                }
+            }
+        }
+        // Segments are written straight to disk, so they can't be concatenated
+        // or follow the response head, and always cover the whole file. They
+        // are fetched with GET, which would lose the body of a POST.
+        if let Some(segmentation) = options.segmentation.filter(|_| {
+            !options.concatenate
+                && !options.save_headers
+                && options.start_pos == 0
+                && post_data.is_none()
+        }) {
+            stats.wait_for_turn(&options.wait);
+            match segmented::probe(&parsed_url, &conditions, connection_cache, config)? {
+                Probe::Redirect(new_url) => {
+                    current_url = new_url;
+                    continue;
+                }
+                Probe::NotModified => {
+                    report_not_modified(path, config);
+                    return Ok(Downloaded::default());
+                }
+                Probe::Ranges { length, head } => {
+                    let Some(pending) = pending_file(&parsed_url, &head, config, options)? else {
+                        return Ok(Downloaded::default());
+                    };
+                    let target = pending.target().to_path_buf();
+                    let progress = new_progress(&target.to_string_lossy(), config, options);
+                    segmented::download(
+                        &parsed_url,
+                        length,
+                        &segmentation,
+                        pending.temp_path(),
+                        config,
+                    )?;
+                    pending.commit().context("Could not save output file")?;
+                    stats.record_download(length);
+                    let metadata = FileMetadata::from_response(&current_url, &head);
+                    save_metadata(&target, &metadata, options)?;
+                    report_saved(&current_url, &target, length, config);
+                    // Segments arrive out of order, so only the summary is shown
+                    if let Some(mut progress) = progress {
+                        progress.update(length, Some(length));
+                        progress.finish();
+                    }
+                    let links = if options.follow_links && is_html(&head) {
+                        let document = fs::read(&target).context("Could not read saved file")?;
+                        links(&scope, &current_url, &document)
+                    } else {
+                        Vec::new()
+                    };
+                    return Ok(Downloaded { data: None, links });
+                }
+                Probe::Unsupported => {
+                    debug!(
+                        config,
+                        "Server does not support ranges, downloading in one piece"
+                    );
+                }
+            }
+        }
+        // Arrived all at once, so there was no progress to show
+        let (response, progress) = match prefetched.take() {
+            Some(response) => (response, None),
+            None => fetch(
+                &parsed_url,
+                &name,
+                |conn| {
+                    let mut request = match post_data {
+                        Some(body) => {
+                            post_request(conn, &parsed_url.path, body, options.continue_wait)
+                        }
+                        None => conn.new_request(HttpMethod::Get, &parsed_url.path),
+                    };
+                    request.set_conditions(&conditions);
+                    if options.start_pos > 0 {
+                        request.set_ranges(&[RangeSpec::From(options.start_pos)]);
+                    }
+                    request
+                },
+                connection_cache,
+                config,
+                options,
+                stats,
+            )?,
+        };
+        debug!(
+            config,
+            "------ response start ------\n{response}\n------ response end -----"
+        );
+        if !conditions.is_empty() && response.status_code == HttpStatusCode::NotModified {
+            report_not_modified(path, config);
+            return Ok(Downloaded::default());
+        }
+        // The file ends before the start position, so there is nothing after it
+        if options.start_pos > 0 && response.status_code == HttpStatusCode::RangeNotSatisfiable {
+            info!(
+                config,
+                "The file is already fully retrieved; nothing to do."
+            );
+            return Ok(Downloaded::default());
+        }
+        match response.status_family() {
+            HttpStatusFamily::Successful => {
+                let data = body_from(&response, options.start_pos, config)?;
+                stats.record_download(data.len() as u64);
+                let links = if options.follow_links && is_html(&response) {
+                    links(&scope, &current_url, &data)
+                } else {
+                    Vec::new()
+                };
+                let data = if options.save_headers {
+                    Cow::Owned([response.head().as_bytes(), &data].concat())
+                } else {
+                    data
+                };
+                if options.concatenate {
+                    if let Some(progress) = progress {
+                        progress.finish();
+                    }
+                    return Ok(Downloaded {
+                        data: Some(data.into_owned()),
+                        links,
+                    });
+                }
+                let Some(pending) = pending_file(&parsed_url, &response, config, options)? else {
+                    if let Some(progress) = progress {
+                        progress.abandon();
+                    }
+                    return Ok(Downloaded::default());
+                };
+                let target = pending.target().to_path_buf();
+                File::create(pending.temp_path())
+                    .and_then(|mut f| f.write_all(&data))
+                    .context("Could not write data to output file")?;
+                pending.commit().context("Could not save output file")?;
+                // Only part of the file was saved, which mustn't pass for the
+                // whole of it when checking for changes later
+                if options.start_pos == 0 {
+                    let metadata = FileMetadata::from_response(&current_url, &response);
+                    save_metadata(&target, &metadata, options)?;
+                }
+                report_saved(&current_url, &target, data.len() as u64, config);
+                if let Some(mut progress) = progress {
+                    progress.rename(&target.to_string_lossy());
+                    progress.finish();
+                }
+                return Ok(Downloaded { data: None, links });
+            }
+            HttpStatusFamily::Redirection => {
+                if let Some(new_url) = response.get_header("Location") {
+                    trace!(
+                        config,
+                        "Got {} with Location \"{}\"",
+                        response.status_code,
+                        new_url
+                    );
+                    // Like browsers, only keep posting where the server says
+                    // the method must not change
+                    if !matches!(
+                        response.status_code,
+                        HttpStatusCode::TemporaryRedirect | HttpStatusCode::PermanentRedirect
+                    ) {
+                        post_data = None;
+                    }
+                    current_url = new_url.to_string();
+                } else {
+                    return Err(missing_location(&response).into());
+                }
+            }
+            HttpStatusFamily::Informational => {
+                log_response(&response, config);
+                return Err(WgetError::ProtocolError(format!(
+                    "Unexpected informational response {}",
+                    response.status_code
+                ))
+                .into());
+            }
+            HttpStatusFamily::ClientError | HttpStatusFamily::ServerError => {
+                log_response(&response, config);
+                return Err(error_response(&response).into());
+            }
+        }
+    }
+}
+
+/// What checking a URL in spider mode found
+enum Spidered {
+    Redirect(String),
+    Exists { links: Vec<String> },
+}
+
+/// Checks that `url` exists without downloading it, unless its body is
+/// needed for its links or the server doesn't support `HEAD`. Only links
+/// within `scope` are returned, like in `links`.
+fn spider(
+    url: &ParsedUrl,
+    url_string: &str,
+    scope: &ParsedUrl,
+    connection_cache: &mut ConnectionCache,
+    config: &Configuration,
+    options: &DownloadOptions,
+    stats: &SessionStats,
+) -> anyhow::Result<Spidered> {
+    let name = url_string;
+    let (mut response, _) = fetch(
+        url,
+        name,
+        |conn| conn.new_request(HttpMethod::Head, &url.path),
+        connection_cache,
+        config,
+        options,
+        stats,
+    )?;
+    let head_unsupported = matches!(
+        response.status_code,
+        HttpStatusCode::MethodNotAllowed | HttpStatusCode::NotImplemented
+    );
+    let needs_links = options.follow_links
+        && response.status_family() == HttpStatusFamily::Successful
+        && is_html(&response);
+    if head_unsupported || needs_links {
+        (response, _) = fetch(
+            url,
+            name,
+            |conn| conn.new_request(HttpMethod::Get, &url.path),
+            connection_cache,
+            config,
+            options,
+            stats,
+        )?;
+    }
+    match response.status_family() {
+        HttpStatusFamily::Successful => {
+            let links = if options.follow_links && is_html(&response) {
+                links(scope, url_string, response.get_data())
+            } else {
+                Vec::new()
+            };
+            Ok(Spidered::Exists { links })
+        }
+        HttpStatusFamily::Redirection => match response.get_header("Location") {
+            Some(location) => Ok(Spidered::Redirect(location.to_string())),
+            None => Err(missing_location(&response).into()),
+        },
+        _ => Err(error_response(&response).into()),
+    }
+}
+
+/// The body of `response` from `start_pos` on. A server that ignored the
+/// `Range` header sends the whole file, whose start is then cut off here.
+fn body_from<'a>(
+    response: &'a HttpResponse,
+    start_pos: u64,
+    config: &Configuration,
+) -> WgetResult<Cow<'a, [u8]>> {
+    let data = response.get_data();
+    if start_pos == 0 {
+        return Ok(Cow::Borrowed(data));
+    }
+    if response.status_code != HttpStatusCode::PartialContent {
+        debug!(
+            config,
+            "Server ignored the range, skipping the first {start_pos} bytes"
+        );
+        let start = usize::try_from(start_pos).map_or(data.len(), |start| start.min(data.len()));
+        return Ok(Cow::Borrowed(&data[start..]));
+    }
+    let mut parts = response.byte_ranges(config)?;
+    match (parts.pop(), parts.is_empty()) {
+        (Some(part), true) if part.range.first == start_pos => {
+            Ok(Cow::Owned(part.get_data().to_vec()))
+        }
+        _ => Err(WgetError::ProtocolError(format!(
+            "Server did not send the file from byte {start_pos} on"
+        ))),
+    }
+}
+
+fn missing_location(response: &HttpResponse) -> WgetError {
+    WgetError::ProtocolError(format!("Got {} without a Location", response.status_code))
+}
+
+fn error_response(response: &HttpResponse) -> WgetError {
+    WgetError::ErrorResponse(response.status_code, response.status_message.clone())
+}
+
+fn is_html(response: &HttpResponse) -> bool {
+    response
+        .get_header("Content-Type")
+        .is_some_and(|content_type| {
+            let media_type = content_type.split(';').next().unwrap_or_default().trim();
+            media_type.eq_ignore_ascii_case("text/html")
+                || media_type.eq_ignore_ascii_case("application/xhtml+xml")
+        })
+}
+
+/// The links in `document`, fetched from `url_string`, that point to other
+/// documents on the same host as `scope`
+fn links(scope: &ParsedUrl, url_string: &str, document: &[u8]) -> Vec<String> {
+    let found = html::extract_links(&String::from_utf8_lossy(document));
+    let base = match &found.base {
+        Some(base) => request_rs::url::resolve(url_string, base),
+        None => url_string.to_string(),
+    };
+    found
+        .links
+        .iter()
+        .map(|link| request_rs::url::resolve(&base, link))
+        // Links like `mailto:` have no `://`, and aren't worth parsing
+        .filter(|link| link.contains("://"))
+        .filter(|link| {
+            ParsedUrl::parse(link, &Configuration::default()).is_ok_and(|link| {
+                link.protocol == scope.protocol
+                    && link.domain_name.eq_ignore_ascii_case(&scope.domain_name)
+                    && link.port == scope.port
+            })
+        })
+        .collect()
+}
+
+/// Starts saving the `response` for `url`, creating any directories it needs,
+/// or returns `None` if it shouldn't be saved at all because a file is
+/// already there
+fn pending_file(
+    url: &ParsedUrl,
+    response: &HttpResponse,
+    config: &Configuration,
+    options: &DownloadOptions,
+) -> anyhow::Result<Option<PendingFile>> {
+    let disposition = response.content_disposition();
+    let hints = NameHints {
+        file_name: disposition.as_ref().and_then(|d| d.filename.as_deref()),
+        content_type: response.get_header("Content-Type"),
+    };
+    let path = options.paths.local_path_with(url, hints);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Could not create output directory")?;
+    }
+    let pending = PendingFile::create(&path, options.existing_files)
+        .context("Could not create output file")?;
+    if pending.is_none() {
+        report_already_there(&path, config);
+    }
+    Ok(pending)
+}
+
+/// The line shown instead of progress with `-nv`, like wget's
+fn report_saved(url: &str, target: &Path, length: u64, config: &Configuration) {
+    brief!(config, "{url} [{length}] -> '{}'", target.display());
+}
+
+fn report_already_there(path: &Path, config: &Configuration) {
+    info!(
+        config,
+        "File '{}' already there; not retrieving.",
+        path.display()
+    );
+}
+
+fn report_not_modified(path: &Path, config: &Configuration) {
+    info!(
+        config,
+        "Server file no newer than local file '{}' -- not retrieving.",
+        path.display()
+    );
+}
+
+/// Records what the server said about the file just saved at `path`, as far
+/// as `options` ask for
+fn save_metadata(
+    path: &Path,
+    metadata: &FileMetadata,
+    options: &DownloadOptions,
+) -> anyhow::Result<()> {
+    if let Some(last_modified) = metadata
+        .last_modified
+        .filter(|_| options.use_server_timestamps)
+    {
+        let modified =
+            SystemTime::try_from(last_modified).context("Could not set modification time")?;
+        File::options()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_modified(modified))
+            .context("Could not set modification time")?;
+    }
+    if options.etag {
+        metadata
+            .store(path)
+            .context("Could not store file metadata")?;
+    }
+    Ok(())
+}
+
+/// Sends the request made by `request` for `url`, retrying if the network
+/// fails. Any progress is shown under `name`, and is returned unfinished for
+/// successful responses so it can be finished once the file is saved.
+fn fetch(
+    url: &ParsedUrl,
+    name: &str,
+    request: impl Fn(&Connection) -> HttpRequest,
+    connection_cache: &mut ConnectionCache,
+    config: &Configuration,
+    options: &DownloadOptions,
+    stats: &SessionStats,
+) -> WgetResult<(HttpResponse, Option<Progress>)> {
+    let mut failures = 0;
+    stats.wait_for_turn(&options.wait);
+    loop {
+        let mut reused = false;
+        let result = connection_cache
+            .get_connection(url, config)
+            .and_then(|conn| {
+                reused = conn.requests_sent() > 0;
+                let request = request(conn);
+                send(name, conn, &request, config, options)
+            });
+        let error = match result {
+            Ok(response) => return Ok(response),
+            Err(e) => e,
+        };
+        connection_cache.remove(url);
+        if reused && is_network_error(&error) {
+            // The server most likely closed the connection while it was idle,
+            // which isn't worth counting as a failure
+            debug!(config, "Cached connection failed ({error}), reconnecting");
+            continue;
+        }
+        failures += 1;
+        let out_of_tries = options.tries != 0 && failures >= options.tries;
+        if out_of_tries || !is_retryable(&error) {
+            return Err(error);
+        }
+        error!(config, "{error}. Retrying.");
+        thread::sleep(options.wait.before_retry(failures));
+    }
+}
+
+/// A POST of a form's data, as wget sends for `--post-data`
+fn post_request(conn: &Connection, path: &str, body: &[u8], wait: Duration) -> HttpRequest {
+    let mut request = conn.new_request(HttpMethod::Post, path);
+    request.add_header("Content-Type", "application/x-www-form-urlencoded");
+    request.set_body(body);
+    // Don't upload a large body only to have it refused
+    if body.len() >= EXPECT_CONTINUE_SIZE && !wait.is_zero() {
+        request.expect_continue(wait);
+    }
+    request
+}
+
+fn send(
+    name: &str,
+    conn: &mut Connection,
+    request: &HttpRequest,
+    config: &Configuration,
+    options: &DownloadOptions,
+) -> WgetResult<(HttpResponse, Option<Progress>)> {
+    // Only a GET or POST has a body worth showing progress for
+    let mut progress = if matches!(request.method(), HttpMethod::Get | HttpMethod::Post) {
+        new_progress(name, config, options)
+    } else {
+        None
+    };
+    let result = conn.send_with_interim(
+        request,
+        config,
+        &mut |received, total| {
+            if let Some(progress) = &mut progress {
+                progress.update(received, total);
+            }
+        },
+        &mut |interim| trace!(config, "Got interim response {}", interim.status_code),
+    );
+    match (result, progress) {
+        (Ok(response), progress) if response.status_family() == HttpStatusFamily::Successful => {
+            Ok((response, progress))
+        }
+        (result, progress) => {
+            if let Some(progress) = progress {
+                progress.abandon();
+            }
+            result.map(|response| (response, None))
+        }
+    }
+}
+
+fn new_progress(name: &str, config: &Configuration, options: &DownloadOptions) -> Option<Progress> {
+    options.progress.map(|style| {
+        Progress::new(style, name, config.logger.clone()).with_rate_unit(options.rate_unit)
+    })
+}
+
+fn is_network_error(error: &WgetError) -> bool {
+    matches!(error, WgetError::Io(_))
+}
+
+/// Whether trying again might help. Like wget, a refused connection isn't
+/// retried since there is clearly nothing listening.
+fn is_retryable(error: &WgetError) -> bool {
+    matches!(error, WgetError::Io(e) if e.kind() != io::ErrorKind::ConnectionRefused)
+}
+
+fn log_response(response: &HttpResponse, config: &Configuration) {
+    let bytes = response.serialize();
+    debug!(config, "{}", String::from_utf8_lossy(&bytes));
+}
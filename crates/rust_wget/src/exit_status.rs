@@ -0,0 +1,146 @@
+use std::{cmp::Ordering, io};
+
+use request_rs::{error::WgetError, http::HttpStatusCode};
+
+/// wget's documented exit statuses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExitStatus {
+    #[default]
+    Success = 0,
+    Generic = 1,
+    /// Parsing the command line or a URL failed
+    Parse = 2,
+    /// Reading or writing a local file failed
+    FileIo = 3,
+    Network = 4,
+    /// The server wanted credentials, or didn't accept the ones given
+    Authentication = 6,
+    /// The server sent something that broke the protocol
+    Protocol = 7,
+    /// The server responded with an error status
+    ServerError = 8,
+}
+
+impl ExitStatus {
+    /// The status for a failed download
+    pub fn of(error: &anyhow::Error) -> Self {
+        // The outermost error that says what happened wins, since any inner
+        // ones are only its causes
+        error
+            .chain()
+            .find_map(|cause| {
+                if let Some(error) = cause.downcast_ref::<WgetError>() {
+                    Some(ExitStatus::of_wget_error(error))
+                } else {
+                    // Network IO always comes wrapped in a `WgetError`, so a
+                    // bare IO error is from a local file
+                    cause
+                        .downcast_ref::<io::Error>()
+                        .map(|_| ExitStatus::FileIo)
+                }
+            })
+            .unwrap_or(ExitStatus::Generic)
+    }
+
+    fn of_wget_error(error: &WgetError) -> Self {
+        match error {
+            WgetError::Io(_) => ExitStatus::Network,
+            WgetError::ParsingError(_)
+            | WgetError::InvalidStatusCode(_)
+            | WgetError::ProtocolError(_) => ExitStatus::Protocol,
+            WgetError::InvalidUrl(_) => ExitStatus::Parse,
+            WgetError::ErrorResponse(
+                HttpStatusCode::Unauthorized | HttpStatusCode::ProxyAuthenticationRequired,
+                _,
+            ) => ExitStatus::Authentication,
+            WgetError::ErrorResponse(..) => ExitStatus::ServerError,
+        }
+    }
+
+    /// The status to exit with when both `self` and `other` happened. Like
+    /// wget, lower statuses win, apart from the generic 1 which only beats
+    /// success.
+    pub fn combine(self, other: Self) -> Self {
+        match self.priority().cmp(&other.priority()) {
+            Ordering::Less => other,
+            _ => self,
+        }
+    }
+
+    fn priority(self) -> u8 {
+        match self {
+            ExitStatus::Success => 0,
+            ExitStatus::Generic => 1,
+            status => u8::MAX - status as u8,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+
+    use super::*;
+
+    #[test]
+    fn classifies_errors() {
+        let status = |error: WgetError| ExitStatus::of(&error.into());
+        assert_eq!(
+            status(WgetError::Io(io::ErrorKind::ConnectionReset.into())),
+            ExitStatus::Network
+        );
+        assert_eq!(
+            status(WgetError::ParsingError("Bad header".to_string())),
+            ExitStatus::Protocol
+        );
+        assert_eq!(
+            status(WgetError::InvalidUrl("Bad port".to_string())),
+            ExitStatus::Parse
+        );
+        assert_eq!(
+            status(WgetError::ErrorResponse(
+                HttpStatusCode::NotFound,
+                "Not Found".to_string()
+            )),
+            ExitStatus::ServerError
+        );
+        assert_eq!(
+            status(WgetError::ErrorResponse(
+                HttpStatusCode::Unauthorized,
+                "Unauthorized".to_string()
+            )),
+            ExitStatus::Authentication
+        );
+        assert_eq!(
+            ExitStatus::of(&anyhow::anyhow!("Something else")),
+            ExitStatus::Generic
+        );
+    }
+
+    #[test]
+    fn looks_through_context() {
+        let file_error: anyhow::Result<()> = Err(io::Error::from(io::ErrorKind::PermissionDenied))
+            .context("Could not write data to output file");
+        assert_eq!(
+            ExitStatus::of(&file_error.expect_err("Should fail")),
+            ExitStatus::FileIo
+        );
+        let network_error: anyhow::Result<()> =
+            Err(WgetError::Io(io::ErrorKind::TimedOut.into())).context("Could not download bytes");
+        assert_eq!(
+            ExitStatus::of(&network_error.expect_err("Should fail")),
+            ExitStatus::Network
+        );
+    }
+
+    #[test]
+    fn lower_statuses_win_except_generic() {
+        use ExitStatus::*;
+        assert_eq!(Success.combine(Generic), Generic);
+        assert_eq!(Generic.combine(ServerError), ServerError);
+        assert_eq!(ServerError.combine(Network), Network);
+        assert_eq!(Network.combine(ServerError), Network);
+        assert_eq!(Parse.combine(Generic), Parse);
+        assert_eq!(Protocol.combine(Success), Protocol);
+    }
+}
@@ -0,0 +1,229 @@
+/// The attributes that hold links to other documents, and the tags they do so on
+const LINK_ATTRIBUTES: &[(&str, &[&str])] = &[
+    ("href", &["a", "area", "link"]),
+    (
+        "src",
+        &[
+            "img", "script", "frame", "iframe", "embed", "input", "audio", "video", "source",
+            "track",
+        ],
+    ),
+    ("background", &["body", "table", "td", "th"]),
+    ("data", &["object"]),
+];
+
+/// The links found in an HTML document
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HtmlLinks {
+    /// The document's own idea of its URL, from `<base href>`, which relative
+    /// links are resolved against
+    pub base: Option<String>,
+    /// Every link in the order they appear, as written in the document apart
+    /// from character references
+    pub links: Vec<String>,
+}
+
+/// Finds the links in `html`. This is only a scanner of tags and their
+/// attributes, which is all that is needed and copes with broken documents
+/// much better than a strict parser would.
+pub fn extract_links(html: &str) -> HtmlLinks {
+    let mut found = HtmlLinks::default();
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        if let Some(comment) = rest.strip_prefix("!--") {
+            rest = comment.find("-->").map_or("", |end| &comment[end + 3..]);
+            continue;
+        }
+        let name_end = rest
+            .find(|c: char| c.is_ascii_whitespace() || c == '>' || c == '/')
+            .unwrap_or(rest.len());
+        let name = rest[..name_end].to_ascii_lowercase();
+        rest = &rest[name_end..];
+        let mut tag = Tag { rest };
+        while let Some((attribute, value)) = tag.attribute() {
+            let Some(value) = value else { continue };
+            let value = decode_references(value.trim());
+            if value.is_empty() {
+                continue;
+            }
+            if name == "base" && attribute.eq_ignore_ascii_case("href") {
+                found.base.get_or_insert(value);
+            } else if is_link(&name, attribute) {
+                found.links.push(value);
+            }
+        }
+        rest = tag.rest;
+        // The contents of scripts and styles aren't HTML, so may contain `<`
+        if let Some(end) = ["script", "style"]
+            .contains(&name.as_str())
+            .then(|| find_ignore_case(rest, &format!("</{name}")))
+        {
+            rest = end.map_or("", |end| &rest[end..]);
+        }
+    }
+    found
+}
+
+fn is_link(tag: &str, attribute: &str) -> bool {
+    LINK_ATTRIBUTES
+        .iter()
+        .any(|(name, tags)| attribute.eq_ignore_ascii_case(name) && tags.contains(&tag))
+}
+
+/// The attributes of a tag, read from just after its name up to the `>`
+struct Tag<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Tag<'a> {
+    /// The next attribute's name and value, or `None` at the end of the tag
+    fn attribute(&mut self) -> Option<(&'a str, Option<&'a str>)> {
+        self.rest = self
+            .rest
+            .trim_start_matches(|c: char| c.is_ascii_whitespace() || c == '/');
+        if self.rest.is_empty() {
+            return None;
+        }
+        if let Some(rest) = self.rest.strip_prefix('>') {
+            self.rest = rest;
+            return None;
+        }
+        let name_end = self
+            .rest
+            .find(|c: char| c.is_ascii_whitespace() || "=>/".contains(c))
+            .unwrap_or(self.rest.len())
+            // An attribute name may start with `=` or `/`, which would loop forever
+            .max(1);
+        let name = &self.rest[..name_end];
+        self.rest = self.rest[name_end..].trim_start();
+        let Some(value) = self.rest.strip_prefix('=') else {
+            return Some((name, None));
+        };
+        let value = value.trim_start();
+        let (value, rest) = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let value = &value[1..];
+                match value.find(quote) {
+                    Some(end) => (&value[..end], &value[end + 1..]),
+                    None => (value, ""),
+                }
+            }
+            _ => {
+                let end = value
+                    .find(|c: char| c.is_ascii_whitespace() || c == '>')
+                    .unwrap_or(value.len());
+                value.split_at(end)
+            }
+        };
+        self.rest = rest;
+        Some((name, Some(value)))
+    }
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Replaces character references like `&amp;` and `&#47;`. Only the named
+/// references that turn up in URLs are known, anything else is left alone.
+fn decode_references(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..]
+            .find(';')
+            .map(|end| &rest[1..end + 1])
+            .and_then(|name| Some((name, decode_reference(name)?)));
+        match reference {
+            Some((name, c)) => {
+                decoded.push(c);
+                rest = &rest[name.len() + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_reference(name: &str) -> Option<char> {
+    let code = if let Some(hex) = name.strip_prefix("#x").or_else(|| name.strip_prefix("#X")) {
+        u32::from_str_radix(hex, 16).ok()?
+    } else if let Some(decimal) = name.strip_prefix('#') {
+        decimal.parse().ok()?
+    } else {
+        return match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => None,
+        };
+    };
+    char::from_u32(code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn links(html: &str) -> Vec<String> {
+        extract_links(html).links
+    }
+
+    #[test]
+    fn finds_links_in_tags() {
+        let html = r#"<html><body background=bg.png>
+            <A HREF="one.html">One</A>
+            <a href='two.html' class="x">Two</a>
+            <img alt="x" src = three.png >
+            <link rel="stylesheet" href="style.css"/>
+            <p title="href=nope.html">Not a link</p>
+        </body></html>"#;
+        assert_eq!(
+            links(html),
+            ["bg.png", "one.html", "two.html", "three.png", "style.css"]
+        );
+    }
+
+    #[test]
+    fn finds_base() {
+        let found = extract_links(r#"<base href="http://example.com/dir/"><a href="a">"#);
+        assert_eq!(found.base.as_deref(), Some("http://example.com/dir/"));
+        assert_eq!(found.links, ["a"]);
+    }
+
+    #[test]
+    fn decodes_character_references() {
+        assert_eq!(
+            links(r#"<a href="/search?a=1&amp;b=2&#38;c=&#x33;&nbsp;&">"#),
+            ["/search?a=1&b=2&c=3&nbsp;&"]
+        );
+    }
+
+    #[test]
+    fn skips_comments_and_scripts() {
+        let html = r#"<!-- <a href="commented.html"> -->
+            <script src="app.js">if (a <b) { x = "<a href='script.html'>"; }</SCRIPT>
+            <a href="real.html">"#;
+        assert_eq!(links(html), ["app.js", "real.html"]);
+    }
+
+    #[test]
+    fn copes_with_broken_html() {
+        assert_eq!(links("<a href=\"unterminated"), ["unterminated"]);
+        assert_eq!(links("<a href"), Vec::<String>::new());
+        assert_eq!(links("<a =x / href=ok.html"), ["ok.html"]);
+        assert_eq!(links("<<<>>>&<a"), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    io::{self, Read},
+};
+
+use anyhow::Context;
+
+use request_rs::{error, url, Configuration};
+
+use crate::html;
+
+/// Reads the URLs to download from `path`, or stdin if it is `-`, as given to
+/// `-i/--input-file`
+///
+/// The file lists one URL per line, unless `force_html` is set, in which case
+/// it is an HTML document and every link in it is downloaded. Relative URLs
+/// are resolved against `base`, or the document's own `<base href>`.
+pub fn read_urls(
+    path: &str,
+    force_html: bool,
+    base: Option<&str>,
+    config: &Configuration,
+) -> anyhow::Result<Vec<String>> {
+    let bytes = if path == "-" {
+        let mut bytes = Vec::new();
+        io::stdin()
+            .read_to_end(&mut bytes)
+            .context("Could not read URLs from stdin")?;
+        bytes
+    } else {
+        fs::read(path).with_context(|| format!("Could not read input file {path}"))?
+    };
+    let contents = String::from_utf8_lossy(&bytes);
+    Ok(if force_html {
+        html_urls(&contents, base, config)
+    } else {
+        text_urls(&contents, base)
+    })
+}
+
+fn text_urls(contents: &str, base: Option<&str>) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| match base {
+            Some(base) => url::resolve(base, line),
+            None => line.to_string(),
+        })
+        .collect()
+}
+
+fn html_urls(contents: &str, base: Option<&str>, config: &Configuration) -> Vec<String> {
+    let found = html::extract_links(contents);
+    // The document's base may itself be relative to the one given
+    let base = match (found.base, base) {
+        (Some(own), Some(base)) => Some(url::resolve(base, &own)),
+        (own, base) => own.or(base.map(str::to_string)),
+    };
+    found
+        .links
+        .into_iter()
+        .filter_map(|link| match &base {
+            Some(base) => Some(url::resolve(base, &link)),
+            // Links like `mailto:` aren't relative, and are dropped below
+            None if link.contains(':') => Some(link),
+            None => {
+                error!(
+                    config,
+                    "{link}: Cannot resolve relative link without a base URL, use -B"
+                );
+                None
+            }
+        })
+        .filter(|link| is_downloadable(link))
+        .collect()
+}
+
+/// Whether `url` points at something that could be downloaded, as opposed
+/// to e.g. a `mailto:` or `javascript:` link
+fn is_downloadable(url: &str) -> bool {
+    ["http://", "https://", "ftp://"]
+        .iter()
+        .any(|scheme| url.len() >= scheme.len() && url[..scheme.len()].eq_ignore_ascii_case(scheme))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_one_url_per_line() {
+        let contents = "http://example.com/a\n\n  http://example.com/b  \r\nc.html\n";
+        assert_eq!(
+            text_urls(contents, None),
+            ["http://example.com/a", "http://example.com/b", "c.html"]
+        );
+        assert_eq!(
+            text_urls(contents, Some("http://base.com/dir/")),
+            [
+                "http://example.com/a",
+                "http://example.com/b",
+                "http://base.com/dir/c.html"
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_links_from_html() {
+        let contents = r#"<a href="http://example.com/a">A</a>
+            <a href="b.html">B</a>
+            <a href="mailto:someone@example.com">Mail</a>
+            <a href="JavaScript:void(0)">Nothing</a>"#;
+        assert_eq!(
+            html_urls(contents, None, &Configuration::default()),
+            ["http://example.com/a"]
+        );
+        assert_eq!(
+            html_urls(
+                contents,
+                Some("http://base.com/dir/index.html"),
+                &Configuration::default()
+            ),
+            ["http://example.com/a", "http://base.com/dir/b.html"]
+        );
+    }
+
+    #[test]
+    fn prefers_the_documents_own_base() {
+        let contents = r#"<base href="/other/"><img src="c.png">"#;
+        assert_eq!(
+            html_urls(
+                contents,
+                Some("http://base.com/dir/"),
+                &Configuration::default()
+            ),
+            ["http://base.com/other/c.png"]
+        );
+    }
+}
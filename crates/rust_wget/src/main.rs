@@ -1,14 +1,54 @@
+mod args;
+mod download;
+mod exit_status;
+mod html;
+mod input;
+mod metadata;
+mod output;
+mod path_mapping;
+mod pool;
+mod progress;
+mod recursion;
+mod robots;
+mod sanitize;
+mod segmented;
+mod size;
+mod stats;
+mod timestamping;
+mod wait;
+
 use std::{
-    fs::File,
-    io::{self, Write},
+    fs::{self, File},
+    io::{self, IsTerminal, Write},
+    num::NonZeroUsize,
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
 };
 
+use anyhow::{anyhow, Context};
 use clap::Parser;
 
+use download::{DownloadOptions, Downloaded};
+use exit_status::ExitStatus;
+use output::ExistingFiles;
+use path_mapping::PathMapping;
+use progress::{ProgressStyle, RateUnit};
+use recursion::Link;
 use request_rs::{
-    connection_cache::ConnectionCache, http::HttpStatusFamily, protocol::Protocol, url::ParsedUrl,
+    connection_cache::ConnectionCache,
+    debug, error,
+    http::HttpVersion,
+    info,
+    log::{Logger, Verbosity},
+    rate_limit::RateLimiter,
     Configuration,
 };
+use robots::RobotsCache;
+use sanitize::FileNameRestrictions;
+use segmented::Segmentation;
+use stats::SessionStats;
+use wait::WaitPolicy;
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -18,131 +58,631 @@ use request_rs::{
     about = "A Rust reimplementation of wget"
 )]
 struct Options {
-    /// An optional output file name, to write the fetched documents to, instead of each individual document. If given, all documents will be concatenated together and written to the given path.
+    /// An optional output file name, to write the fetched documents to, instead of each individual document. If given, all documents will be concatenated together and written to the given path, or to stdout if it is -
+    #[arg(short = 'O', long)]
+    output_document: Option<String>,
+    /// Write all messages to this log file instead of stderr
+    #[arg(short = 'o', long, conflicts_with = "append_output")]
+    output_file: Option<PathBuf>,
+    /// Append all messages to this log file instead of writing them to stderr
     #[arg(short, long)]
-    output_file: Option<String>,
-    /// The level of debug information to output to stdout, can be used up to three times
+    append_output: Option<PathBuf>,
+    /// Show the status line and headers of every response the servers send
+    #[arg(short = 'S', long)]
+    server_response: bool,
+    /// Show debug information, or also every line sent and received if given twice
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
+    /// Show nothing but the downloaded documents
+    #[arg(short, long, overrides_with_all = ["verbose", "no_verbose"])]
+    quiet: bool,
+    /// Show progress and everything that happens, which is the default
+    #[arg(short, long, overrides_with_all = ["quiet", "no_verbose"])]
+    verbose: bool,
+    /// Only show errors and a line for each file saved. Can also be given as -nv
+    #[arg(long, overrides_with_all = ["quiet", "verbose"])]
+    no_verbose: bool,
+    /// Show transfer rates in bits or bytes per second
+    #[arg(long, default_value = "bytes")]
+    report_speed: RateUnit,
+    /// The number of URLs to download at the same time, each on its own thread with its own connections
+    #[arg(long, visible_alias = "max-threads", default_value = "1")]
+    parallel: NonZeroUsize,
+    /// Download each file as byte ranges over this many connections at once, if the server supports ranges
+    #[arg(long, default_value = "1")]
+    segments: NonZeroUsize,
+    /// The size of each byte range requested when downloading in segments, e.g. 4m. Defaults to splitting the file evenly between the segments
+    #[arg(long, value_parser = size::parse_size)]
+    chunk_size: Option<u64>,
+    /// How to show download progress: bar, bar:force, or dot with an optional style of default, binary, mega or giga (e.g. dot:mega). Bars become dots when stderr isn't a terminal, unless forced
+    #[arg(long, default_value = "bar")]
+    progress: ProgressStyle,
+    /// Limit the download speed to this many bytes per second, e.g. 200k. The limit applies to all downloads together, even in parallel
+    #[arg(long, value_parser = size::parse_size)]
+    limit_rate: Option<u64>,
+    /// Talk HTTP/2 to servers straight away, without first checking they support it
+    #[arg(long)]
+    http2_prior_knowledge: bool,
+    /// The version of HTTP/1 to send requests as: 1.0 or 1.1
+    #[arg(long, value_parser = parse_http_version, default_value = "1.1")]
+    http_version: HttpVersion,
+    /// Send several requests to a server at once over HTTP/1.1 without waiting for each response. Only some servers handle this, so it is turned off again for any that drop the connection
+    #[arg(long)]
+    pipeline: bool,
+    /// Ask servers to close each connection after one response, instead of reusing connections
+    #[arg(long)]
+    no_http_keep_alive: bool,
+    /// Accept responses from ancient servers that send no status line or headers, just the file
+    #[arg(long = "http0.9")]
+    http09: bool,
+    /// Send this header with every request, e.g. 'Accept-Language: fr'. Can be given several times, and the values of headers given more than once are joined. A header with an empty value, like 'Accept-Encoding:', stops that default header from being sent
+    #[arg(long = "header", value_name = "HEADER", value_parser = parse_header)]
+    headers: Vec<(String, String)>,
+    /// Identify as this instead of Wget/1.21.3. An empty string sends no User-Agent at all
+    #[arg(short = 'U', long, value_parser = parse_header_value)]
+    user_agent: Option<String>,
+    /// Send this URL in the Referer header, as the page the downloads were linked from
+    #[arg(long, value_parser = parse_header_value)]
+    referer: Option<String>,
+    /// Ask servers and proxies for a fresh copy of each file rather than a cached one
+    #[arg(long)]
+    no_cache: bool,
+    /// Start downloading each file at this zero-based byte offset, e.g. 1k, saving only the rest of it
+    #[arg(long, value_parser = size::parse_size, default_value = "0")]
+    start_pos: u64,
+    /// Send this string as the body of a POST request instead of a GET, e.g. 'name=value&other=1'. Redirects other than 307 and 308 are followed with a GET
+    #[arg(long, conflicts_with = "post_file")]
+    post_data: Option<String>,
+    /// Like --post-data, but send the contents of this file
+    #[arg(long)]
+    post_file: Option<PathBuf>,
+    /// When posting 1 MiB or more, wait this many seconds for the server to accept the body before sending it anyway. 0 sends it straight away
+    #[arg(long, value_parser = wait::parse_seconds, default_value = "1")]
+    continue_wait: Duration,
+    /// Save the status line and headers of the response at the start of each file, before its contents
+    #[arg(long)]
+    save_headers: bool,
+    /// Stop starting new downloads once this many bytes have been downloaded in total, e.g. 10m. 0 means no limit
+    #[arg(short = 'Q', long, value_parser = size::parse_size, default_value = "0")]
+    quota: u64,
+    /// Wait this many seconds between requests, or use a suffix of m, h or d for minutes, hours or days
+    #[arg(short, long, value_parser = wait::parse_seconds)]
+    wait: Option<Duration>,
+    /// Vary the time between requests from 0.5 to 1.5 times --wait
+    #[arg(long)]
+    random_wait: bool,
+    /// Wait up to this many seconds before retrying a failed download, waiting one second after the first failure, two after the second, and so on
+    #[arg(long, value_parser = wait::parse_seconds, default_value = "10")]
+    waitretry: Duration,
+    /// The number of times to try each download when the network fails, 0 for no limit
+    #[arg(short, long, default_value = "20")]
+    tries: u32,
+    /// Only download files that are newer than the local copy, or a different size
+    #[arg(short = 'N', long, conflicts_with = "no_clobber")]
+    timestamping: bool,
+    /// When timestamping, check the server's copy with a HEAD request instead of an If-Modified-Since request
+    #[arg(long)]
+    no_if_modified_since: bool,
+    /// Don't set the modification time of downloaded files to the one the server gives
+    #[arg(long)]
+    no_use_server_timestamps: bool,
+    /// Remember each file's ETag, and only download it again if the server's ETag has changed
+    #[arg(long)]
+    etag: bool,
+    /// Skip downloads that would be saved over an existing file, instead of saving them as file.1, file.2 and so on. Can also be given as -nc
+    #[arg(long)]
+    no_clobber: bool,
+    /// Replace existing files instead of numbering new ones, keeping up to this many old versions as file.1, file.2 and so on
+    #[arg(long, default_value = "0")]
+    backups: usize,
+    /// Save files under this directory instead of the current one
+    #[arg(short = 'P', long)]
+    directory_prefix: Option<PathBuf>,
+    /// Recreate the directories of each URL locally, starting with the host name
+    #[arg(short = 'x', long, conflicts_with = "no_directories")]
+    force_directories: bool,
+    /// Save every file straight into the directory prefix, never creating directories. Can also be given as -nd
+    #[arg(long)]
+    no_directories: bool,
+    /// Don't start the recreated directories with the host name. Can also be given as -nH
+    #[arg(long)]
+    no_host_directories: bool,
+    /// Start the recreated directories with the protocol, e.g. http/example.com/
+    #[arg(long)]
+    protocol_directories: bool,
+    /// Leave this many leading directories of each URL out of the recreated directories
+    #[arg(long, default_value = "0")]
+    cut_dirs: usize,
+    /// The file name to save URLs ending in / as
+    #[arg(long, default_value = "index.html")]
+    default_page: String,
+    /// Escape characters in file names that aren't allowed by these comma separated modes: unix or windows, nocontrol to allow control characters, ascii to escape everything else, and lowercase or uppercase. Defaults to the current platform
+    #[arg(long)]
+    restrict_file_names: Option<FileNameRestrictions>,
+    /// Name files after the file name in the server's Content-Disposition header, when it sends one
+    #[arg(long)]
+    content_disposition: bool,
+    /// Add .html or .css to the names of HTML and CSS documents that don't already end that way
+    #[arg(short = 'E', long)]
+    adjust_extension: bool,
+    /// Also download the documents linked to from HTML documents on the same host
+    #[arg(short, long)]
+    recursive: bool,
+    /// How many links away from the given URLs to follow when downloading recursively, or inf for no limit
+    #[arg(short = 'l', long, default_value = "5", value_parser = recursion::parse_depth)]
+    level: Depth,
+    /// Run a .wgetrc command, like robots=off to also follow links that robots.txt forbids. Only robots is supported so far
+    #[arg(short = 'e', long = "execute", value_name = "COMMAND", value_parser = parse_command)]
+    commands: Vec<WgetrcCommand>,
+    /// Only check that the URLs exist without saving anything, then report any broken links
+    #[arg(long)]
+    spider: bool,
+    /// Always show download progress, even when downloading in parallel where the bars would otherwise be hidden
+    #[arg(long)]
+    show_progress: bool,
+    /// Also download the URLs listed in this file, one per line, or read them from stdin if it is -
+    #[arg(short, long)]
+    input_file: Option<String>,
+    /// Treat the input file as HTML and download every link in it
+    #[arg(short = 'F', long, requires = "input_file")]
+    force_html: bool,
+    /// Resolve relative URLs in the input file against this URL
+    #[arg(short = 'B', long, requires = "input_file")]
+    base: Option<String>,
 
     /// The URLs to fetch
     urls: Vec<String>,
 }
 
-fn main() -> anyhow::Result<()> {
-    let options = Options::parse();
-    if options.debug > 0 {
-        println!("{options:?}");
+/// Parses the HTTP version given to `--http-version`
+fn parse_http_version(s: &str) -> Result<HttpVersion, String> {
+    match s {
+        "1.0" => Ok(HttpVersion::Version1_0),
+        "1.1" => Ok(HttpVersion::Version1_1),
+        _ => Err(format!("Unsupported HTTP version {s}, expected 1.0 or 1.1")),
+    }
+}
+
+/// A `.wgetrc` command given to `-e`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum WgetrcCommand {
+    /// Whether to follow `robots.txt` when following links
+    Robots(bool),
+}
+
+/// Parses a command given to `-e` as `name = value`. Like wget, case and any
+/// `-` or `_` in the name don't matter.
+fn parse_command(s: &str) -> Result<WgetrcCommand, String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("Invalid command {s}, expected 'name = value'"))?;
+    let name = name.trim().to_ascii_lowercase().replace(['-', '_'], "");
+    let value = value.trim();
+    let flag = || match value.to_ascii_lowercase().as_str() {
+        "on" | "yes" | "1" => Ok(true),
+        "off" | "no" | "0" => Ok(false),
+        _ => Err(format!(
+            "Invalid value {value} for {name}, expected on or off"
+        )),
+    };
+    match name.as_str() {
+        "robots" => flag().map(WgetrcCommand::Robots),
+        _ => Err(format!("Unknown or unsupported command {name}")),
+    }
+}
+
+/// Parses a header given to `--header` as `Name: value`
+fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("Invalid header {s}, expected 'Name: value'"))?;
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c.is_control()) {
+        return Err(format!("Invalid header name {name:?}"));
+    }
+    Ok((name.to_string(), parse_header_value(value.trim())?))
+}
+
+/// Checks a header value given on the command line. Control characters, a
+/// line break above all, would let it add headers of its own.
+fn parse_header_value(s: &str) -> Result<String, String> {
+    if s.contains(|c: char| c.is_control() && c != '\t') {
+        return Err(format!("Invalid header value {s:?}"));
+    }
+    Ok(s.to_string())
+}
+
+/// The headers `options` add to or remove from every request. Those given
+/// with `--header` come last, so they win over the other options. A header
+/// given more than once with `--header` is sent once with all the values,
+/// unless the last is empty.
+fn request_headers(options: &Options) -> Vec<(String, String)> {
+    let mut headers = Vec::new();
+    if let Some(user_agent) = &options.user_agent {
+        headers.push(("User-Agent".to_string(), user_agent.clone()));
+    }
+    if let Some(referer) = &options.referer {
+        headers.push(("Referer".to_string(), referer.clone()));
+    }
+    if options.no_cache {
+        headers.push(("Cache-Control".to_string(), "no-cache".to_string()));
+        // For HTTP/1.0 caches
+        headers.push(("Pragma".to_string(), "no-cache".to_string()));
     }
-    let mut has_error = false;
+    let mut custom: Vec<(String, String)> = Vec::new();
+    for (name, value) in &options.headers {
+        match custom
+            .iter_mut()
+            .find(|(earlier, _)| earlier.eq_ignore_ascii_case(name))
+        {
+            Some((_, earlier)) if !earlier.is_empty() && !value.is_empty() => {
+                earlier.push_str(", ");
+                earlier.push_str(value);
+            }
+            Some((_, earlier)) => earlier.clone_from(value),
+            None => custom.push((name.clone(), value.clone())),
+        }
+    }
+    headers.extend(custom);
+    headers
+}
+
+/// A maximum recursion depth, where `None` is no limit. A type of its own
+/// since clap would treat a plain `Option` as an optional argument.
+type Depth = Option<usize>;
+
+fn main() {
+    let options = Options::parse_from(args::expand_short_options(std::env::args_os()));
+    let logger = match open_log(&options) {
+        Ok(logger) => logger,
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            std::process::exit(ExitStatus::of(&e) as i32);
+        }
+    };
     let config = Configuration {
-        debug: options.debug,
+        logger: Arc::new(logger),
+        rate_limiter: options
+            .limit_rate
+            .filter(|rate| *rate > 0)
+            .map(|rate| Arc::new(RateLimiter::new(rate))),
+        http2_prior_knowledge: options.http2_prior_knowledge,
+        header_limits: Default::default(),
+        http_version: options.http_version,
+        no_keep_alive: options.no_http_keep_alive,
+        accept_http09: options.http09,
+        headers: request_headers(&options),
+        server_response: options.server_response,
+    };
+    let status = run(options, &config).unwrap_or_else(|e| {
+        error!(config, "Error: {e:?}");
+        ExitStatus::of(&e)
+    });
+    std::process::exit(status as i32);
+}
+
+/// Sets up logging to stderr or the log file, as `options` ask
+fn open_log(options: &Options) -> anyhow::Result<Logger> {
+    let verbosity = if options.debug > 0 {
+        Verbosity::Debug(options.debug)
+    } else if options.quiet {
+        Verbosity::Quiet
+    } else if options.no_verbose {
+        Verbosity::NotVerbose
+    } else {
+        Verbosity::Verbose
+    };
+    let output: Box<dyn io::Write + Send> = if let Some(path) = &options.output_file {
+        Box::new(File::create(path).context("Could not create log file")?)
+    } else if let Some(path) = &options.append_output {
+        Box::new(
+            File::options()
+                .create(true)
+                .append(true)
+                .open(path)
+                .context("Could not open log file")?,
+        )
+    } else {
+        Box::new(io::stderr())
     };
+    Ok(Logger::new(verbosity, output))
+}
+
+fn run(options: Options, config: &Configuration) -> anyhow::Result<ExitStatus> {
+    debug!(config, "{options:?}");
+    let mut urls = options.urls.clone();
+    if let Some(input_file) = &options.input_file {
+        urls.extend(input::read_urls(
+            input_file,
+            options.force_html,
+            options.base.as_deref(),
+            config,
+        )?);
+    }
+    if urls.is_empty() {
+        return Err(anyhow!("Missing URL"));
+    }
+    let mut status = ExitStatus::Success;
     let mut output_file = options
-        .output_file
+        .output_document
         .map(|path| {
             if path == "-" {
-                if config.debug > 0 {
-                    println!("Writing to stdout");
-                }
+                debug!(config, "Writing to stdout");
                 Ok(Box::new(io::stdout()) as Box<dyn io::Write>)
             } else {
-                if config.debug > 0 {
-                    println!("Writing to {path}");
-                }
+                debug!(config, "Writing to {path}");
                 File::create(path).map(|f| Box::new(f) as Box<dyn io::Write>)
             }
         })
         // Go from Option<Result<...>> to Result<Option<...>>
         .map_or(Ok(None), |r| r.map(Some))?;
-    let mut connection_cache = ConnectionCache::default();
-    for url in options.urls {
-        let mut current_url = url;
-        let mut successful = false;
-        while !successful {
-            let parsed_url = ParsedUrl::parse(&current_url, &config)?;
-            if config.debug > 0 {
-                println!("{parsed_url:?}");
-            }
-            if parsed_url.protocol != Protocol::Http {
-                return Err(anyhow::anyhow!(
-                    "Protocols other than HTTP are not yet implemented"
-                ));
+    let post_data = match (&options.post_data, &options.post_file) {
+        (Some(data), _) => Some(data.clone().into_bytes()),
+        (None, Some(path)) => {
+            Some(fs::read(path).with_context(|| format!("Could not read {}", path.display()))?)
+        }
+        (None, None) => None,
+    };
+    let shows_progress = matches!(
+        config.logger.verbosity(),
+        Verbosity::Verbose | Verbosity::Debug(_)
+    );
+    let logs_to_terminal = options.output_file.is_none()
+        && options.append_output.is_none()
+        && io::stderr().is_terminal();
+    let download_options = DownloadOptions {
+        concatenate: output_file.is_some(),
+        segmentation: (options.segments.get() > 1 || options.chunk_size.is_some()).then_some(
+            Segmentation {
+                segments: options.segments.get(),
+                chunk_size: options.chunk_size,
+            },
+        ),
+        // Several bars redrawing over each other would be unreadable
+        progress: (shows_progress
+            && !options.spider
+            && (options.parallel.get() == 1 || options.show_progress))
+            .then(|| options.progress.for_terminal(logs_to_terminal)),
+        rate_unit: options.report_speed,
+        quota: options.quota,
+        wait: WaitPolicy {
+            wait: options.wait.unwrap_or_default(),
+            random: options.random_wait,
+            wait_retry: options.waitretry,
+        },
+        tries: options.tries,
+        timestamping: options.timestamping,
+        if_modified_since: !options.no_if_modified_since,
+        use_server_timestamps: !options.no_use_server_timestamps,
+        etag: options.etag,
+        // Conditional downloads replace the file they were checked against
+        existing_files: if options.no_clobber {
+            ExistingFiles::Skip
+        } else if options.timestamping || options.etag || options.backups > 0 {
+            ExistingFiles::Replace {
+                backups: options.backups,
             }
-            let conn = connection_cache.get_connection(&parsed_url, &config)?;
-            let result = conn.send_request(&parsed_url.path, &config);
+        } else {
+            ExistingFiles::Number
+        },
+        paths: PathMapping {
+            prefix: options.directory_prefix.unwrap_or_default(),
+            directories: (options.force_directories || options.recursive)
+                && !options.no_directories,
+            host_directories: !options.no_host_directories,
+            protocol_directories: options.protocol_directories,
+            cut_dirs: options.cut_dirs,
+            default_page: options.default_page,
+            restrictions: options.restrict_file_names.unwrap_or_default(),
+            content_disposition: options.content_disposition,
+            adjust_extension: options.adjust_extension,
+        },
+        follow_links: options.recursive,
+        spider: options.spider,
+        start_pos: options.start_pos,
+        post_data,
+        pipelining: options.pipeline,
+        continue_wait: options.continue_wait,
+        save_headers: options.save_headers,
+    };
+    // Several requests only go at once over HTTP/2, or when pipelining
+    let batches = (config.http2_prior_knowledge || download_options.pipelining)
+        && download_options.can_batch();
+    let obeys_robots = options
+        .commands
+        .iter()
+        .fold(true, |_, command| match command {
+            WgetrcCommand::Robots(on) => *on,
+        });
+    let robots = RobotsCache::default();
+    let robots = (options.recursive && obeys_robots).then_some(&robots);
+    let stats = SessionStats::default();
+    let mut visited = 0;
+    let mut broken_links = Vec::new();
+    recursion::crawl(
+        &urls,
+        if options.recursive {
+            options.level
+        } else {
+            Some(0)
+        },
+        options.parallel.get(),
+        |link| {
+            batches
+                .then(|| download::server_of(&link.url, config))
+                .flatten()
+        },
+        |connection_cache: &mut ConnectionCache, links| {
+            let urls: Vec<&str> = links.iter().map(|link| link.url.as_str()).collect();
+            download::download_batch(
+                &urls,
+                connection_cache,
+                config,
+                &download_options,
+                &stats,
+                robots,
+            )
+        },
+        found_links,
+        |link, result| {
+            visited += 1;
             match result {
-                Ok(response) => {
-                    if config.debug > 0 {
-                        println!(
-                            "------ response start ------\n{response}\n------ response end -----"
-                        );
-                    }
-                    match response.status_family() {
-                        HttpStatusFamily::Successful => {
-                            successful = true;
-                            if let Some(output_file) = &mut output_file {
-                                if let Err(e) = output_file.write_all(response.get_data()) {
-                                    eprintln!("Could not write data to output file: {e}");
-                                }
-                            } else if let Err(e) =
-                                File::create(parsed_url.filename)?.write_all(response.get_data())
-                            {
-                                eprintln!("Could not write data to output file: {e}");
-                            }
-                        }
-                        HttpStatusFamily::Redirection => {
-                            if let Some(new_url) = response.get_header("Location") {
-                                if config.debug > 1 {
-                                    println!(
-                                        "Got {} with Location \"{}\"",
-                                        response.status_code, new_url
-                                    );
-                                }
-                                current_url = new_url.to_string();
-                            } else {
-                                eprintln!("Got {} without a Location!", response.status_code);
-                                has_error = true;
-                                break;
-                            }
-                        }
-                        HttpStatusFamily::Informational => {
-                            eprintln!("Received Informational response?");
-                            let bytes = response.serialize();
-                            let response_string = String::from_utf8_lossy(&bytes);
-                            println!("{response_string}");
-                            has_error = true;
-                            successful = true;
-                        }
-                        HttpStatusFamily::ClientError => {
-                            eprintln!("ServerError!");
-                            let bytes = response.serialize();
-                            let response_string = String::from_utf8_lossy(&bytes);
-                            println!("{response_string}");
-                            has_error = true;
-                            successful = true;
-                        }
-                        HttpStatusFamily::ServerError => {
-                            eprintln!("ServerError!");
-                            let bytes = response.serialize();
-                            let response_string = String::from_utf8_lossy(&bytes);
-                            println!("{response_string}");
-                            has_error = true;
-                            successful = true;
+                Ok(Downloaded {
+                    data: Some(data), ..
+                }) => {
+                    if let Some(output_file) = &mut output_file {
+                        if let Err(e) = output_file.write_all(&data) {
+                            error!(config, "Could not write data to output file: {e}");
+                            status = status.combine(ExitStatus::FileIo);
                         }
                     }
                 }
+                Ok(_) => {}
                 Err(e) => {
-                    eprintln!("{e:?}");
-                    has_error = true;
-                    break;
+                    error!(config, "{}: {e:?}", link.url);
+                    status = status.combine(ExitStatus::of(&e));
+                    if options.spider {
+                        broken_links.push((link.clone(), e));
+                    }
                 }
             }
+        },
+    );
+    if options.spider {
+        report_broken_links(&broken_links, config);
+    } else if visited > 1 {
+        info!(config, "{}", stats.summary(options.report_speed));
+    }
+    Ok(status)
+}
+
+fn found_links(result: &anyhow::Result<Downloaded>) -> &[String] {
+    result.as_ref().map_or(&[], |downloaded| &downloaded.links)
+}
+
+/// Lists the links found broken in spider mode, each with the page that
+/// linked to it
+fn report_broken_links(broken_links: &[(Link, anyhow::Error)], config: &Configuration) {
+    match broken_links.len() {
+        0 => error!(config, "Found no broken links."),
+        1 => error!(config, "Found 1 broken link."),
+        count => error!(config, "Found {count} broken links."),
+    }
+    for (link, error) in broken_links {
+        error!(config, "\n{} ({error})", link.url);
+        if let Some(referrer) = &link.referrer {
+            error!(config, "    linked from {referrer}");
         }
     }
-    if has_error {
-        std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn options_are_valid() {
+        Options::command().debug_assert();
+    }
+
+    #[test]
+    fn parses_headers() {
+        assert_eq!(
+            parse_header("Accept-Language:  fr, en "),
+            Ok(("Accept-Language".to_string(), "fr, en".to_string()))
+        );
+        assert_eq!(
+            parse_header("Accept-Encoding:"),
+            Ok(("Accept-Encoding".to_string(), String::new()))
+        );
+        assert!(parse_header("Accept-Language fr").is_err());
+        assert!(parse_header(": fr").is_err());
+        assert!(parse_header("Accept Language: fr").is_err());
+        assert!(parse_header("X-Test: a\r\nInjected: b").is_err());
+        assert!(parse_header("X-Test: a\nb").is_err());
+        assert_eq!(
+            parse_header("X-Test: a\tb"),
+            Ok(("X-Test".to_string(), "a\tb".to_string()))
+        );
     }
 
-    Ok(())
+    #[test]
+    fn rejects_control_characters_in_header_options() {
+        for args in [
+            ["rust_wget", "-U", "agent\r\nInjected: yes"],
+            ["rust_wget", "--referer", "http://example.com/\n"],
+            ["rust_wget", "--header", "Accept: */*\u{0}"],
+        ] {
+            assert!(
+                Options::try_parse_from(args.into_iter().chain(["http://example.com/"])).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn joins_repeated_custom_headers() {
+        let options = Options::parse_from([
+            "rust_wget",
+            "--header",
+            "Accept-Language: fr",
+            "--header",
+            "X-Removed: yes",
+            "--header",
+            "accept-language: en",
+            "--header",
+            "X-Removed:",
+            "http://example.com/file",
+        ]);
+        assert_eq!(
+            request_headers(&options),
+            [
+                ("Accept-Language".to_string(), "fr, en".to_string()),
+                ("X-Removed".to_string(), String::new()),
+            ]
+        );
+    }
+
+    #[test]
+    fn header_options_come_before_custom_headers() {
+        let options = Options::parse_from([
+            "rust_wget",
+            "-U",
+            "agent",
+            "--no-cache",
+            "--header",
+            "User-Agent: other",
+            "--referer=http://example.com/",
+            "http://example.com/file",
+        ]);
+        let headers = request_headers(&options);
+        let names: Vec<&str> = headers.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "User-Agent",
+                "Referer",
+                "Cache-Control",
+                "Pragma",
+                "User-Agent"
+            ]
+        );
+        assert_eq!(headers[4].1, "other");
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse_command("robots=off"),
+            Ok(WgetrcCommand::Robots(false))
+        );
+        assert_eq!(
+            parse_command("Robots = ON"),
+            Ok(WgetrcCommand::Robots(true))
+        );
+        assert_eq!(parse_command("ro_bots=0"), Ok(WgetrcCommand::Robots(false)));
+        assert!(parse_command("robots").is_err());
+        assert!(parse_command("robots=maybe").is_err());
+        assert!(parse_command("tries=3").is_err());
+    }
 }
@@ -0,0 +1,244 @@
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use request_rs::http::{HttpDate, HttpResponse};
+
+/// The extension of the file metadata is kept in when extended attributes aren't available
+const SIDECAR_EXTENSION: &str = "wget-meta";
+
+/// What the server said about a downloaded file, kept alongside it so the
+/// next run can ask whether it has changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub url: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<HttpDate>,
+}
+
+impl FileMetadata {
+    pub fn from_response(url: &str, response: &HttpResponse) -> Self {
+        FileMetadata {
+            url: Some(url.to_string()),
+            etag: response.get_header("ETag").map(str::to_string),
+            last_modified: response.last_modified(),
+        }
+    }
+
+    /// Reads the metadata stored for `path`, from its extended attributes if
+    /// it has them or else from its sidecar file. Returns `None` if nothing
+    /// has been stored.
+    pub fn load(path: &Path) -> io::Result<Option<Self>> {
+        let stored = match xattr::get(path) {
+            Ok(Some(stored)) => Some(stored),
+            Ok(None) | Err(_) => match fs::read(sidecar_path(path)) {
+                Ok(stored) => Some(stored),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                Err(e) => return Err(e),
+            },
+        };
+        Ok(stored.map(|stored| Self::parse(&String::from_utf8_lossy(&stored))))
+    }
+
+    /// Stores the metadata for `path` in its extended attributes, falling
+    /// back to a sidecar file if they can't be set for any reason, like the
+    /// file system not supporting them or limiting their size
+    pub fn store(&self, path: &Path) -> io::Result<()> {
+        let serialized = self.serialize();
+        match xattr::set(path, serialized.as_bytes()) {
+            Ok(()) => Ok(()),
+            Err(_) => fs::write(sidecar_path(path), serialized),
+        }
+    }
+
+    fn serialize(&self) -> String {
+        let mut serialized = String::new();
+        if let Some(url) = &self.url {
+            serialized += &format!("url: {url}\n");
+        }
+        if let Some(etag) = &self.etag {
+            serialized += &format!("etag: {etag}\n");
+        }
+        if let Some(last_modified) = &self.last_modified {
+            serialized += &format!("last-modified: {last_modified}\n");
+        }
+        serialized
+    }
+
+    /// Parses the output of `serialize`, ignoring anything it doesn't recognise
+    fn parse(s: &str) -> Self {
+        let mut metadata = FileMetadata::default();
+        for line in s.lines() {
+            match line.split_once(": ") {
+                Some(("url", url)) => metadata.url = Some(url.to_string()),
+                Some(("etag", etag)) => metadata.etag = Some(etag.to_string()),
+                Some(("last-modified", date)) => metadata.last_modified = date.parse().ok(),
+                _ => {}
+            }
+        }
+        metadata
+    }
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = OsString::from(path.as_os_str());
+    sidecar.push(".");
+    sidecar.push(SIDECAR_EXTENSION);
+    PathBuf::from(sidecar)
+}
+
+#[cfg(target_os = "linux")]
+mod xattr {
+    use std::{
+        ffi::{CStr, CString},
+        io,
+        os::unix::ffi::OsStrExt,
+        path::Path,
+        ptr,
+    };
+
+    const NAME: &CStr = c"user.rust_wget.metadata";
+
+    fn error() -> io::Error {
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() == Some(libc::ENOTSUP) {
+            io::Error::new(io::ErrorKind::Unsupported, error)
+        } else {
+            error
+        }
+    }
+
+    pub fn get(path: &Path) -> io::Result<Option<Vec<u8>>> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: both strings are nul terminated, and a null buffer of size
+        // 0 asks for the size of the value without writing anything
+        let size = unsafe { libc::getxattr(path.as_ptr(), NAME.as_ptr(), ptr::null_mut(), 0) };
+        if size < 0 {
+            return match error() {
+                e if e.raw_os_error() == Some(libc::ENODATA) => Ok(None),
+                e => Err(e),
+            };
+        }
+        let mut value = vec![0u8; size as usize];
+        // SAFETY: `value` is valid for writes of `value.len()` bytes
+        let size = unsafe {
+            libc::getxattr(
+                path.as_ptr(),
+                NAME.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        if size < 0 {
+            return Err(error());
+        }
+        value.truncate(size as usize);
+        Ok(Some(value))
+    }
+
+    pub fn set(path: &Path, value: &[u8]) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        // SAFETY: both strings are nul terminated and `value` is valid for
+        // reads of `value.len()` bytes
+        let result = unsafe {
+            libc::setxattr(
+                path.as_ptr(),
+                NAME.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if result < 0 {
+            return Err(error());
+        }
+        Ok(())
+    }
+}
+
+/// Extended attributes are only supported on Linux, so everywhere else
+/// metadata always goes in a sidecar file
+#[cfg(not(target_os = "linux"))]
+mod xattr {
+    use std::{io, path::Path};
+
+    pub fn get(_path: &Path) -> io::Result<Option<Vec<u8>>> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    pub fn set(_path: &Path, _value: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    fn metadata() -> FileMetadata {
+        FileMetadata {
+            url: Some("http://example.com/file".to_string()),
+            etag: Some("\"abc\"".to_string()),
+            last_modified: "Sun, 06 Nov 1994 08:49:37 GMT".parse().ok(),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        assert_eq!(FileMetadata::parse(&metadata().serialize()), metadata());
+        assert_eq!(FileMetadata::parse(""), FileMetadata::default());
+        assert_eq!(
+            FileMetadata::parse("etag: W/\"x\"\nsomething: else\n"),
+            FileMetadata {
+                etag: Some("W/\"x\"".to_string()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn names_sidecar_after_file() {
+        assert_eq!(
+            sidecar_path(Path::new("dir/file.txt")),
+            PathBuf::from("dir/file.txt.wget-meta")
+        );
+    }
+
+    #[test]
+    fn stores_and_loads_metadata() {
+        let path = std::env::temp_dir().join(format!("rust_wget_metadata_{}", process::id()));
+        fs::write(&path, "contents").expect("Could not create file");
+        assert_eq!(FileMetadata::load(&path).expect("Could not load"), None);
+
+        metadata().store(&path).expect("Could not store");
+        assert_eq!(
+            FileMetadata::load(&path).expect("Could not load"),
+            Some(metadata())
+        );
+        fs::remove_file(&path).expect("Could not remove file");
+        let _ = fs::remove_file(sidecar_path(&path));
+    }
+
+    #[test]
+    fn falls_back_to_sidecar_when_attributes_fail() {
+        let path = std::env::temp_dir().join(format!("rust_wget_metadata_large_{}", process::id()));
+        fs::write(&path, "contents").expect("Could not create file");
+        // Larger than any extended attribute may be
+        let metadata = FileMetadata {
+            url: Some(format!("http://example.com/{}", "a".repeat(70_000))),
+            ..metadata()
+        };
+        metadata.store(&path).expect("Could not store");
+        assert!(sidecar_path(&path).exists());
+        assert_eq!(
+            FileMetadata::load(&path).expect("Could not load"),
+            Some(metadata)
+        );
+        fs::remove_file(&path).expect("Could not remove file");
+        fs::remove_file(sidecar_path(&path)).expect("Could not remove sidecar");
+    }
+}
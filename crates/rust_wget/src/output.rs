@@ -0,0 +1,423 @@
+use std::{
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Tells apart the temporary files of downloads running at the same time
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// The longest file name most file systems allow, in bytes
+const NAME_MAX: usize = 255;
+
+/// What to do when a download would be saved over an existing file
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExistingFiles {
+    /// Save under the first free name of `name.1`, `name.2` and so on, like wget
+    #[default]
+    Number,
+    /// Don't download the file at all, from `--no-clobber`
+    Skip,
+    /// Replace the file, first moving up to `backups` previous versions to
+    /// `name.1`, `name.2` and so on
+    Replace { backups: usize },
+}
+
+impl ExistingFiles {
+    /// Whether a download meant for `path` is skipped, as far as can be told
+    /// before it starts. Another download may still take the name first,
+    /// which `PendingFile::create` finds out.
+    pub fn skips(&self, path: &Path) -> bool {
+        *self == ExistingFiles::Skip && path.exists()
+    }
+}
+
+/// `path` with `.n` appended, e.g. `index.html.2`
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    with_suffix(path, &format!(".{n}"))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// `path` with `suffix` appended, cutting the file name short if the whole
+/// of it would be too long. Only for temporary files, as names cut short
+/// can clash, which `reserve` finds out.
+fn with_temp_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut end = name.len().min(NAME_MAX - suffix.len());
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    path.with_file_name(format!("{}{suffix}", &name[..end]))
+}
+
+/// A download in progress, which is written to a temporary file next to
+/// `target` and only moved into place once it is complete. If it is dropped
+/// without being committed, the temporary file is removed, so a failed
+/// transfer never leaves a half-written file behind or damages an old copy.
+///
+/// Unless existing files are replaced, the target name is reserved by
+/// creating it empty straight away, so downloads running in parallel never
+/// pick the same name.
+#[derive(Debug)]
+pub struct PendingFile {
+    target: PathBuf,
+    temp: PathBuf,
+    existing: ExistingFiles,
+    /// The empty file at `target` holding the name for us
+    reservation: Option<File>,
+    committed: bool,
+}
+
+impl PendingFile {
+    /// Starts a download meant for `path`, choosing its name as `existing`
+    /// says, or returns `None` if it is to be skipped
+    pub fn create(path: &Path, existing: ExistingFiles) -> io::Result<Option<Self>> {
+        let (target, reservation) = match existing {
+            ExistingFiles::Replace { .. } => (path.to_path_buf(), None),
+            ExistingFiles::Skip => match reserve(path) {
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Ok(None),
+                result => (path.to_path_buf(), Some(result?)),
+            },
+            ExistingFiles::Number => {
+                let (target, file) = reserve_numbered(path)?;
+                (target, Some(file))
+            }
+        };
+        // Dropping this on failure gives up the reserved name again
+        let mut pending = PendingFile {
+            target,
+            temp: PathBuf::new(),
+            existing,
+            reservation,
+            committed: false,
+        };
+        pending.temp = loop {
+            let n = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+            let temp = with_temp_suffix(&pending.target, &format!(".part.{}.{n}", process::id()));
+            match reserve(&temp) {
+                Ok(_) => break temp,
+                // Left over from an earlier run that happened to have our id
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(e) => return Err(e),
+            }
+        };
+        Ok(Some(pending))
+    }
+
+    /// Where the download will be saved
+    pub fn target(&self) -> &Path {
+        &self.target
+    }
+
+    /// Where the download should be written to until it is committed
+    pub fn temp_path(&self) -> &Path {
+        &self.temp
+    }
+
+    /// Moves the finished download to its target. Existing files are moved
+    /// out of the way first if they are to be replaced, and are otherwise
+    /// never written over, even if another program took the name meanwhile.
+    pub fn commit(mut self) -> io::Result<()> {
+        if let ExistingFiles::Replace { backups } = self.existing {
+            rotate_backups(&self.target, backups)?;
+            fs::rename(&self.temp, &self.target)?;
+        } else {
+            // Swap the empty file reserving the name for the download, failing
+            // rather than replacing anything that appears in between
+            if !self.holds_name() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} was created by someone else", self.target.display()),
+                ));
+            }
+            fs::remove_file(&self.target)?;
+            match fs::hard_link(&self.temp, &self.target) {
+                Ok(()) => fs::remove_file(&self.temp)?,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => return Err(e),
+                // Not every file system has hard links
+                Err(e) => match rename_no_replace(&self.temp, &self.target) {
+                    Err(rename) if rename.kind() == io::ErrorKind::Unsupported => return Err(e),
+                    result => result?,
+                },
+            }
+            self.reservation = None;
+        }
+        self.committed = true;
+        Ok(())
+    }
+
+    /// Whether `target` is still the empty file we reserved it with, and not
+    /// something put in its place since
+    fn holds_name(&self) -> bool {
+        let Some(reservation) = &self.reservation else {
+            return false;
+        };
+        match (reservation.metadata(), fs::symlink_metadata(&self.target)) {
+            (Ok(ours), Ok(current)) => {
+                current.is_file()
+                    && current.len() == 0
+                    && ours.len() == 0
+                    && current.modified().ok() == ours.modified().ok()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            // There may be no temporary file if the download failed early
+            let _ = fs::remove_file(&self.temp);
+            // Nor is the name kept reserved
+            if self.holds_name() {
+                let _ = fs::remove_file(&self.target);
+            }
+        }
+    }
+}
+
+/// Creates `path` empty, failing if it already exists
+fn reserve(path: &Path) -> io::Result<File> {
+    File::options().write(true).create_new(true).open(path)
+}
+
+/// Moves `from` to `to`, failing if `to` already exists
+#[cfg(target_os = "linux")]
+fn rename_no_replace(from: &Path, to: &Path) -> io::Result<()> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let from = CString::new(from.as_os_str().as_bytes())?;
+    let to = CString::new(to.as_os_str().as_bytes())?;
+    // SAFETY: both paths are nul terminated
+    let result = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Elsewhere a rename may replace `to`, so it isn't done at all
+#[cfg(not(target_os = "linux"))]
+fn rename_no_replace(_from: &Path, _to: &Path) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Reserves the first free name of `path`, `path.1`, `path.2` and so on
+fn reserve_numbered(path: &Path) -> io::Result<(PathBuf, File)> {
+    let candidates =
+        std::iter::once(path.to_path_buf()).chain((1..usize::MAX).map(|n| numbered(path, n)));
+    for candidate in candidates {
+        match reserve(&candidate) {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("No free name left for {}", path.display()),
+    ))
+}
+
+/// Shifts `path.1` to `path.2` and so on, dropping the oldest once there are
+/// `backups` of them, then moves `path` to `path.1`
+fn rotate_backups(path: &Path, backups: usize) -> io::Result<()> {
+    if backups == 0 || !path.exists() {
+        return Ok(());
+    }
+    for n in (1..backups).rev() {
+        let backup = numbered(path, n);
+        if backup.exists() {
+            fs::rename(backup, numbered(path, n + 1))?;
+        }
+    }
+    fs::rename(path, numbered(path, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+
+    /// A fresh, empty directory for each test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust_wget_output_{}_{name}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Could not create test directory");
+        dir
+    }
+
+    fn read(path: PathBuf) -> String {
+        fs::read_to_string(path).expect("Could not read file")
+    }
+
+    fn create(path: &Path, existing: ExistingFiles) -> Option<PendingFile> {
+        PendingFile::create(path, existing).expect("Could not create file")
+    }
+
+    fn save(path: &Path, existing: ExistingFiles, contents: &str) {
+        let pending = create(path, existing).expect("Download was skipped");
+        fs::write(pending.temp_path(), contents).expect("Could not write file");
+        pending.commit().expect("Could not commit");
+    }
+
+    fn file_count(dir: &Path) -> usize {
+        fs::read_dir(dir).expect("Could not list directory").count()
+    }
+
+    #[test]
+    fn numbers_duplicate_names() {
+        let dir = test_dir("number");
+        let path = dir.join("file");
+        // Downloads in progress at the same time each get their own name
+        let first = create(&path, ExistingFiles::Number).expect("Download was skipped");
+        let second = create(&path, ExistingFiles::Number).expect("Download was skipped");
+        assert_eq!(first.target(), path);
+        assert_eq!(second.target(), dir.join("file.1"));
+        fs::write(second.temp_path(), "1").expect("Could not write file");
+        second.commit().expect("Could not commit");
+        drop(first);
+        assert_eq!(read(dir.join("file.1")), "1");
+        assert!(!path.exists());
+
+        save(&path, ExistingFiles::Number, "0");
+        save(&path, ExistingFiles::Number, "2");
+        assert_eq!(read(path), "0");
+        assert_eq!(read(dir.join("file.2")), "2");
+        assert_eq!(file_count(&dir), 3);
+        fs::remove_dir_all(dir).expect("Could not remove test directory");
+    }
+
+    #[test]
+    fn skips_existing_files() {
+        let dir = test_dir("skip");
+        let path = dir.join("file");
+        assert!(!ExistingFiles::Skip.skips(&path));
+        let pending = create(&path, ExistingFiles::Skip).expect("Download was skipped");
+        // Taken by the download in progress
+        assert!(ExistingFiles::Skip.skips(&path));
+        assert!(create(&path, ExistingFiles::Skip).is_none());
+        fs::write(pending.temp_path(), "0").expect("Could not write file");
+        pending.commit().expect("Could not commit");
+        assert!(create(&path, ExistingFiles::Skip).is_none());
+        assert_eq!(read(path), "0");
+        fs::remove_dir_all(dir).expect("Could not remove test directory");
+    }
+
+    #[test]
+    fn never_commits_over_other_files() {
+        let dir = test_dir("clobber");
+        let path = dir.join("file");
+        let pending = create(&path, ExistingFiles::Skip).expect("Download was skipped");
+        fs::write(pending.temp_path(), "ours").expect("Could not write file");
+        // Something else takes the name while the download runs
+        fs::remove_file(&path).expect("Could not remove file");
+        fs::write(&path, "theirs").expect("Could not write file");
+        assert!(pending.commit().is_err());
+        assert_eq!(read(path), "theirs");
+        assert_eq!(file_count(&dir), 1);
+        fs::remove_dir_all(dir).expect("Could not remove test directory");
+    }
+
+    #[test]
+    fn commits_by_renaming() {
+        let dir = test_dir("commit");
+        let path = dir.join("file");
+        fs::write(&path, "old").expect("Could not write file");
+        save(&path, ExistingFiles::Replace { backups: 0 }, "new");
+        assert_eq!(read(path), "new");
+        assert_eq!(file_count(&dir), 1);
+        fs::remove_dir_all(dir).expect("Could not remove test directory");
+    }
+
+    #[test]
+    fn keeps_temporary_names_short() {
+        let dir = test_dir("long");
+        let path = dir.join("a".repeat(crate::sanitize::MAX_NAME_LENGTH));
+        fs::write(&path, "old").expect("Could not write file");
+        let pending = create(&path, ExistingFiles::Number).expect("Download was skipped");
+        let temp_name = pending.temp_path().file_name().expect("No file name");
+        assert!(temp_name.len() <= NAME_MAX);
+        fs::write(pending.temp_path(), "new").expect("Could not write file");
+        let target = pending.target().to_path_buf();
+        pending.commit().expect("Could not commit");
+        assert_eq!(target, numbered(&path, 1));
+        assert_eq!(read(target), "new");
+        assert_eq!(file_count(&dir), 2);
+        fs::remove_dir_all(dir).expect("Could not remove test directory");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn renames_without_replacing() {
+        let dir = test_dir("noreplace");
+        let (from, to) = (dir.join("from"), dir.join("to"));
+        fs::write(&from, "ours").expect("Could not write file");
+        fs::write(&to, "theirs").expect("Could not write file");
+        let error = rename_no_replace(&from, &to).expect_err("Replaced a file");
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(read(to.clone()), "theirs");
+        fs::remove_file(&to).expect("Could not remove file");
+        rename_no_replace(&from, &to).expect("Could not rename");
+        assert_eq!(read(to), "ours");
+        assert!(!from.exists());
+        fs::remove_dir_all(dir).expect("Could not remove test directory");
+    }
+
+    #[test]
+    fn leaves_other_part_files_alone() {
+        let dir = test_dir("part");
+        let path = dir.join("file");
+        fs::write(dir.join("file.part"), "mine").expect("Could not write file");
+        save(&path, ExistingFiles::Number, "new");
+        drop(create(&path, ExistingFiles::Number));
+        assert_eq!(read(path), "new");
+        assert_eq!(read(dir.join("file.part")), "mine");
+        assert_eq!(file_count(&dir), 2);
+        fs::remove_dir_all(dir).expect("Could not remove test directory");
+    }
+
+    #[test]
+    fn removes_uncommitted_files() {
+        let dir = test_dir("uncommitted");
+        for existing in [ExistingFiles::Number, ExistingFiles::Replace { backups: 1 }] {
+            let pending = create(&dir.join("file"), existing).expect("Download was skipped");
+            fs::write(pending.temp_path(), "partial").expect("Could not write file");
+            drop(pending);
+            assert_eq!(file_count(&dir), 0);
+        }
+        fs::remove_dir_all(dir).expect("Could not remove test directory");
+    }
+
+    #[test]
+    fn rotates_backups() {
+        let dir = test_dir("backups");
+        let path = dir.join("file");
+        for version in ["1", "2", "3", "4"] {
+            save(&path, ExistingFiles::Replace { backups: 2 }, version);
+        }
+        assert_eq!(read(path), "4");
+        assert_eq!(read(dir.join("file.1")), "3");
+        assert_eq!(read(dir.join("file.2")), "2");
+        assert!(!dir.join("file.3").exists());
+        fs::remove_dir_all(dir).expect("Could not remove test directory");
+    }
+}
@@ -0,0 +1,431 @@
+use std::{borrow::Cow, path::PathBuf};
+
+use request_rs::url::ParsedUrl;
+
+use crate::sanitize::FileNameRestrictions;
+
+/// Decides where on disk the document at a URL is saved, from wget's
+/// directory options
+#[derive(Debug, Clone)]
+pub struct PathMapping {
+    /// The directory everything is saved under, from `-P/--directory-prefix`
+    pub prefix: PathBuf,
+    /// Recreate the URL's directories locally, as with `-x/--force-directories`.
+    /// Otherwise every file goes straight into `prefix`.
+    pub directories: bool,
+    /// Start the directories with the host name, unless `-nH/--no-host-directories`
+    pub host_directories: bool,
+    /// Start the directories with the protocol, from `--protocol-directories`
+    pub protocol_directories: bool,
+    /// Leave out this many of the URL's leading directories, from `--cut-dirs`
+    pub cut_dirs: usize,
+    /// The file name to use for URLs ending in `/`, from `--default-page`
+    pub default_page: String,
+    /// Which characters may appear in file names, from `--restrict-file-names`
+    pub restrictions: FileNameRestrictions,
+    /// Use the file name from `Content-Disposition` where the server sends
+    /// one, from `--content-disposition`
+    pub content_disposition: bool,
+    /// Add `.html` or `.css` to the names of HTML and CSS documents that
+    /// don't already end that way, from `-E/--adjust-extension`
+    pub adjust_extension: bool,
+}
+
+/// What a response says about how to name the document it holds
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NameHints<'a> {
+    /// The file name from `Content-Disposition`
+    pub file_name: Option<&'a str>,
+    /// The media type from `Content-Type`
+    pub content_type: Option<&'a str>,
+}
+
+impl Default for PathMapping {
+    fn default() -> Self {
+        PathMapping {
+            prefix: PathBuf::new(),
+            directories: false,
+            host_directories: true,
+            protocol_directories: false,
+            cut_dirs: 0,
+            default_page: "index.html".to_string(),
+            restrictions: FileNameRestrictions::default(),
+            content_disposition: false,
+            adjust_extension: false,
+        }
+    }
+}
+
+impl PathMapping {
+    /// The path the document at `url` should be saved to, before anything is
+    /// known about the response
+    pub fn local_path(&self, url: &ParsedUrl) -> PathBuf {
+        self.local_path_with(url, NameHints::default())
+    }
+
+    /// The path the document at `url` should be saved to, taking into account
+    /// what the response said about it
+    ///
+    /// Escapes in the URL are decoded, then every name is sanitised, and `..`
+    /// can only climb as far as the root of
+    /// the URL, so the path always stays inside `prefix`.
+    pub fn local_path_with(&self, url: &ParsedUrl, hints: NameHints) -> PathBuf {
+        let (url_path, query) = match url.path.split_once('?') {
+            Some((url_path, query)) => (url_path, Some(query)),
+            None => (url.path.as_str(), None),
+        };
+        let mut segments = Vec::new();
+        for segment in url_path.split('/') {
+            match segment {
+                "" | "." => {}
+                ".." => {
+                    segments.pop();
+                }
+                segment => segments.push(segment),
+            }
+        }
+        // A path ending in `/`, `.` or `..` names a directory
+        let file_name = match url_path.rsplit('/').next() {
+            Some("" | "." | "..") | None => &self.default_page,
+            Some(_) => segments.pop().expect("The last segment was just pushed"),
+        };
+        let mut file_name = match query {
+            Some(query) => format!(
+                "{}{}{}",
+                percent_decode(file_name),
+                self.restrictions.query_separator(),
+                percent_decode(query)
+            ),
+            None => percent_decode(file_name).into_owned(),
+        };
+        if let Some(name) = hints.file_name.filter(|_| self.content_disposition) {
+            // Only the last part of the name is used, so the server can't
+            // choose the directory
+            match name.rsplit(['/', '\\']).next() {
+                Some("" | "." | "..") | None => {}
+                Some(name) => file_name = name.to_string(),
+            }
+        }
+        if let Some(content_type) = hints.content_type.filter(|_| self.adjust_extension) {
+            if let Some(extension) = missing_extension(&file_name, content_type) {
+                file_name.push_str(extension);
+            }
+        }
+
+        let mut path = self.prefix.clone();
+        if self.directories {
+            if self.protocol_directories {
+                path.push(url.protocol.to_string());
+            }
+            if self.host_directories {
+                let host = if url.port == url.protocol.get_port() {
+                    url.domain_name.clone()
+                } else {
+                    format!("{}:{}", url.domain_name, url.port)
+                };
+                path.push(self.restrictions.sanitize(&host));
+            }
+            for segment in segments.into_iter().skip(self.cut_dirs) {
+                path.push(self.restrictions.sanitize(&percent_decode(segment)));
+            }
+        }
+        path.push(self.restrictions.sanitize(&file_name));
+        path
+    }
+}
+
+/// Decodes the `%XX` escapes in part of a URL. Anything that isn't an escape
+/// is kept as it is, and so is the whole of `s` if it doesn't decode to UTF-8.
+fn percent_decode(s: &str) -> Cow<'_, str> {
+    if !s.contains('%') {
+        return Cow::Borrowed(s);
+    }
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        let hex = after.get(..2).and_then(|hex| std::str::from_utf8(hex).ok());
+        match hex.filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit())) {
+            Some(hex) if byte == b'%' => {
+                bytes.push(u8::from_str_radix(hex, 16).expect("Checked for hex digits"));
+                rest = &after[2..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = after;
+            }
+        }
+    }
+    String::from_utf8(bytes).map_or(Cow::Borrowed(s), Cow::Owned)
+}
+
+/// The extension `file_name` should have for `content_type` but doesn't
+fn missing_extension(file_name: &str, content_type: &str) -> Option<&'static str> {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let (extension, accepted): (_, &[&str]) = match media_type.as_str() {
+        "text/html" | "application/xhtml+xml" => (".html", &[".html", ".htm"]),
+        "text/css" => (".css", &[".css"]),
+        _ => return None,
+    };
+    let lowercase_name = file_name.to_ascii_lowercase();
+    (!accepted
+        .iter()
+        .any(|accepted| lowercase_name.ends_with(accepted)))
+    .then_some(extension)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use request_rs::Configuration;
+
+    use super::*;
+
+    fn url(url: &str) -> ParsedUrl {
+        ParsedUrl::parse(url, &Configuration::default()).expect("Could not parse URL")
+    }
+
+    /// The mapping used on Unix, so tests don't depend on the platform
+    fn unix() -> PathMapping {
+        PathMapping {
+            restrictions: "unix".parse().expect("Could not parse restrictions"),
+            ..Default::default()
+        }
+    }
+
+    fn directories() -> PathMapping {
+        PathMapping {
+            directories: true,
+            ..unix()
+        }
+    }
+
+    #[test]
+    fn saves_to_file_name_by_default() {
+        let mapping = unix();
+        assert_eq!(
+            mapping.local_path(&url("http://example.com/a/b/c.txt")),
+            PathBuf::from("c.txt")
+        );
+        assert_eq!(
+            mapping.local_path(&url("http://example.com/a/")),
+            PathBuf::from("index.html")
+        );
+    }
+
+    #[test]
+    fn uses_prefix_and_default_page() {
+        let mapping = PathMapping {
+            prefix: PathBuf::from("downloads"),
+            default_page: "default.htm".to_string(),
+            ..unix()
+        };
+        assert_eq!(
+            mapping.local_path(&url("http://example.com/a/")),
+            PathBuf::from("downloads/default.htm")
+        );
+    }
+
+    #[test]
+    fn recreates_directories() {
+        assert_eq!(
+            directories().local_path(&url("http://example.com/a/b/c.txt")),
+            PathBuf::from("example.com/a/b/c.txt")
+        );
+        assert_eq!(
+            directories().local_path(&url("http://example.com")),
+            PathBuf::from("example.com/index.html")
+        );
+        assert_eq!(
+            directories().local_path(&url("http://example.com//a/./b/")),
+            PathBuf::from("example.com/a/b/index.html")
+        );
+    }
+
+    #[test]
+    fn includes_non_default_ports() {
+        assert_eq!(
+            directories().local_path(&url("http://example.com:8080/c.txt")),
+            PathBuf::from("example.com:8080/c.txt")
+        );
+    }
+
+    #[test]
+    fn adds_protocol_and_drops_host() {
+        let mapping = PathMapping {
+            protocol_directories: true,
+            host_directories: false,
+            ..directories()
+        };
+        assert_eq!(
+            mapping.local_path(&url("https://example.com/a/c.txt")),
+            PathBuf::from("https/a/c.txt")
+        );
+    }
+
+    #[test]
+    fn keeps_query_in_file_name() {
+        assert_eq!(
+            unix().local_path(&url("http://example.com/search?q=a/b")),
+            PathBuf::from("search?q=a%2Fb")
+        );
+        assert_eq!(
+            unix().local_path(&url("http://example.com/?q=1")),
+            PathBuf::from("index.html?q=1")
+        );
+        let windows = PathMapping {
+            restrictions: "windows".parse().expect("Could not parse restrictions"),
+            ..Default::default()
+        };
+        assert_eq!(
+            windows.local_path(&url("http://example.com/search?q=1")),
+            PathBuf::from("search@q=1")
+        );
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        assert_eq!(
+            unix().local_path(&url("http://localhost:8765/my%20file.txt")),
+            PathBuf::from("my file.txt")
+        );
+        assert_eq!(
+            directories().local_path(&url("http://example.com/caf%C3%A9/a%2Fb?q=%3F%zz")),
+            PathBuf::from("example.com/café/a%2Fb?q=?%zz")
+        );
+        // Not UTF-8, so left alone
+        assert_eq!(
+            unix().local_path(&url("http://example.com/%FF.txt")),
+            PathBuf::from("%FF.txt")
+        );
+        let windows = PathMapping {
+            restrictions: "windows".parse().expect("Could not parse restrictions"),
+            ..Default::default()
+        };
+        assert_eq!(
+            windows.local_path(&url("http://example.com/a%3Fb%25")),
+            PathBuf::from("a%3Fb%")
+        );
+    }
+
+    #[test]
+    fn stays_inside_prefix() {
+        let mapping = PathMapping {
+            prefix: PathBuf::from("out"),
+            ..directories()
+        };
+        assert_eq!(
+            mapping.local_path(&url("http://example.com/a/../../../etc/passwd")),
+            PathBuf::from("out/example.com/etc/passwd")
+        );
+        assert_eq!(
+            mapping.local_path(&url("http://example.com/a/..")),
+            PathBuf::from("out/example.com/index.html")
+        );
+        assert_eq!(
+            unix().local_path(&url("http://example.com/..")),
+            PathBuf::from("index.html")
+        );
+        for url in [
+            url("http://example.com/../../x/../../y"),
+            url("http://../..//../z"),
+        ] {
+            let path = mapping.local_path(&url);
+            assert!(path.starts_with("out"), "{path:?}");
+            assert!(
+                path.components()
+                    .all(|c| matches!(c, std::path::Component::Normal(_))),
+                "{path:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn uses_content_disposition_file_name() {
+        let mapping = PathMapping {
+            content_disposition: true,
+            ..directories()
+        };
+        let url = url("http://example.com/files/download?id=42");
+        let hints = NameHints {
+            file_name: Some("report.pdf"),
+            ..Default::default()
+        };
+        assert_eq!(
+            mapping.local_path_with(&url, hints),
+            PathBuf::from("example.com/files/report.pdf")
+        );
+        for file_name in ["../../.ssh/authorized_keys", "..\\evil.exe"] {
+            let hints = NameHints {
+                file_name: Some(file_name),
+                ..Default::default()
+            };
+            let path = mapping.local_path_with(&url, hints);
+            assert_eq!(path.parent(), Some(Path::new("example.com/files")));
+        }
+        // Ignored unless asked for
+        assert_eq!(
+            directories().local_path_with(&url, hints),
+            PathBuf::from("example.com/files/download?id=42")
+        );
+    }
+
+    #[test]
+    fn adjusts_extensions() {
+        let mapping = PathMapping {
+            adjust_extension: true,
+            ..unix()
+        };
+        let path = |url_str: &str, content_type: &str| {
+            let hints = NameHints {
+                content_type: Some(content_type),
+                ..Default::default()
+            };
+            mapping.local_path_with(&url(url_str), hints)
+        };
+        assert_eq!(
+            path(
+                "http://example.com/page.php?x=1",
+                "text/html; charset=utf-8"
+            ),
+            PathBuf::from("page.php?x=1.html")
+        );
+        assert_eq!(
+            path("http://example.com/style", "text/css"),
+            PathBuf::from("style.css")
+        );
+        assert_eq!(
+            path("http://example.com/page.HTM", "text/html"),
+            PathBuf::from("page.HTM")
+        );
+        assert_eq!(
+            path("http://example.com/", "text/html"),
+            PathBuf::from("index.html")
+        );
+        assert_eq!(
+            path("http://example.com/data", "application/json"),
+            PathBuf::from("data")
+        );
+    }
+
+    #[test]
+    fn cuts_leading_directories() {
+        let mapping = PathMapping {
+            cut_dirs: 2,
+            ..directories()
+        };
+        assert_eq!(
+            mapping.local_path(&url("http://example.com/a/b/c/d.txt")),
+            PathBuf::from("example.com/c/d.txt")
+        );
+        assert_eq!(
+            mapping.local_path(&url("http://example.com/a/d.txt")),
+            PathBuf::from("example.com/d.txt")
+        );
+    }
+}
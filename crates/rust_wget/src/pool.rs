@@ -0,0 +1,116 @@
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+/// Runs `work` over every job using up to `workers` threads.
+///
+/// Each worker thread owns its own `S`, so per-thread resources such as a
+/// `ConnectionCache` are never shared. Results are handed to `on_result` on the
+/// calling thread in the same order as `jobs`, no matter which worker finishes
+/// first.
+pub fn run<T, S, R, W, F>(jobs: &[T], workers: usize, work: W, mut on_result: F)
+where
+    T: Sync,
+    S: Default,
+    R: Send,
+    W: Fn(&mut S, &T) -> R + Sync,
+    F: FnMut(&T, R),
+{
+    let next_job = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..workers.min(jobs.len()) {
+            let tx = tx.clone();
+            let next_job = &next_job;
+            let work = &work;
+            scope.spawn(move || {
+                let mut state = S::default();
+                loop {
+                    let index = next_job.fetch_add(1, Ordering::Relaxed);
+                    if index >= jobs.len() {
+                        break;
+                    }
+                    let result = work(&mut state, &jobs[index]);
+                    if tx.send((index, result)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // Only the workers hold senders now, so the loop below ends once they
+        // have all finished
+        drop(tx);
+
+        let mut pending = BTreeMap::new();
+        let mut next_result = 0;
+        for (index, result) in rx {
+            pending.insert(index, result);
+            while let Some(result) = pending.remove(&next_result) {
+                on_result(&jobs[next_result], result);
+                next_result += 1;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread::sleep, time::Duration};
+
+    use super::*;
+
+    #[test]
+    fn runs_every_job() {
+        let jobs: Vec<u32> = (0..100).collect();
+        let mut sum = 0;
+        run(&jobs, 4, |_: &mut (), job| job * 2, |_, result| sum += result);
+        assert_eq!(sum, 9900);
+    }
+
+    #[test]
+    fn delivers_results_in_job_order() {
+        // Earlier jobs take longer, so they finish after later ones
+        let jobs: Vec<u64> = (0..8).rev().collect();
+        let mut results = Vec::new();
+        run(
+            &jobs,
+            8,
+            |_: &mut (), job| {
+                sleep(Duration::from_millis(job * 5));
+                *job
+            },
+            |job, result| {
+                assert_eq!(*job, result);
+                results.push(result);
+            },
+        );
+        assert_eq!(results, jobs);
+    }
+
+    #[test]
+    fn worker_state_persists_between_jobs() {
+        let jobs: Vec<u32> = (0..50).collect();
+        let mut counts = Vec::new();
+        run(
+            &jobs,
+            1,
+            |count: &mut usize, _| {
+                *count += 1;
+                *count
+            },
+            |_, count| counts.push(count),
+        );
+        assert_eq!(counts, (1..=50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn handles_no_jobs() {
+        let jobs: Vec<u32> = Vec::new();
+        run(&jobs, 4, |_: &mut (), _| (), |_, _| panic!("No jobs to run"));
+    }
+}
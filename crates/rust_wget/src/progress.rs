@@ -0,0 +1,674 @@
+use std::{
+    io::{self, Write},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use request_rs::log::{LogWriter, Logger};
+
+/// How often the bar is redrawn while data is arriving
+const BAR_REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+/// The number of characters between the `[` and `]` of the bar
+const BAR_WIDTH: usize = 30;
+/// The number of characters of the file name shown before the bar
+const BAR_NAME_WIDTH: usize = 20;
+
+/// The unit rates are shown in, from `--report-speed`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum RateUnit {
+    /// Bytes per second, in powers of 1024 like `345KB/s`
+    #[default]
+    Bytes,
+    /// Bits per second, in powers of 1000 like `2.83Mb/s`
+    Bits,
+}
+
+impl FromStr for RateUnit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bytes" => Ok(RateUnit::Bytes),
+            "bits" => Ok(RateUnit::Bits),
+            _ => Err(format!("Unknown speed unit {s}, expected bits or bytes")),
+        }
+    }
+}
+
+/// The sizes used when printing progress as dots, as in wget's `--progress=dot:<style>`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DotStyle {
+    /// The number of bytes each dot stands for
+    pub dot_bytes: u64,
+    /// The number of dots grouped together between spaces
+    pub dots_per_cluster: u64,
+    /// The number of dots on each line
+    pub dots_per_line: u64,
+}
+
+impl DotStyle {
+    pub const DEFAULT: DotStyle = DotStyle {
+        dot_bytes: 1024,
+        dots_per_cluster: 10,
+        dots_per_line: 50,
+    };
+    pub const BINARY: DotStyle = DotStyle {
+        dot_bytes: 8 * 1024,
+        dots_per_cluster: 16,
+        dots_per_line: 48,
+    };
+    pub const MEGA: DotStyle = DotStyle {
+        dot_bytes: 64 * 1024,
+        dots_per_cluster: 8,
+        dots_per_line: 48,
+    };
+    pub const GIGA: DotStyle = DotStyle {
+        dot_bytes: 1024 * 1024,
+        dots_per_cluster: 8,
+        dots_per_line: 32,
+    };
+}
+
+/// How download progress is displayed, parsed from wget's `--progress` values
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ProgressStyle {
+    /// A bar redrawn in place. Unless `force` is set this becomes dots when
+    /// stderr isn't a terminal, since redrawing makes a mess of log files.
+    Bar {
+        force: bool,
+    },
+    Dot(DotStyle),
+}
+
+impl ProgressStyle {
+    /// Picks the style to actually use, given whether stderr is a terminal
+    pub fn for_terminal(self, is_terminal: bool) -> Self {
+        match self {
+            ProgressStyle::Bar { force: false } if !is_terminal => {
+                ProgressStyle::Dot(DotStyle::DEFAULT)
+            }
+            style => style,
+        }
+    }
+}
+
+impl Default for ProgressStyle {
+    fn default() -> Self {
+        ProgressStyle::Bar { force: false }
+    }
+}
+
+impl FromStr for ProgressStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split(':');
+        match parts.next() {
+            Some("bar") => {
+                let mut force = false;
+                for param in parts {
+                    match param {
+                        "force" => force = true,
+                        // There is only ever one line of progress, so there is nothing to scroll
+                        "noscroll" => {}
+                        _ => return Err(format!("Unknown bar parameter {param}")),
+                    }
+                }
+                Ok(ProgressStyle::Bar { force })
+            }
+            Some("dot") => {
+                let style = match parts.next() {
+                    None | Some("default") => DotStyle::DEFAULT,
+                    Some("binary") => DotStyle::BINARY,
+                    Some("mega") => DotStyle::MEGA,
+                    Some("giga") => DotStyle::GIGA,
+                    Some(style) => return Err(format!("Unknown dot style {style}")),
+                };
+                if parts.next().is_some() {
+                    return Err(format!("Invalid progress style {s}"));
+                }
+                Ok(ProgressStyle::Dot(style))
+            }
+            _ => Err(format!("Invalid progress style {s}")),
+        }
+    }
+}
+
+/// Displays the progress of a single transfer on stderr, or another writer
+pub struct Progress<W: Write = LogWriter> {
+    style: ProgressStyle,
+    rate_unit: RateUnit,
+    name: String,
+    out: W,
+    start: Instant,
+    last_draw: Option<Instant>,
+    received: u64,
+    total: Option<u64>,
+    /// The received byte count and time of the last rate sample, used for
+    /// the current rate
+    sample: (u64, Instant),
+    current_rate: f64,
+    dots: u64,
+    /// When the current line of dots began, for the rate shown at its end
+    line_started: Instant,
+}
+
+impl Progress {
+    /// Shows progress in the output of `logger`, alongside everything else logged
+    pub fn new(style: ProgressStyle, name: &str, logger: Arc<Logger>) -> Self {
+        Progress::with_writer(style, name, LogWriter(logger))
+    }
+}
+
+impl<W: Write> Progress<W> {
+    pub fn with_writer(style: ProgressStyle, name: &str, out: W) -> Self {
+        let now = Instant::now();
+        Progress {
+            style,
+            rate_unit: RateUnit::default(),
+            name: name.to_string(),
+            out,
+            start: now,
+            last_draw: None,
+            received: 0,
+            total: None,
+            sample: (0, now),
+            current_rate: 0.0,
+            dots: 0,
+            line_started: now,
+        }
+    }
+
+    pub fn with_rate_unit(mut self, rate_unit: RateUnit) -> Self {
+        self.rate_unit = rate_unit;
+        self
+    }
+
+    /// Shows the transfer as `name` from now on, for when the file it is saved
+    /// to is only decided once it has arrived
+    pub fn rename(&mut self, name: &str) {
+        self.name = name.to_string();
+    }
+
+    /// Records that `received` bytes out of `total` have now arrived
+    pub fn update(&mut self, received: u64, total: Option<u64>) {
+        self.received = received;
+        self.total = total;
+        let now = Instant::now();
+        let (sample_bytes, sample_time) = self.sample;
+        let sample_elapsed = now.duration_since(sample_time);
+        if sample_elapsed >= BAR_REDRAW_INTERVAL {
+            self.current_rate =
+                received.saturating_sub(sample_bytes) as f64 / sample_elapsed.as_secs_f64();
+            self.sample = (received, now);
+        }
+        // Failing to show progress shouldn't fail the download, so write errors are ignored
+        let _ = match self.style {
+            ProgressStyle::Bar { .. } => {
+                if self
+                    .last_draw
+                    .is_none_or(|last| now.duration_since(last) >= BAR_REDRAW_INTERVAL)
+                {
+                    self.last_draw = Some(now);
+                    self.draw_bar(now)
+                } else {
+                    Ok(())
+                }
+            }
+            ProgressStyle::Dot(style) => self.draw_dots(style, now),
+        };
+    }
+
+    /// Draws the final state of the transfer, followed by the summary line
+    pub fn finish(mut self) {
+        let now = Instant::now();
+        let _ = match self.style {
+            ProgressStyle::Bar { .. } => self.draw_bar(now).and_then(|_| writeln!(self.out)),
+            ProgressStyle::Dot(style) => self.finish_dots(style, now),
+        };
+        let elapsed = now.duration_since(self.start);
+        let _ = writeln!(self.out, "{}", self.summary(elapsed));
+    }
+
+    /// Ends the progress display without a summary, for transfers that failed
+    pub fn abandon(mut self) {
+        let drawn = match self.style {
+            ProgressStyle::Bar { .. } => self.last_draw.is_some(),
+            ProgressStyle::Dot(style) => !self.dots.is_multiple_of(style.dots_per_line),
+        };
+        if drawn {
+            let _ = writeln!(self.out);
+        }
+    }
+
+    fn summary(&self, elapsed: Duration) -> String {
+        let rate = average_rate(self.received, elapsed);
+        match self.total {
+            Some(total) => format!(
+                "({}) - '{}' saved [{}/{}]",
+                format_rate(rate, self.rate_unit, " "),
+                self.name,
+                self.received,
+                total
+            ),
+            None => format!(
+                "({}) - '{}' saved [{}]",
+                format_rate(rate, self.rate_unit, " "),
+                self.name,
+                self.received
+            ),
+        }
+    }
+
+    fn draw_bar(&mut self, now: Instant) -> io::Result<()> {
+        let elapsed = now.duration_since(self.start);
+        let line = render_bar(
+            &self.name,
+            self.received,
+            self.total,
+            // Until a full sample has been taken, the average is the best guess
+            if self.current_rate > 0.0 {
+                self.current_rate
+            } else {
+                average_rate(self.received, elapsed)
+            },
+            elapsed,
+            self.rate_unit,
+        );
+        write!(self.out, "\r{line}")?;
+        self.out.flush()
+    }
+
+    fn draw_dots(&mut self, style: DotStyle, now: Instant) -> io::Result<()> {
+        let target = self.received / style.dot_bytes;
+        let mut text = String::new();
+        while self.dots < target {
+            push_dot(&mut text, self.dots, style);
+            self.dots += 1;
+            if self.dots.is_multiple_of(style.dots_per_line) {
+                text.push_str(&self.dot_line_end(style, now, false));
+            }
+        }
+        if !text.is_empty() {
+            write!(self.out, "{text}")?;
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+
+    fn finish_dots(&mut self, style: DotStyle, now: Instant) -> io::Result<()> {
+        self.draw_dots(style, now)?;
+        let in_line = self.dots % style.dots_per_line;
+        if in_line == 0 && self.dots > 0 && self.received.is_multiple_of(style.dot_bytes) {
+            // The last line was already completed by `draw_dots`
+            return Ok(());
+        }
+        let mut text = String::new();
+        if in_line == 0 {
+            text.push_str(&format!("{:>6}K", self.dots * style.dot_bytes / 1024));
+        }
+        // Pad out the rest of the line so the statistics line up
+        for dot in in_line..style.dots_per_line {
+            if dot % style.dots_per_cluster == 0 {
+                text.push(' ');
+            }
+            text.push(' ');
+        }
+        text.push_str(&self.dot_line_end(style, now, true));
+        write!(self.out, "{text}")
+    }
+
+    fn dot_line_end(&mut self, style: DotStyle, now: Instant, last: bool) -> String {
+        let elapsed = now.duration_since(self.start);
+        let line_received = if last {
+            self.received
+        } else {
+            self.dots * style.dot_bytes
+        };
+        let percent = match self.total {
+            Some(total) if total > 0 => format!(" {:>3}%", line_received * 100 / total),
+            _ => String::new(),
+        };
+        if last {
+            format!(
+                "{percent} {:>7}={}\n",
+                format_rate(average_rate(self.received, elapsed), self.rate_unit, ""),
+                format_duration(elapsed)
+            )
+        } else {
+            let eta = self
+                .total
+                .and_then(|total| eta(line_received, total, average_rate(line_received, elapsed)))
+                .map(|eta| format!(" {}", format_duration(eta)))
+                .unwrap_or_default();
+            let line_rate = average_rate(
+                style.dot_bytes * style.dots_per_line,
+                now.duration_since(self.line_started),
+            );
+            self.line_started = now;
+            format!(
+                "{percent} {:>7}{eta}\n",
+                format_rate(line_rate, self.rate_unit, "")
+            )
+        }
+    }
+}
+
+fn push_dot(text: &mut String, dot: u64, style: DotStyle) {
+    let in_line = dot % style.dots_per_line;
+    if in_line == 0 {
+        text.push_str(&format!("{:>6}K", dot * style.dot_bytes / 1024));
+    }
+    if in_line.is_multiple_of(style.dots_per_cluster) {
+        text.push(' ');
+    }
+    text.push('.');
+}
+
+/// Renders a line like `file.tar.gz  45%[=====>     ]  1.20M  345KB/s  eta 3s`
+fn render_bar(
+    name: &str,
+    received: u64,
+    total: Option<u64>,
+    current_rate: f64,
+    elapsed: Duration,
+    rate_unit: RateUnit,
+) -> String {
+    let name: String = name.chars().take(BAR_NAME_WIDTH).collect();
+    let rate = format_rate(current_rate, rate_unit, "");
+    match total {
+        Some(total) if total > 0 => {
+            let received = received.min(total);
+            let percent = received * 100 / total;
+            let filled = (received * BAR_WIDTH as u64 / total) as usize;
+            let bar = if filled >= BAR_WIDTH {
+                "=".repeat(BAR_WIDTH)
+            } else {
+                format!(
+                    "{}>{}",
+                    "=".repeat(filled),
+                    " ".repeat(BAR_WIDTH - filled - 1)
+                )
+            };
+            let eta = if received == total {
+                format!("in {}", format_duration(elapsed))
+            } else {
+                eta(received, total, average_rate(received, elapsed))
+                    .map(|eta| format!("eta {}", format_duration(eta)))
+                    .unwrap_or_default()
+            };
+            format!(
+                "{name:<BAR_NAME_WIDTH$} {percent:>3}%[{bar}] {:>7} {rate:>9}  {eta:<10}",
+                format_size(received)
+            )
+        }
+        _ => {
+            // Without a total all that can be shown is a marker bouncing back and forth
+            let span = (BAR_WIDTH - 3) as u64;
+            let step = (received / 4096) % (span * 2);
+            let position = if step > span { span * 2 - step } else { step } as usize;
+            let bar = format!(
+                "{}<=>{}",
+                " ".repeat(position),
+                " ".repeat(BAR_WIDTH - 3 - position)
+            );
+            format!(
+                "{name:<BAR_NAME_WIDTH$}     [{bar}] {:>7} {rate:>9}  {:<10}",
+                format_size(received),
+                format!("in {}", format_duration(elapsed))
+            )
+        }
+    }
+}
+
+pub fn average_rate(received: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        received as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+fn eta(received: u64, total: u64, rate: f64) -> Option<Duration> {
+    if rate <= 0.0 || received >= total {
+        return None;
+    }
+    Some(Duration::from_secs_f64((total - received) as f64 / rate))
+}
+
+/// Formats a byte count the way wget does, e.g. `512`, `34.5K` or `1.20M`
+pub fn format_size(bytes: u64) -> String {
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let (value, unit) = scale(bytes as f64);
+    format!("{}{unit}", precise(value))
+}
+
+/// Formats a rate of `rate` bytes per second in `rate_unit`, e.g. `345KB/s`,
+/// with `separator` between the number and the unit
+pub fn format_rate(rate: f64, rate_unit: RateUnit, separator: &str) -> String {
+    let (rate, base, suffix) = match rate_unit {
+        RateUnit::Bytes => (rate, 1024.0, "B/s"),
+        RateUnit::Bits => (rate * 8.0, 1000.0, "b/s"),
+    };
+    if rate < base {
+        return format!("{rate:.0}{separator}{suffix}");
+    }
+    let (value, unit) = scale_by(rate, base);
+    format!("{}{separator}{unit}{suffix}", precise(value))
+}
+
+fn scale(value: f64) -> (f64, &'static str) {
+    scale_by(value, 1024.0)
+}
+
+fn scale_by(mut value: f64, base: f64) -> (f64, &'static str) {
+    let mut unit = "";
+    for next_unit in ["K", "M", "G", "T"] {
+        if value < base {
+            break;
+        }
+        value /= base;
+        unit = next_unit;
+    }
+    (value, unit)
+}
+
+/// Keeps about three significant figures
+fn precise(value: f64) -> String {
+    if value < 10.0 {
+        format!("{value:.2}")
+    } else if value < 100.0 {
+        format!("{value:.1}")
+    } else {
+        format!("{value:.0}")
+    }
+}
+
+/// Formats a duration like `3s`, `1m 5s` or `2h 3m`
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    if secs == 0 {
+        format!("{:.1}s", duration.as_secs_f64())
+    } else if secs < 60 {
+        format!("{secs}s")
+    } else if secs < 60 * 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h {}m", secs / 3600, (secs / 60) % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_progress_styles() {
+        assert_eq!("bar".parse(), Ok(ProgressStyle::Bar { force: false }));
+        assert_eq!("bar:force".parse(), Ok(ProgressStyle::Bar { force: true }));
+        assert_eq!(
+            "bar:force:noscroll".parse(),
+            Ok(ProgressStyle::Bar { force: true })
+        );
+        assert_eq!("dot".parse(), Ok(ProgressStyle::Dot(DotStyle::DEFAULT)));
+        assert_eq!(
+            "dot:binary".parse(),
+            Ok(ProgressStyle::Dot(DotStyle::BINARY))
+        );
+        assert_eq!("dot:mega".parse(), Ok(ProgressStyle::Dot(DotStyle::MEGA)));
+        assert_eq!("dot:giga".parse(), Ok(ProgressStyle::Dot(DotStyle::GIGA)));
+        assert!("dot:huge".parse::<ProgressStyle>().is_err());
+        assert!("bar:sideways".parse::<ProgressStyle>().is_err());
+        assert!("spinner".parse::<ProgressStyle>().is_err());
+    }
+
+    #[test]
+    fn bar_degrades_to_dots_off_terminal() {
+        let bar = ProgressStyle::Bar { force: false };
+        assert_eq!(bar.for_terminal(true), bar);
+        assert_eq!(
+            bar.for_terminal(false),
+            ProgressStyle::Dot(DotStyle::DEFAULT)
+        );
+
+        let forced = ProgressStyle::Bar { force: true };
+        assert_eq!(forced.for_terminal(false), forced);
+
+        let dots = ProgressStyle::Dot(DotStyle::MEGA);
+        assert_eq!(dots.for_terminal(true), dots);
+    }
+
+    #[test]
+    fn formats_sizes_and_rates() {
+        assert_eq!(format_size(512), "512");
+        assert_eq!(format_size(1024), "1.00K");
+        assert_eq!(format_size(35 * 1024 + 512), "35.5K");
+        assert_eq!(format_size(1258291), "1.20M");
+        assert_eq!(format_rate(100.0, RateUnit::Bytes, ""), "100B/s");
+        assert_eq!(format_rate(345.0 * 1024.0, RateUnit::Bytes, ""), "345KB/s");
+        assert_eq!(
+            format_rate(1.5 * 1024.0 * 1024.0, RateUnit::Bytes, " "),
+            "1.50 MB/s"
+        );
+        assert_eq!(format_rate(100.0, RateUnit::Bits, ""), "800b/s");
+        assert_eq!(format_rate(354_000.0, RateUnit::Bits, ""), "2.83Mb/s");
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_millis(250)), "0.2s");
+        assert_eq!(format_duration(Duration::from_secs(3)), "3s");
+        assert_eq!(format_duration(Duration::from_secs(65)), "1m 5s");
+        assert_eq!(format_duration(Duration::from_secs(7380)), "2h 3m");
+    }
+
+    #[test]
+    fn estimates_time_remaining() {
+        assert_eq!(eta(50, 150, 10.0), Some(Duration::from_secs(10)));
+        assert_eq!(eta(150, 150, 10.0), None);
+        assert_eq!(eta(50, 150, 0.0), None);
+    }
+
+    #[test]
+    fn renders_bar_with_known_length() {
+        let line = render_bar(
+            "file.txt",
+            512,
+            Some(1024),
+            1024.0,
+            Duration::from_secs(1),
+            RateUnit::Bytes,
+        );
+        assert!(line.starts_with("file.txt"), "{line}");
+        assert!(
+            line.contains(" 50%[===============>              ]"),
+            "{line}"
+        );
+        assert!(line.contains("1.00KB/s"), "{line}");
+        assert!(line.contains("eta 1s"), "{line}");
+    }
+
+    #[test]
+    fn renders_complete_bar() {
+        let line = render_bar(
+            "file.txt",
+            10,
+            Some(10),
+            0.0,
+            Duration::from_secs(2),
+            RateUnit::Bytes,
+        );
+        assert!(
+            line.contains("100%[==============================]"),
+            "{line}"
+        );
+        assert!(line.contains("in 2s"), "{line}");
+    }
+
+    #[test]
+    fn renders_bar_with_unknown_length() {
+        let line = render_bar(
+            "file.txt",
+            0,
+            None,
+            0.0,
+            Duration::from_secs(2),
+            RateUnit::Bytes,
+        );
+        assert!(line.contains("[<=>"), "{line}");
+        assert!(!line.contains('%'), "{line}");
+    }
+
+    #[test]
+    fn prints_dots_in_clusters_and_lines() {
+        let style = DotStyle {
+            dot_bytes: 1,
+            dots_per_cluster: 2,
+            dots_per_line: 4,
+        };
+        let mut out = Vec::new();
+        {
+            let mut progress = Progress::with_writer(ProgressStyle::Dot(style), "file", &mut out);
+            progress.update(3, Some(6));
+            progress.update(6, Some(6));
+            progress.finish();
+        }
+        let out = String::from_utf8(out).expect("Output wasn't UTF-8");
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[0].starts_with("     0K .. .. "), "{out}");
+        assert!(lines[0].contains(" 66%"), "{out}");
+        assert!(lines[1].starts_with("     0K ..    100%"), "{out}");
+        assert!(lines[2].ends_with("- 'file' saved [6/6]"), "{out}");
+    }
+
+    #[test]
+    fn summary_without_total() {
+        let mut out = Vec::new();
+        {
+            let mut progress =
+                Progress::with_writer(ProgressStyle::Bar { force: true }, "file", &mut out);
+            progress.update(42, None);
+            progress.finish();
+        }
+        let out = String::from_utf8(out).expect("Output wasn't UTF-8");
+        assert!(out.trim_end().ends_with("- 'file' saved [42]"), "{out}");
+    }
+
+    #[test]
+    fn abandon_ends_the_line() {
+        let mut out = Vec::new();
+        {
+            let mut progress =
+                Progress::with_writer(ProgressStyle::Bar { force: true }, "file", &mut out);
+            progress.update(1, Some(2));
+            progress.abandon();
+        }
+        let out = String::from_utf8(out).expect("Output wasn't UTF-8");
+        assert!(out.ends_with('\n'), "{out}");
+        assert!(!out.contains("saved"), "{out}");
+    }
+}
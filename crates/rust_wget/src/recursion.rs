@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+
+use request_rs::url::normalize;
+
+use crate::pool;
+
+/// The most links handed to `work` together as one batch
+const MAX_BATCH: usize = 10;
+
+/// A URL waiting to be downloaded, and the page that linked to it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Link {
+    pub url: String,
+    /// The page the URL was found on, or `None` if it was given by the user
+    pub referrer: Option<String>,
+}
+
+/// Parses a maximum recursion depth as given to `-l/--level`, where `inf` and
+/// 0 both mean there is no limit, like in wget
+pub fn parse_depth(s: &str) -> Result<Option<usize>, String> {
+    if s.eq_ignore_ascii_case("inf") {
+        return Ok(None);
+    }
+    match s.parse() {
+        Ok(0) => Ok(None),
+        Ok(depth) => Ok(Some(depth)),
+        Err(_) => Err(format!("Invalid depth {s}")),
+    }
+}
+
+/// Runs `work` over each of `urls` using up to `workers` threads, then over
+/// the links it returns for those, and so on up to `max_depth` links away
+/// from `urls`, or without limit if it is `None`. No URL is visited twice,
+/// however it is written.
+///
+/// Links next to each other with the same `batch_key` are handed to `work`
+/// together, which returns a result for each of them. Links without a key
+/// are handed over alone.
+///
+/// Each level of links is finished before the next is started, so results are
+/// handed to `on_result` breadth first, in the order the links were found.
+pub fn crawl<S, R, K, W, L, F>(
+    urls: &[String],
+    max_depth: Option<usize>,
+    workers: usize,
+    batch_key: K,
+    work: W,
+    links: L,
+    mut on_result: F,
+) where
+    S: Default,
+    R: Send,
+    K: Fn(&Link) -> Option<String>,
+    W: Fn(&mut S, &[Link]) -> Vec<R> + Sync,
+    L: Fn(&R) -> &[String],
+    F: FnMut(&Link, R),
+{
+    let mut seen: HashSet<String> = HashSet::new();
+    let mut level: Vec<Link> = urls
+        .iter()
+        .filter(|url| seen.insert(normalize(url)))
+        .map(|url| Link {
+            url: url.clone(),
+            referrer: None,
+        })
+        .collect();
+    let mut depth = 0;
+    while !level.is_empty() {
+        let follow = max_depth.is_none_or(|max_depth| depth < max_depth);
+        let mut next = Vec::new();
+        let batches = batches(level, &batch_key);
+        let work = |state: &mut S, batch: &Vec<Link>| work(state, batch);
+        pool::run(&batches, workers, work, |batch, results| {
+            for (link, result) in batch.iter().zip(results) {
+                if follow {
+                    for url in links(&result) {
+                        if seen.insert(normalize(url)) {
+                            next.push(Link {
+                                url: url.clone(),
+                                referrer: Some(link.url.clone()),
+                            });
+                        }
+                    }
+                }
+                on_result(link, result);
+            }
+        });
+        level = next;
+        depth += 1;
+    }
+}
+
+/// Splits `level` into runs of links with the same batch key, of up to
+/// `MAX_BATCH` links each. Keeping the links in order keeps the results in
+/// order too.
+fn batches(level: Vec<Link>, batch_key: impl Fn(&Link) -> Option<String>) -> Vec<Vec<Link>> {
+    let mut batches: Vec<Vec<Link>> = Vec::new();
+    let mut last_key = None;
+    for link in level {
+        let key = batch_key(&link);
+        match batches.last_mut() {
+            Some(batch) if key.is_some() && key == last_key && batch.len() < MAX_BATCH => {
+                batch.push(link)
+            }
+            _ => batches.push(vec![link]),
+        }
+        last_key = key;
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// A small site where every page links back to the start
+    fn site() -> HashMap<&'static str, Vec<String>> {
+        HashMap::from([
+            ("/", vec!["/a".to_string(), "/b".to_string()]),
+            ("/a", vec!["/".to_string(), "/a/1".to_string()]),
+            ("/b", vec!["/".to_string(), "/a".to_string()]),
+            ("/a/1", vec!["/".to_string(), "/a/1/x".to_string()]),
+            ("/a/1/x", vec![]),
+        ])
+    }
+
+    fn visit(max_depth: Option<usize>) -> Vec<Link> {
+        let site = site();
+        let mut visited = Vec::new();
+        crawl(
+            &["/".to_string()],
+            max_depth,
+            3,
+            |_| None,
+            |_: &mut (), batch| {
+                batch
+                    .iter()
+                    .map(|link| site.get(link.url.as_str()).cloned().unwrap_or_default())
+                    .collect()
+            },
+            Vec::as_slice,
+            |link, _| visited.push(link.clone()),
+        );
+        visited
+    }
+
+    fn link(url: &str, referrer: &str) -> Link {
+        Link {
+            url: url.to_string(),
+            referrer: Some(referrer.to_string()),
+        }
+    }
+
+    #[test]
+    fn parses_depths() {
+        assert_eq!(parse_depth("3"), Ok(Some(3)));
+        assert_eq!(parse_depth("0"), Ok(None));
+        assert_eq!(parse_depth("INF"), Ok(None));
+        assert!(parse_depth("-1").is_err());
+    }
+
+    #[test]
+    fn visits_each_page_once_breadth_first() {
+        assert_eq!(
+            visit(None),
+            [
+                Link {
+                    url: "/".to_string(),
+                    referrer: None
+                },
+                link("/a", "/"),
+                link("/b", "/"),
+                link("/a/1", "/a"),
+                link("/a/1/x", "/a/1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_max_depth() {
+        let urls = |max_depth| {
+            visit(Some(max_depth))
+                .into_iter()
+                .map(|link| link.url)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(urls(0), ["/"]);
+        assert_eq!(urls(1), ["/", "/a", "/b"]);
+        assert_eq!(urls(2), ["/", "/a", "/b", "/a/1"]);
+    }
+
+    #[test]
+    fn batches_neighbouring_links_with_the_same_key() {
+        let links: Vec<Link> = ["a/1", "a/2", "b/1", "a/3", "c", "d"]
+            .into_iter()
+            .chain(["e/x"; 12])
+            .map(|url| Link {
+                url: url.to_string(),
+                referrer: None,
+            })
+            .collect();
+        let key = |link: &Link| {
+            let (host, _) = link.url.split_once('/')?;
+            Some(host.to_string())
+        };
+        let sizes: Vec<usize> = batches(links, key).iter().map(Vec::len).collect();
+        assert_eq!(sizes, [2, 1, 1, 1, 1, MAX_BATCH, 2]);
+    }
+
+    #[test]
+    fn visits_differently_written_urls_once() {
+        let mut visited = Vec::new();
+        crawl(
+            &["http://example.com/".to_string()],
+            None,
+            1,
+            |_| None,
+            |_: &mut (), batch| {
+                batch
+                    .iter()
+                    .map(|_| {
+                        vec![
+                            "HTTP://Example.com:80/#top".to_string(),
+                            "http://example.com/a#part".to_string(),
+                            "http://EXAMPLE.com/a".to_string(),
+                        ]
+                    })
+                    .collect()
+            },
+            Vec::as_slice,
+            |link, _| visited.push(link.url.clone()),
+        );
+        assert_eq!(
+            visited,
+            ["http://example.com/", "http://example.com/a#part"]
+        );
+    }
+}
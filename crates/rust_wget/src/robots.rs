@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// What a site's `robots.txt` lets us fetch when following links
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Robots {
+    /// Path prefixes, each with whether it is allowed
+    rules: Vec<(String, bool)>,
+}
+
+impl Robots {
+    /// Reads the rules `text` sets for wget, or for every robot if it doesn't
+    /// name wget. Only plain path prefixes are understood, not the `*` and `$`
+    /// patterns some robots support.
+    pub fn parse(text: &str) -> Self {
+        let mut ours = None;
+        let mut everyone = None;
+        // The user agents of the group being read, and whether its rules have
+        // started, after which another user agent starts a new group
+        let mut agents: Vec<String> = Vec::new();
+        let mut in_rules = false;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            let allowed = match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    if in_rules {
+                        agents.clear();
+                        in_rules = false;
+                    }
+                    agents.push(value.to_ascii_lowercase());
+                    continue;
+                }
+                "allow" => true,
+                "disallow" => false,
+                _ => continue,
+            };
+            in_rules = true;
+            for agent in &agents {
+                let rules = if agent.starts_with("wget") {
+                    &mut ours
+                } else if agent == "*" {
+                    &mut everyone
+                } else {
+                    continue;
+                };
+                // Even a group that allows everything, with an empty
+                // `Disallow`, stops the rules for everyone applying to wget
+                let rules = rules.get_or_insert_with(Vec::new);
+                if !value.is_empty() {
+                    rules.push((value.to_string(), allowed));
+                }
+            }
+        }
+        Robots {
+            rules: ours.or(everyone).unwrap_or_default(),
+        }
+    }
+
+    /// Whether `path` may be fetched. The longest rule matching it decides,
+    /// and `Allow` wins a tie.
+    pub fn allows(&self, path: &str) -> bool {
+        self.rules
+            .iter()
+            .filter(|(prefix, _)| path.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, allowed)| (prefix.len(), *allowed))
+            .is_none_or(|(_, allowed)| *allowed)
+    }
+}
+
+/// The `robots.txt` of every server seen so far, shared between the threads
+/// downloading in parallel
+#[derive(Debug, Default)]
+pub struct RobotsCache {
+    servers: Mutex<HashMap<String, Arc<Robots>>>,
+}
+
+impl RobotsCache {
+    pub fn get(&self, server: &str) -> Option<Arc<Robots>> {
+        self.servers
+            .lock()
+            .expect("Robots cache poisoned")
+            .get(server)
+            .cloned()
+    }
+
+    /// Stores the rules for `server`, unless another thread got there first,
+    /// and returns whichever are kept
+    pub fn insert(&self, server: String, robots: Robots) -> Arc<Robots> {
+        self.servers
+            .lock()
+            .expect("Robots cache poisoned")
+            .entry(server)
+            .or_insert_with(|| Arc::new(robots))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_rules_for_everyone() {
+        let robots = Robots::parse(
+            "# Keep out of the private bits\n\
+             User-agent: *\n\
+             Disallow: /private/\n\
+             Allow: /private/open/\n\
+             Disallow:\n\
+             \n\
+             User-agent: SomeBot\n\
+             Disallow: /\n",
+        );
+        assert!(robots.allows("/"));
+        assert!(robots.allows("/private"));
+        assert!(!robots.allows("/private/file"));
+        assert!(robots.allows("/private/open/file"));
+    }
+
+    #[test]
+    fn prefers_rules_for_wget() {
+        let robots = Robots::parse(
+            "User-agent: *\n\
+             Disallow: /\n\
+             \n\
+             User-agent: Googlebot\n\
+             user-agent: Wget/1.21\n\
+             disallow: /cgi-bin # Too slow\n",
+        );
+        assert!(robots.allows("/index.html"));
+        assert!(!robots.allows("/cgi-bin/search?q=x"));
+
+        let robots = Robots::parse("User-agent: *\nDisallow: /\n\nUser-agent: wget\nDisallow:\n");
+        assert!(robots.allows("/index.html"));
+    }
+
+    #[test]
+    fn allows_everything_without_rules() {
+        for text in [
+            "",
+            "Sitemap: http://example.com/map.xml",
+            "User-agent: *\nDisallow:",
+        ] {
+            assert_eq!(Robots::parse(text), Robots::default());
+        }
+        assert!(Robots::default().allows("/anything"));
+    }
+
+    #[test]
+    fn caches_the_first_rules_stored() {
+        let cache = RobotsCache::default();
+        assert!(cache.get("example.com:80").is_none());
+        let first = cache.insert(
+            "example.com:80".to_string(),
+            Robots::parse("User-agent: *\nDisallow: /a"),
+        );
+        let second = cache.insert("example.com:80".to_string(), Robots::default());
+        assert!(Arc::ptr_eq(&first, &second));
+        assert!(!cache
+            .get("example.com:80")
+            .expect("Rules not cached")
+            .allows("/a"));
+    }
+}
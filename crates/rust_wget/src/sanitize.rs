@@ -0,0 +1,270 @@
+use std::{fmt::Write, str::FromStr};
+
+/// The longest a single file name may be in bytes, leaving room below the
+/// usual limit of 255 for the `.1`, `.2` and so on of numbered copies.
+/// Temporary files cut the name short themselves.
+pub const MAX_NAME_LENGTH: usize = 240;
+
+/// Which file system's rules file names have to follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Unix,
+    Windows,
+}
+
+/// The platform being run on, like wget
+impl Default for Platform {
+    fn default() -> Self {
+        if cfg!(windows) {
+            Platform::Windows
+        } else {
+            Platform::Unix
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Case {
+    Lower,
+    Upper,
+}
+
+/// Which characters may appear in local file names, from wget's
+/// `--restrict-file-names`. Anything else is escaped as `%XX`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileNameRestrictions {
+    pub platform: Platform,
+    /// Leave control characters alone, from `nocontrol`
+    pub allow_control: bool,
+    /// Escape everything outside of ASCII, from `ascii`
+    pub ascii_only: bool,
+    pub case: Option<Case>,
+}
+
+/// Parses a comma separated list of modes, e.g. `windows,lowercase`
+impl FromStr for FileNameRestrictions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut restrictions = FileNameRestrictions::default();
+        for mode in s.split(',') {
+            match mode.trim().to_ascii_lowercase().as_str() {
+                "unix" => restrictions.platform = Platform::Unix,
+                "windows" => restrictions.platform = Platform::Windows,
+                "nocontrol" => restrictions.allow_control = true,
+                "ascii" => restrictions.ascii_only = true,
+                "lowercase" => restrictions.case = Some(Case::Lower),
+                "uppercase" => restrictions.case = Some(Case::Upper),
+                _ => return Err(format!("Unknown file name restriction {mode}")),
+            }
+        }
+        Ok(restrictions)
+    }
+}
+
+impl FileNameRestrictions {
+    /// The separator between a file name and its URL's query string, since
+    /// `?` isn't allowed in file names on Windows
+    pub fn query_separator(&self) -> char {
+        match self.platform {
+            Platform::Unix => '?',
+            Platform::Windows => '@',
+        }
+    }
+
+    /// Makes `segment` of a URL safe to use as a single file or directory
+    /// name: it never contains a path separator, is never `.` or `..`, is
+    /// never empty and is no longer than `MAX_NAME_LENGTH` bytes. On Windows
+    /// it never names a device like `CON` or `NUL` either.
+    pub fn sanitize(&self, segment: &str) -> String {
+        let segment = match self.case {
+            Some(Case::Lower) => segment.to_lowercase(),
+            Some(Case::Upper) => segment.to_uppercase(),
+            None => segment.to_string(),
+        };
+        let mut name = String::with_capacity(segment.len());
+        for c in segment.chars() {
+            if self.is_allowed(c) {
+                name.push(c);
+            } else {
+                let mut bytes = [0; 4];
+                for byte in c.encode_utf8(&mut bytes).bytes() {
+                    write!(name, "%{byte:02X}").expect("Writing to a String can't fail");
+                }
+            }
+        }
+        if self.platform == Platform::Windows && is_device_name(&name) {
+            // `NUL.txt` is the device too, so the suffix goes before any
+            // extension
+            let end = name.find('.').unwrap_or(name.len());
+            name.insert(end, '_');
+        }
+        match name.as_str() {
+            "" => "%00".to_string(),
+            "." => "%2E".to_string(),
+            ".." => "%2E%2E".to_string(),
+            _ => truncate(name),
+        }
+    }
+
+    fn is_allowed(&self, c: char) -> bool {
+        if c == '/' {
+            return false;
+        }
+        if c.is_control() && !self.allow_control {
+            return false;
+        }
+        if !c.is_ascii() && self.ascii_only {
+            return false;
+        }
+        match self.platform {
+            Platform::Unix => true,
+            Platform::Windows => !matches!(c, '\\' | '|' | ':' | '?' | '"' | '*' | '<' | '>'),
+        }
+    }
+}
+
+/// Whether Windows takes `name` to be a device rather than a file, whatever
+/// its case and extension
+fn is_device_name(name: &str) -> bool {
+    let stem = name
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .trim_end_matches(' ');
+    let stem = stem.to_ascii_uppercase();
+    match stem.as_bytes() {
+        b"CON" | b"PRN" | b"AUX" | b"NUL" => true,
+        [b'C', b'O', b'M', digit] | [b'L', b'P', b'T', digit] => (b'1'..=b'9').contains(digit),
+        _ => false,
+    }
+}
+
+/// Shortens names that are too long for the file system, keeping them unique
+/// by replacing the end with a hash of the whole name. A short extension is
+/// kept, so the file still opens with the right program.
+fn truncate(name: String) -> String {
+    if name.len() <= MAX_NAME_LENGTH {
+        return name;
+    }
+    let extension = match name.rfind('.') {
+        Some(dot) if name.len() - dot <= 10 => &name[dot..],
+        _ => "",
+    };
+    let hash = format!("-{:08x}", fnv1a(name.as_bytes()));
+    let mut end = MAX_NAME_LENGTH - hash.len() - extension.len();
+    while !name.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{hash}{extension}", &name[..end])
+}
+
+/// A small hash that stays the same between runs and versions, so a
+/// truncated name is the same every time the URL is downloaded
+fn fnv1a(bytes: &[u8]) -> u32 {
+    bytes.iter().fold(0x811c9dc5, |hash, byte| {
+        (hash ^ *byte as u32).wrapping_mul(0x01000193)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn restrictions(modes: &str) -> FileNameRestrictions {
+        modes.parse().expect("Could not parse restrictions")
+    }
+
+    #[test]
+    fn parses_modes() {
+        assert_eq!(
+            restrictions("unix"),
+            FileNameRestrictions {
+                platform: Platform::Unix,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            restrictions("windows,nocontrol,ascii,uppercase"),
+            FileNameRestrictions {
+                platform: Platform::Windows,
+                allow_control: true,
+                ascii_only: true,
+                case: Some(Case::Upper),
+            }
+        );
+        assert!("unix,beos".parse::<FileNameRestrictions>().is_err());
+    }
+
+    #[test]
+    fn escapes_unsafe_characters() {
+        let unix = restrictions("unix");
+        assert_eq!(unix.sanitize("a:b?c\\d"), "a:b?c\\d");
+        assert_eq!(unix.sanitize("a/b"), "a%2Fb");
+        assert_eq!(unix.sanitize("a\nb\u{7f}"), "a%0Ab%7F");
+        assert_eq!(
+            restrictions("windows").sanitize("a:b?c\\d"),
+            "a%3Ab%3Fc%5Cd"
+        );
+        assert_eq!(restrictions("nocontrol").sanitize("a\nb"), "a\nb");
+        // Names come decoded, so `%` is just another character
+        assert_eq!(unix.sanitize("a%2Fb"), "a%2Fb");
+        assert_eq!(restrictions("windows").sanitize("100%"), "100%");
+    }
+
+    #[test]
+    fn renames_windows_devices() {
+        let windows = restrictions("windows");
+        assert_eq!(windows.sanitize("CON"), "CON_");
+        assert_eq!(windows.sanitize("nul.txt"), "nul_.txt");
+        assert_eq!(windows.sanitize("Com1.tar.gz"), "Com1_.tar.gz");
+        assert_eq!(windows.sanitize("lpt9"), "lpt9_");
+        assert_eq!(windows.sanitize("aux .html"), "aux _.html");
+        for name in ["CONSOLE", "COM0", "COM10", "xnul", "LPT"] {
+            assert_eq!(windows.sanitize(name), name);
+        }
+        assert_eq!(restrictions("unix").sanitize("CON"), "CON");
+    }
+
+    #[test]
+    fn escapes_non_ascii_characters() {
+        assert_eq!(restrictions("unix").sanitize("café"), "café");
+        assert_eq!(restrictions("ascii").sanitize("café"), "caf%C3%A9");
+    }
+
+    #[test]
+    fn changes_case() {
+        assert_eq!(
+            restrictions("lowercase").sanitize("Index.HTML"),
+            "index.html"
+        );
+        assert_eq!(
+            restrictions("uppercase").sanitize("Index.html"),
+            "INDEX.HTML"
+        );
+    }
+
+    #[test]
+    fn never_produces_special_names() {
+        let unix = restrictions("unix");
+        assert_eq!(unix.sanitize(""), "%00");
+        assert_eq!(unix.sanitize("."), "%2E");
+        assert_eq!(unix.sanitize(".."), "%2E%2E");
+    }
+
+    #[test]
+    fn truncates_long_names() {
+        let unix = restrictions("unix");
+        let long = format!("{}.html", "a".repeat(300));
+        let name = unix.sanitize(&long);
+        assert_eq!(name.len(), MAX_NAME_LENGTH);
+        assert!(name.ends_with(".html"));
+        assert_eq!(unix.sanitize(&long), name);
+        let other = unix.sanitize(&format!("{}b.html", "a".repeat(299)));
+        assert_ne!(other, name);
+
+        // Never split a character
+        let name = unix.sanitize(&"é".repeat(200));
+        assert!(name.len() <= MAX_NAME_LENGTH);
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context};
+
+use request_rs::{
+    connection::Connection,
+    connection_cache::ConnectionCache,
+    debug,
+    error::{WgetError, WgetResult},
+    http::{
+        ByteRange, Conditions, ContentRange, HttpMethod, HttpResponse, HttpStatusCode,
+        HttpStatusFamily,
+    },
+    url::ParsedUrl,
+    Configuration,
+};
+
+use crate::pool;
+
+/// How many times a single segment is attempted before the download is abandoned
+const SEGMENT_TRIES: usize = 3;
+
+/// Settings for downloading a single file as several byte ranges at once
+#[derive(Debug, Clone, Copy)]
+pub struct Segmentation {
+    /// How many connections to download segments over at the same time
+    pub segments: usize,
+    /// The size of each range requested, if not set the file is split evenly
+    /// between the connections
+    pub chunk_size: Option<u64>,
+}
+
+/// What a `HEAD` request revealed about a URL
+#[derive(Debug)]
+pub enum Probe {
+    Redirect(String),
+    /// The request was conditional and the conditions still hold
+    NotModified,
+    /// The file can be downloaded in ranges. The response to the `HEAD`
+    /// request is kept for what it says about the file.
+    Ranges {
+        length: u64,
+        head: HttpResponse,
+    },
+    Unsupported,
+}
+
+/// Sends a `HEAD` request to find out whether `url` can be downloaded in
+/// segments, or doesn't need downloading at all because of `conditions`
+pub fn probe(
+    url: &ParsedUrl,
+    conditions: &Conditions,
+    connection_cache: &mut ConnectionCache,
+    config: &Configuration,
+) -> WgetResult<Probe> {
+    let conn = connection_cache.get_connection(url, config)?;
+    let mut request = conn.new_request(HttpMethod::Head, &url.path);
+    request.set_conditions(conditions);
+    let response = conn.send(&request, config)?;
+    if response.status_code == HttpStatusCode::NotModified {
+        return Ok(Probe::NotModified);
+    }
+    match response.status_family() {
+        HttpStatusFamily::Redirection => {
+            if let Some(location) = response.get_header("Location") {
+                return Ok(Probe::Redirect(location.to_string()));
+            }
+        }
+        HttpStatusFamily::Successful => {
+            let accepts_bytes = response.get_header("Accept-Ranges").is_some_and(|units| {
+                units
+                    .split(',')
+                    .any(|unit| unit.trim().eq_ignore_ascii_case("bytes"))
+            });
+            if let (true, Some(length @ 1..)) = (accepts_bytes, response.content_length()) {
+                return Ok(Probe::Ranges {
+                    length,
+                    head: response,
+                });
+            }
+        }
+        _ => {}
+    }
+    Ok(Probe::Unsupported)
+}
+
+/// Downloads the `length` bytes of `url` to `path`, fetching ranges concurrently
+pub fn download(
+    url: &ParsedUrl,
+    length: u64,
+    segmentation: &Segmentation,
+    path: &Path,
+    config: &Configuration,
+) -> anyhow::Result<()> {
+    File::create(path)
+        .and_then(|f| f.set_len(length))
+        .context("Could not create output file")?;
+    let ranges = split_ranges(length, segmentation);
+    debug!(
+        config,
+        "Downloading {length} bytes in {} segments over {} connections",
+        ranges.len(),
+        segmentation.segments
+    );
+
+    let mut result = Ok(());
+    pool::run(
+        &ranges,
+        segmentation.segments,
+        |connection: &mut Option<Connection>, range| {
+            fetch_segment(url, *range, path, connection, config)
+        },
+        |range, segment_result| {
+            if let (Ok(()), Err(e)) = (&result, segment_result) {
+                result = Err(e.context(format!("Could not download bytes {range}")));
+            }
+        },
+    );
+    result
+}
+
+fn split_ranges(length: u64, segmentation: &Segmentation) -> Vec<ByteRange> {
+    let chunk_size = segmentation
+        .chunk_size
+        .unwrap_or_else(|| length.div_ceil(segmentation.segments as u64))
+        .max(1);
+    let mut ranges = Vec::new();
+    let mut first = 0;
+    while first < length {
+        let last = first.saturating_add(chunk_size).min(length) - 1;
+        ranges.push(ByteRange::new(first, last));
+        first = last + 1;
+    }
+    ranges
+}
+
+fn fetch_segment(
+    url: &ParsedUrl,
+    range: ByteRange,
+    path: &Path,
+    connection: &mut Option<Connection>,
+    config: &Configuration,
+) -> anyhow::Result<()> {
+    let mut last_error = anyhow!("Segment was never attempted");
+    for attempt in 1..=SEGMENT_TRIES {
+        match try_fetch_segment(url, range, path, connection, config) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                debug!(config, "Attempt {attempt} at bytes {range} failed: {e:?}");
+                // Whatever went wrong, the connection can't be trusted anymore
+                *connection = None;
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+fn try_fetch_segment(
+    url: &ParsedUrl,
+    range: ByteRange,
+    path: &Path,
+    connection: &mut Option<Connection>,
+    config: &Configuration,
+) -> anyhow::Result<()> {
+    let conn = match connection {
+        Some(conn) => conn,
+        None => connection.insert(Connection::new(url.domain_name.clone(), url.port, config)?),
+    };
+    let mut request = conn.new_request(HttpMethod::Get, &url.path);
+    request.set_range(range);
+    let response = conn.send(&request, config)?;
+    if !conn.is_reusable() {
+        *connection = None;
+    }
+
+    if response.status_code != HttpStatusCode::PartialContent {
+        return Err(WgetError::ProtocolError(format!(
+            "Expected {}, got {} {}",
+            HttpStatusCode::PartialContent,
+            response.status_code,
+            response.status_message
+        ))
+        .into());
+    }
+    let content_range = response
+        .get_header("Content-Range")
+        .ok_or_else(|| {
+            WgetError::ProtocolError("No Content-Range in partial response".to_string())
+        })?
+        .parse::<ContentRange>()?;
+    if content_range.range != Some(range) || response.get_data().len() as u64 != range.len() {
+        return Err(WgetError::ProtocolError(format!(
+            "Server sent {content_range} instead of bytes {range}"
+        ))
+        .into());
+    }
+
+    let mut file = OpenOptions::new().write(true).open(path)?;
+    file.seek(SeekFrom::Start(range.first))?;
+    file.write_all(response.get_data())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashSet,
+        fs,
+        io::{BufRead, BufReader},
+        net::{TcpListener, TcpStream},
+        process,
+        sync::{Arc, Mutex},
+        thread,
+    };
+
+    use request_rs::protocol::Protocol;
+
+    use super::*;
+
+    const BODY: &[u8] = b"The quick brown fox jumps over the lazy dog";
+
+    /// Serves `BODY`, honouring `Range` headers if `ranges` is set. If
+    /// `fail_first` is set, the first request for each range is answered with a
+    /// 500 to exercise retries.
+    fn serve(ranges: bool, fail_first: bool) -> u16 {
+        let listener = TcpListener::bind("localhost:0").expect("Could not create listener");
+        let port = listener.local_addr().expect("No local addr").port();
+        let failed = Arc::new(Mutex::new(HashSet::new()));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.expect("Error in incoming");
+                let failed = failed.clone();
+                thread::spawn(move || handle(stream, ranges, fail_first, &failed));
+            }
+        });
+        port
+    }
+
+    fn handle(mut stream: TcpStream, ranges: bool, fail_first: bool, failed: &Mutex<HashSet<u64>>) {
+        let mut reader = BufReader::new(stream.try_clone().expect("Could not clone"));
+        loop {
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                return;
+            }
+            let mut range = None;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).expect("Could not read header");
+                if line.trim().is_empty() {
+                    break;
+                }
+                if let Some(value) = line.trim().strip_prefix("Range: bytes=") {
+                    range = Some(value.parse::<ByteRange>().expect("Bad range"));
+                }
+            }
+            let accept_ranges = if ranges {
+                "Accept-Ranges: bytes\r\n"
+            } else {
+                ""
+            };
+            let response = match range.filter(|_| ranges) {
+                _ if request_line.starts_with("HEAD") => format!(
+                    "HTTP/1.1 200 OK\r\n{accept_ranges}Content-Length: {}\r\n\r\n",
+                    BODY.len()
+                )
+                .into_bytes(),
+                Some(range)
+                    if fail_first && failed.lock().expect("Poisoned lock").insert(range.first) =>
+                {
+                    b"HTTP/1.1 500 Oops\r\nContent-Length: 0\r\n\r\n".to_vec()
+                }
+                Some(range) => {
+                    let mut response = format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {range}/{}\r\nContent-Length: {}\r\n\r\n",
+                        BODY.len(),
+                        range.len()
+                    )
+                    .into_bytes();
+                    response.extend(&BODY[range.first as usize..=range.last as usize]);
+                    response
+                }
+                None => {
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\n{accept_ranges}Content-Length: {}\r\n\r\n",
+                        BODY.len()
+                    )
+                    .into_bytes();
+                    response.extend(BODY);
+                    response
+                }
+            };
+            stream
+                .write_all(&response)
+                .expect("Could not write response");
+        }
+    }
+
+    fn url(port: u16) -> ParsedUrl {
+        ParsedUrl {
+            protocol: Protocol::Http,
+            domain_name: "localhost".to_string(),
+            port,
+            path: "/file".to_string(),
+            filename: "file".to_string(),
+        }
+    }
+
+    fn output_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("rust_wget_segmented_{}_{name}", process::id()))
+    }
+
+    #[test]
+    fn splits_evenly_between_segments() {
+        let segmentation = Segmentation {
+            segments: 4,
+            chunk_size: None,
+        };
+        assert_eq!(
+            split_ranges(10, &segmentation),
+            vec![
+                ByteRange::new(0, 2),
+                ByteRange::new(3, 5),
+                ByteRange::new(6, 8),
+                ByteRange::new(9, 9)
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_by_chunk_size() {
+        let segmentation = Segmentation {
+            segments: 2,
+            chunk_size: Some(4),
+        };
+        assert_eq!(
+            split_ranges(10, &segmentation),
+            vec![
+                ByteRange::new(0, 3),
+                ByteRange::new(4, 7),
+                ByteRange::new(8, 9)
+            ]
+        );
+    }
+
+    #[test]
+    fn never_splits_into_empty_ranges() {
+        let segmentation = Segmentation {
+            segments: 8,
+            chunk_size: None,
+        };
+        assert_eq!(
+            split_ranges(2, &segmentation),
+            vec![ByteRange::new(0, 0), ByteRange::new(1, 1)]
+        );
+    }
+
+    #[test]
+    fn probes_for_range_support() {
+        let config = Configuration::default();
+        let mut cache = ConnectionCache::default();
+        let port = serve(true, false);
+        let probed =
+            probe(&url(port), &Conditions::default(), &mut cache, &config).expect("Probe failed");
+        assert!(
+            matches!(probed, Probe::Ranges { length, .. } if length == BODY.len() as u64),
+            "{probed:?}"
+        );
+
+        let port = serve(false, false);
+        let probed =
+            probe(&url(port), &Conditions::default(), &mut cache, &config).expect("Probe failed");
+        assert!(matches!(probed, Probe::Unsupported), "{probed:?}");
+    }
+
+    #[test]
+    fn downloads_segments_to_the_right_offsets() {
+        let config = Configuration::default();
+        let port = serve(true, false);
+        let path = output_path("offsets");
+        let segmentation = Segmentation {
+            segments: 3,
+            chunk_size: Some(5),
+        };
+        download(&url(port), BODY.len() as u64, &segmentation, &path, &config)
+            .expect("Download failed");
+        assert_eq!(fs::read(&path).expect("Could not read output"), BODY);
+        fs::remove_file(path).expect("Could not remove output");
+    }
+
+    #[test]
+    fn retries_failed_segments() {
+        let config = Configuration::default();
+        let port = serve(true, true);
+        let path = output_path("retries");
+        let segmentation = Segmentation {
+            segments: 2,
+            chunk_size: Some(4),
+        };
+        download(&url(port), BODY.len() as u64, &segmentation, &path, &config)
+            .expect("Download failed");
+        assert_eq!(fs::read(&path).expect("Could not read output"), BODY);
+        fs::remove_file(path).expect("Could not remove output");
+    }
+}
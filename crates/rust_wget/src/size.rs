@@ -0,0 +1,55 @@
+/// Parses a byte count the way wget does, e.g. `4096`, `200k`, `1.5m` or `2g`
+///
+/// Suffixes are powers of 1024 and are case-insensitive.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((index, suffix)) if suffix.is_ascii_alphabetic() => {
+            let multiplier: u64 = match suffix.to_ascii_lowercase() {
+                'k' => 1 << 10,
+                'm' => 1 << 20,
+                'g' => 1 << 30,
+                't' => 1 << 40,
+                _ => return Err(format!("Unknown size suffix in {s}")),
+            };
+            (&s[..index], multiplier)
+        }
+        _ => (s, 1),
+    };
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid size {s}"))?;
+    if !number.is_finite() || number < 0.0 {
+        return Err(format!("Invalid size {s}"));
+    }
+    Ok((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_numbers() {
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("4096"), Ok(4096));
+    }
+
+    #[test]
+    fn parses_suffixes() {
+        assert_eq!(parse_size("200k"), Ok(200 * 1024));
+        assert_eq!(parse_size("200K"), Ok(200 * 1024));
+        assert_eq!(parse_size("1.5m"), Ok(1024 * 1024 * 3 / 2));
+        assert_eq!(parse_size("2g"), Ok(2 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1t"), Ok(1024 * 1024 * 1024 * 1024));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("k").is_err());
+        assert!(parse_size("12x").is_err());
+        assert!(parse_size("-5").is_err());
+        assert!(parse_size("inf").is_err());
+    }
+}
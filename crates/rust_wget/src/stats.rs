@@ -0,0 +1,142 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Instant,
+};
+
+use crate::{
+    progress::{self, RateUnit},
+    wait::WaitPolicy,
+};
+
+/// Statistics for the whole run, shared between all of the download workers
+#[derive(Debug)]
+pub struct SessionStats {
+    started: Instant,
+    bytes_downloaded: AtomicU64,
+    files_downloaded: AtomicUsize,
+    quota_reported: AtomicBool,
+    /// The earliest the next request may be sent, to space requests out
+    next_request: Mutex<Option<Instant>>,
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        SessionStats {
+            started: Instant::now(),
+            bytes_downloaded: AtomicU64::new(0),
+            files_downloaded: AtomicUsize::new(0),
+            quota_reported: AtomicBool::new(false),
+            next_request: Mutex::new(None),
+        }
+    }
+}
+
+impl SessionStats {
+    /// Records that a file of `bytes` bytes was downloaded
+    pub fn record_download(&self, bytes: u64) {
+        self.bytes_downloaded.fetch_add(bytes, Ordering::Relaxed);
+        self.files_downloaded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn bytes_downloaded(&self) -> u64 {
+        self.bytes_downloaded.load(Ordering::Relaxed)
+    }
+
+    pub fn files_downloaded(&self) -> usize {
+        self.files_downloaded.load(Ordering::Relaxed)
+    }
+
+    /// Whether more than `quota` bytes have been downloaded. A quota of 0 means no limit.
+    pub fn quota_exceeded(&self, quota: u64) -> bool {
+        quota > 0 && self.bytes_downloaded() > quota
+    }
+
+    /// Returns true only the first time it is called, so exceeding the quota
+    /// is only reported once however many downloads are skipped
+    pub fn should_report_quota(&self) -> bool {
+        !self.quota_reported.swap(true, Ordering::Relaxed)
+    }
+
+    /// Blocks until `wait` says the next request may be sent. The first
+    /// request of the session never waits.
+    ///
+    /// Requests from every worker are spaced out together, so running in
+    /// parallel doesn't make the requests any less polite.
+    pub fn wait_for_turn(&self, wait: &WaitPolicy) {
+        let now = Instant::now();
+        let start = {
+            let mut next_request = self.next_request.lock().expect("Stats lock poisoned");
+            let start = next_request.map_or(now, |next| next.max(now));
+            *next_request = Some(start + wait.between_requests());
+            start
+        };
+        thread::sleep(start - now);
+    }
+
+    /// A line like wget's `Downloaded: 2 files, 1.20M in 3.0s (409 KB/s)`
+    pub fn summary(&self, rate_unit: RateUnit) -> String {
+        let elapsed = self.started.elapsed();
+        let bytes = self.bytes_downloaded();
+        format!(
+            "Downloaded: {} files, {} in {} ({})",
+            self.files_downloaded(),
+            progress::format_size(bytes),
+            progress::format_duration(elapsed),
+            progress::format_rate(progress::average_rate(bytes, elapsed), rate_unit, " ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn records_downloads() {
+        let stats = SessionStats::default();
+        stats.record_download(100);
+        stats.record_download(50);
+        assert_eq!(stats.bytes_downloaded(), 150);
+        assert_eq!(stats.files_downloaded(), 2);
+        assert!(stats
+            .summary(RateUnit::Bytes)
+            .starts_with("Downloaded: 2 files, 150 in "));
+    }
+
+    #[test]
+    fn checks_quota() {
+        let stats = SessionStats::default();
+        stats.record_download(100);
+        assert!(!stats.quota_exceeded(0));
+        assert!(!stats.quota_exceeded(100));
+        assert!(stats.quota_exceeded(99));
+    }
+
+    #[test]
+    fn reports_quota_once() {
+        let stats = SessionStats::default();
+        assert!(stats.should_report_quota());
+        assert!(!stats.should_report_quota());
+    }
+
+    #[test]
+    fn spaces_out_requests() {
+        let stats = SessionStats::default();
+        let wait = WaitPolicy {
+            wait: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        stats.wait_for_turn(&wait);
+        assert!(start.elapsed() < Duration::from_millis(50));
+        stats.wait_for_turn(&wait);
+        stats.wait_for_turn(&wait);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
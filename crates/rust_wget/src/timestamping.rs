@@ -0,0 +1,132 @@
+use std::{fmt, fs, io, path::Path};
+
+use request_rs::http::{HttpDate, HttpResponse};
+
+/// The modification time and size of a file that has already been downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalFile {
+    pub modified: HttpDate,
+    pub len: u64,
+}
+
+impl LocalFile {
+    /// Looks up `path`, returning `None` if there is no such file yet
+    pub fn find(path: &Path) -> io::Result<Option<Self>> {
+        match fs::metadata(path) {
+            Ok(metadata) => Ok(Some(LocalFile {
+                modified: metadata.modified()?.into(),
+                len: metadata.len(),
+            })),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Compares this file with the server's copy described by `response`
+    pub fn compare(&self, response: &HttpResponse) -> Comparison {
+        self.compare_with(response.last_modified(), response.content_length())
+    }
+
+    fn compare_with(&self, last_modified: Option<HttpDate>, length: Option<u64>) -> Comparison {
+        match last_modified {
+            None => Comparison::NoLastModified,
+            Some(last_modified) if last_modified > self.modified => Comparison::RemoteNewer,
+            Some(_) if length.is_some_and(|length| length != self.len) => {
+                Comparison::SizesDiffer { local: self.len }
+            }
+            Some(_) => Comparison::UpToDate,
+        }
+    }
+}
+
+/// Whether a local file needs downloading again, and why
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    NoLastModified,
+    RemoteNewer,
+    SizesDiffer { local: u64 },
+    UpToDate,
+}
+
+impl Comparison {
+    pub fn is_up_to_date(&self) -> bool {
+        *self == Comparison::UpToDate
+    }
+}
+
+/// The same messages wget gives
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Comparison::NoLastModified => {
+                write!(f, "Last-modified header missing -- time-stamps turned off.")
+            }
+            Comparison::RemoteNewer => write!(f, "Remote file is newer, retrieving."),
+            Comparison::SizesDiffer { local } => {
+                write!(f, "The sizes do not match (local {local}) -- retrieving.")
+            }
+            Comparison::UpToDate => write!(f, "Local file is up to date -- not retrieving."),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        process,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    };
+
+    use super::*;
+
+    fn date(seconds: u64) -> HttpDate {
+        HttpDate::from(UNIX_EPOCH + Duration::from_secs(seconds))
+    }
+
+    #[test]
+    fn compares_with_server_copy() {
+        let local = LocalFile {
+            modified: date(1000),
+            len: 100,
+        };
+        assert_eq!(
+            local.compare_with(None, Some(100)),
+            Comparison::NoLastModified
+        );
+        assert_eq!(
+            local.compare_with(Some(date(1001)), Some(100)),
+            Comparison::RemoteNewer
+        );
+        assert_eq!(
+            local.compare_with(Some(date(1000)), Some(99)),
+            Comparison::SizesDiffer { local: 100 }
+        );
+        assert_eq!(
+            local.compare_with(Some(date(1000)), Some(100)),
+            Comparison::UpToDate
+        );
+        assert_eq!(
+            local.compare_with(Some(date(999)), None),
+            Comparison::UpToDate
+        );
+    }
+
+    #[test]
+    fn finds_local_files() {
+        let path = std::env::temp_dir().join(format!("rust_wget_timestamping_{}", process::id()));
+        assert_eq!(LocalFile::find(&path).expect("Lookup failed"), None);
+
+        File::create(&path)
+            .and_then(|f| {
+                f.set_modified(SystemTime::try_from(date(1000)).expect("Date out of range"))
+            })
+            .expect("Could not create file");
+        let local = LocalFile::find(&path)
+            .expect("Lookup failed")
+            .expect("File not found");
+        assert_eq!(local.modified, date(1000));
+        assert_eq!(local.len, 0);
+        fs::remove_file(path).expect("Could not remove file");
+    }
+}
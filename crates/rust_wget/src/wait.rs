@@ -0,0 +1,126 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// When to pause between requests, from wget's `--wait`, `--random-wait` and
+/// `--waitretry` options
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WaitPolicy {
+    /// How long to wait between requests
+    pub wait: Duration,
+    /// Vary each wait between 0.5 and 1.5 times `wait`, so the requests look
+    /// less like a program made them
+    pub random: bool,
+    /// The longest to wait before retrying a failed download
+    pub wait_retry: Duration,
+}
+
+impl WaitPolicy {
+    /// How long to wait before the next request
+    pub fn between_requests(&self) -> Duration {
+        if self.random {
+            self.wait.mul_f64(0.5 + random_fraction())
+        } else {
+            self.wait
+        }
+    }
+
+    /// How long to wait before retrying after `failures` failed attempts
+    ///
+    /// Like wget this backs off linearly, waiting a second after the first
+    /// failure, two after the second, and so on up to `wait_retry`.
+    pub fn before_retry(&self, failures: u32) -> Duration {
+        Duration::from_secs(failures.into()).min(self.wait_retry)
+    }
+}
+
+/// Parses a number of seconds the way wget does, e.g. `2`, `0.5`, `3m`, `1h` or `1d`
+pub fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (number, multiplier) = match s.char_indices().last() {
+        Some((index, suffix)) if suffix.is_ascii_alphabetic() => {
+            let multiplier = match suffix.to_ascii_lowercase() {
+                's' => 1.0,
+                'm' => 60.0,
+                'h' => 60.0 * 60.0,
+                'd' => 24.0 * 60.0 * 60.0,
+                _ => return Err(format!("Unknown time suffix in {s}")),
+            };
+            (&s[..index], multiplier)
+        }
+        _ => (s, 1.0),
+    };
+    let number = number
+        .parse::<f64>()
+        .map_err(|_| format!("Invalid time {s}"))?;
+    Duration::try_from_secs_f64(number * multiplier).map_err(|_| format!("Invalid time {s}"))
+}
+
+/// A number in `[0, 1)` that is different every time. It only needs to keep
+/// waits from looking regular, so the standard library's randomly keyed hasher
+/// is plenty.
+fn random_fraction() -> f64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_seconds() {
+        assert_eq!(parse_seconds("2"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_seconds("0.5"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_seconds("10s"), Ok(Duration::from_secs(10)));
+        assert_eq!(parse_seconds("3m"), Ok(Duration::from_secs(180)));
+        assert_eq!(parse_seconds("1h"), Ok(Duration::from_secs(3600)));
+        assert_eq!(parse_seconds("1d"), Ok(Duration::from_secs(86400)));
+        assert!(parse_seconds("").is_err());
+        assert!(parse_seconds("-1").is_err());
+        assert!(parse_seconds("5y").is_err());
+    }
+
+    #[test]
+    fn waits_fixed_time() {
+        let policy = WaitPolicy {
+            wait: Duration::from_secs(2),
+            ..Default::default()
+        };
+        assert_eq!(policy.between_requests(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn random_waits_stay_in_range() {
+        let policy = WaitPolicy {
+            wait: Duration::from_secs(2),
+            random: true,
+            ..Default::default()
+        };
+        let waits: Vec<Duration> = (0..100).map(|_| policy.between_requests()).collect();
+        for wait in &waits {
+            assert!(
+                (Duration::from_secs(1)..=Duration::from_secs(3)).contains(wait),
+                "{wait:?}"
+            );
+        }
+        assert!(waits.iter().any(|wait| *wait != waits[0]));
+    }
+
+    #[test]
+    fn backs_off_linearly_before_retrying() {
+        let policy = WaitPolicy {
+            wait_retry: Duration::from_secs(3),
+            ..Default::default()
+        };
+        assert_eq!(policy.before_retry(1), Duration::from_secs(1));
+        assert_eq!(policy.before_retry(2), Duration::from_secs(2));
+        assert_eq!(policy.before_retry(3), Duration::from_secs(3));
+        assert_eq!(policy.before_retry(10), Duration::from_secs(3));
+    }
+}
@@ -0,0 +1,32 @@
+//! Adds and removes arbitrary headers, checking each lookup agrees with what
+//! was last done to that header
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use request_rs::http::{HttpMethod, HttpRequest, HttpVersion};
+
+#[derive(Arbitrary, Debug)]
+enum Operation<'a> {
+    Add(&'a str, &'a str),
+    Remove(&'a str),
+}
+
+fuzz_target!(|operations: Vec<Operation>| {
+    let mut request = HttpRequest::new(HttpMethod::Get, "/", HttpVersion::Version1_1);
+    for operation in operations {
+        match operation {
+            Operation::Add(key, value) => {
+                request.add_header(key, value);
+                assert_eq!(request.get_header(key), Some(value));
+            }
+            Operation::Remove(key) => {
+                request.delete_header(key);
+                assert_eq!(request.get_header(key), None);
+            }
+        }
+    }
+    assert!(request
+        .headers()
+        .all(|(key, value)| request.get_header(key) == Some(value)));
+});
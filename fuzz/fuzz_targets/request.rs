@@ -0,0 +1,9 @@
+//! Feeds arbitrary bytes to the request parser used by test servers
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use request_rs::{http::HttpRequest, Configuration};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = HttpRequest::receive_request(&mut &data[..], &Configuration::default());
+});
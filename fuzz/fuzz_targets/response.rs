@@ -0,0 +1,13 @@
+//! Feeds arbitrary bytes to the response parser, as a server could
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use request_rs::{http::HttpResponse, Configuration};
+
+fuzz_target!(|data: &[u8]| {
+    let config = Configuration::default();
+    let mut reader = data;
+    // Keep reading, like on a kept-alive connection, until the bytes run out
+    // or stop making sense
+    while !reader.is_empty() && HttpResponse::receive_response(&mut reader, &config).is_ok() {}
+});
@@ -0,0 +1,16 @@
+//! Parses arbitrary URLs, and resolves them against each other like links
+//! found while downloading recursively
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use request_rs::{
+    url::{resolve, ParsedUrl},
+    Configuration,
+};
+
+fuzz_target!(|urls: (&str, &str)| {
+    let (base, reference) = urls;
+    let config = Configuration::default();
+    let _ = ParsedUrl::parse(base, &config);
+    let _ = ParsedUrl::parse(&resolve(base, reference), &config);
+});